@@ -9,16 +9,21 @@ use core::fmt::{self, Debug};
 use fat32::vfat::{HandleReference, VFat, VFatHandle};
 use filesystem;
 use filesystem::devices::CharDeviceFileSystem;
-use filesystem::fs2::{Directory2, FileSystem2};
+use filesystem::fs2::{Directory2, FileSystem2, Readiness};
 use filesystem::path::Path;
 use filesystem::{CharDevice, VirtualFileSystem};
+use pi::interrupt::{Controller, Interrupt};
 use pi::uart::MiniUart;
-use shim::{io, newioerr};
+use shim::{io, ioerr, newioerr};
 use shim::io::{Read, Write};
 
 
-use crate::FILESYSTEM;
+use crate::{FILESYSTEM, GLOABAL_IRQ, SCHEDULER};
 use crate::multiprocessing::mutex::Mutex;
+use crate::multiprocessing::per_core::UtilisationFile;
+use crate::process::{channel_of, Channel};
+use crate::tracepoint::TraceFile;
+use crate::traps::irq::IrqHandlerRegistry;
 
 pub mod sd;
 
@@ -82,6 +87,10 @@ impl<'a> FileSystem2 for DiskFileSystem<'a> {
     fn copy_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
         HandleReference(self.0).copy_entry(source, destination)
     }
+
+    fn move_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        HandleReference(self.0).move_entry(source, destination)
+    }
 }
 
 pub struct FileSystem(Mutex<Option<VirtualFileSystem>>);
@@ -111,10 +120,22 @@ impl FileSystem {
 
         let console_path = Path::root();
 
+        let console = ConsoleFile::new();
+        console.listen();
         let console_filesystem = Box::new(CharDeviceFileSystem::new(
-            "console".to_string(), ConsoleFile::new())
+            "console".to_string(), console)
         );
         FILESYSTEM.0.lock().as_mut().unwrap().mount(console_path, console_filesystem);
+
+        let trace_filesystem = Box::new(CharDeviceFileSystem::new(
+            "trace".to_string(), TraceFile::new())
+        );
+        FILESYSTEM.0.lock().as_mut().unwrap().mount(Path::root(), trace_filesystem);
+
+        let cpus_filesystem = Box::new(CharDeviceFileSystem::new(
+            "cpus".to_string(), UtilisationFile::new())
+        );
+        FILESYSTEM.0.lock().as_mut().unwrap().mount(Path::root(), cpus_filesystem);
     }
 }
 
@@ -126,13 +147,31 @@ impl FileSystem2 for &FileSystem {
     fn copy_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
         self.0.lock().as_mut().ok_or(newioerr!(Unsupported))?.copy_entry(source, destination)
     }
+
+    fn move_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        self.0.lock().as_mut().ok_or(newioerr!(Unsupported))?.move_entry(source, destination)
+    }
 }
 
+/// The `/console` device. Reading it returns the input received so far, or
+/// fails with `WouldBlock` if there is none, so the reader blocks on the wait
+/// channel of the UART until the receive interrupt wakes it.
 struct ConsoleFile(Arc<Mutex<MiniUart>>);
 
 impl Read for ConsoleFile {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.lock().read(buf)
+        let mut uart = self.0.lock();
+        if !uart.has_byte() {
+            uart.set_receive_interrupt(true);
+            return ioerr!(WouldBlock);
+        }
+
+        let mut amount = 0;
+        while amount < buf.len() && uart.has_byte() {
+            buf[amount] = uart.read_byte();
+            amount += 1;
+        }
+        Ok(amount)
     }
 }
 
@@ -152,10 +191,40 @@ impl Clone for ConsoleFile {
     }
 }
 
-impl CharDevice for ConsoleFile {}
+impl CharDevice for ConsoleFile {
+    fn readiness(&mut self) -> Readiness {
+        let mut uart = self.0.lock();
+        let readable = uart.has_byte();
+        if !readable {
+            uart.set_receive_interrupt(true);
+        }
+        Readiness { readable, writable: uart.can_write(), hangup: false }
+    }
+
+    fn wait_channel(&self) -> Option<usize> {
+        Some(ConsoleFile::channel(&self.0))
+    }
+}
 
 impl ConsoleFile {
     fn new() -> Self {
         ConsoleFile(Arc::new(Mutex::new(MiniUart::new())))
     }
+
+    fn channel(uart: &Arc<Mutex<MiniUart>>) -> Channel {
+        channel_of(&**uart)
+    }
+
+    /// Registers the handler of the mini UART's interrupt, which wakes the
+    /// readers blocked on the console. The receive interrupt stays on while
+    /// input is waiting, so the handler switches it off, and a read or poll
+    /// that finds no input switches it back on.
+    fn listen(&self) {
+        let uart = self.0.clone();
+        GLOABAL_IRQ.register(Interrupt::Aux, Box::new(move |_| {
+            uart.lock().set_receive_interrupt(false);
+            SCHEDULER.wake(ConsoleFile::channel(&uart));
+        }));
+        Controller::new().enable(Interrupt::Aux);
+    }
 }
\ No newline at end of file
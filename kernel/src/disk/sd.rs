@@ -3,6 +3,8 @@ use core::time::Duration;
 use filesystem::BlockDevice;
 use shim::io;
 
+use crate::tracepoint::{self, Tracepoint};
+
 extern "C" {
     /// A global representing the last SD controller error that occured.
     static sd_err: i64;
@@ -78,7 +80,11 @@ impl BlockDevice for Sd {
                 return Err(io::Error::from(io::ErrorKind::InvalidInput));
             }
 
-            sd_readsector(n as i32, ptr) == 0
+            let start = pi::timer::current_time();
+            let did_err = sd_readsector(n as i32, ptr) == 0;
+            let micros = (pi::timer::current_time() - start).as_micros() as u64;
+            tracepoint::record(Tracepoint::BlockRead { sector: n, micros });
+            did_err
         };
 
         if did_err {
@@ -92,6 +92,11 @@ unsafe fn switch_to_el1() {
         // FIXME: load `vectors` addr into appropriate register (guide: 10.4)
         VBAR_EL1.set(&vectors as *const u64 as u64);
 
+        // unlock the debug registers and leave software step off until a
+        // traced process is stepped (ref: D2.12)
+        OSLAR_EL1.set(0);
+        MDSCR_EL1.set(0);
+
         // change execution level to EL1 (ref: C5.2.19)
         SPSR_EL2.set(
             (SPSR_EL2::M & 0b0101) // EL1h
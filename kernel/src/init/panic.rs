@@ -1,6 +1,7 @@
 use core::panic::PanicInfo;
 
 use crate::kprintln;
+use crate::tracepoint;
 
 #[panic_handler]
 fn panic(panic_info: &PanicInfo) -> ! {
@@ -36,6 +37,7 @@ fn panic(panic_info: &PanicInfo) -> ! {
         None => {}
     }
 
+    tracepoint::dump();
 
     loop {}
 }
@@ -49,6 +49,8 @@ context_save:
     PUSH32 q1,  q0
 
     mrs x0, TPIDR_EL0
+    PUSH16 xzr, x0
+    mrs x0, TPIDRRO_EL0
     mrs x1, SP_EL0
     PUSH16 x0, x1
 
@@ -88,7 +90,9 @@ context_restore:
     msr SPSR_EL1, x1
     POP16 x0, x1
     msr SP_EL0, x0
-    msr TPIDR_EL0, x1
+    msr TPIDRRO_EL0, x1
+    POP16 x0, x1
+    msr TPIDR_EL0, x0
 
     POP32 q0, q1
     POP32 q2, q3
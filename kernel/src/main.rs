@@ -43,6 +43,7 @@ pub mod process;
 pub mod traps;
 pub mod memory;
 pub mod multiprocessing;
+pub mod tracepoint;
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: KernelAllocator = KernelAllocator::uninitialized();
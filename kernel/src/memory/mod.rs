@@ -9,9 +9,11 @@ use crate::param::{KERNEL_MASK_BITS, USER_MASK_BITS};
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
 pub use self::pagetable::*;
+pub use self::user::{UserPtr, UserSlice};
 
 mod address;
 mod pagetable;
+mod user;
 
 pub struct VMManager {
     kern_pt: Mutex<Option<KernPageTable>>,
@@ -1,6 +1,7 @@
 use alloc::boxed::Box;
 use alloc::fmt;
 use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
 use core::fmt::Formatter;
 use core::iter::Chain;
 use core::ops::{Deref, DerefMut, Sub};
@@ -13,6 +14,7 @@ use shim::{const_assert_size, io, ioerr};
 use crate::ALLOCATOR;
 use crate::memory::{PhysicalAddr, VirtualAddr};
 use crate::param::*;
+use crate::tracepoint::{self, Tracepoint};
 
 #[repr(C)]
 pub struct Page([u8; PAGE_SIZE]);
@@ -65,6 +67,18 @@ impl L3Entry {
     pub fn address(&self) -> usize {
         (self.0.get_value(RawL3Entry::ADDR) as usize) << PAGE_ALIGN
     }
+
+    /// Returns the permissions user space has on the page of a user entry.
+    pub fn perm(&self) -> PagePerm {
+        let read_only = self.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RO;
+        let executable = self.0.get_value(RawL3Entry::UXN) == 0;
+        match (read_only, executable) {
+            (true, true) => PagePerm::RX,
+            (true, false) => PagePerm::RO,
+            (false, true) => PagePerm::RWX,
+            (false, false) => PagePerm::RW,
+        }
+    }
 }
 
 impl From<u64> for L3Entry {
@@ -266,20 +280,48 @@ impl KernPageTable {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PagePerm {
     RW,
     RO,
     RWX,
+    RX,
 }
 
-pub struct UserPageTable(Box<PageTable>);
+impl PagePerm {
+    /// Returns whether user space may write to a page with this permission.
+    pub fn is_writable(&self) -> bool {
+        match self {
+            PagePerm::RW | PagePerm::RWX => true,
+            PagePerm::RO | PagePerm::RX => false,
+        }
+    }
+
+    /// Returns whether user space may execute a page with this permission.
+    pub fn is_executable(&self) -> bool {
+        match self {
+            PagePerm::RWX | PagePerm::RX => true,
+            PagePerm::RO | PagePerm::RW => false,
+        }
+    }
+}
+
+pub struct UserPageTable {
+    table: Box<PageTable>,
+    /// The number of pages mapped
+    pages: usize,
+    /// The most pages that were mapped at once
+    max_pages: usize,
+}
 
 impl UserPageTable {
     /// Returns a new `UserPageTable` containing a `PageTable` created with
     /// `USER_RW` permission.
     pub fn new() -> UserPageTable {
         UserPageTable {
-            0: PageTable::new(EntryPerm::USER_RW)
+            table: PageTable::new(EntryPerm::USER_RW),
+            pages: 0,
+            max_pages: 0,
         }
     }
 
@@ -287,41 +329,127 @@ impl UserPageTable {
     /// physical address of the allocated page. Returns the allocated page.
     ///
     /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
-    ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, mut va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
-        if (va.as_ptr() as usize) < USER_IMG_BASE {
-            panic!("invalid virtual address");
+    /// Panics if `map` fails. See `map` for the cases in which it does.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        match self.map(va, perm) {
+            Ok(page) => page,
+            Err(e) => panic!("unable to allocate user page: {:?}", e),
         }
+    }
 
-        va = va.sub(VirtualAddr::from(USER_IMG_BASE));
-
-        if self.0.is_valid(va) {
-            panic!("entry has already been allocated");
+    /// Allocates a zeroed page and maps it at the given page aligned virtual
+    /// address with the permissions in `perm`. Returns the allocated page.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AddrNotAvailable` if the address is outside of the user
+    /// address space, `AlreadyExists` if it is already mapped, and
+    /// `OutOfMemory` if a page could not be allocated.
+    pub fn map(&mut self, va: VirtualAddr, perm: PagePerm) -> io::Result<&mut [u8]> {
+        let va = UserPageTable::user_offset(va)?;
+        if self.table.is_valid(va) {
+            return ioerr!(AlreadyExists);
         }
 
         let page = unsafe { ALLOCATOR.alloc(Page::layout()) };
+        if page.is_null() {
+            return ioerr!(OutOfMemory);
+        }
+        unsafe { core::ptr::write_bytes(page, 0, PAGE_SIZE) };
         let address = page as u64;
+        tracepoint::record(Tracepoint::PageAlloc { address });
+
+        let access = if perm.is_writable() { EntryPerm::USER_RW } else { EntryPerm::USER_RO };
+        let never_execute = if perm.is_executable() { 0 } else { 1 };
 
         let mut entry = RawL3Entry::new(0);
         entry.set_value(address >> PAGE_ALIGN, RawL3Entry::ADDR);
         entry.set_value(EntrySh::ISh as u64, RawL3Entry::SH);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.set_value(access, RawL3Entry::AP);
+        entry.set_value(never_execute, RawL3Entry::UXN);
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
         entry.set_value(EntryType::Table, RawL3Entry::TYPE);
         entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
         entry.set_value(0b1_u64, RawL3Entry::AF);
-        self.0.set_entry(va, entry);
+        self.table.set_entry(va, entry);
+        self.pages += 1;
+        self.max_pages = max(self.max_pages, self.pages);
+
+        Ok(unsafe { core::slice::from_raw_parts_mut(page, PAGE_SIZE) })
+    }
+
+    /// Removes the mapping of the page at the given page aligned virtual
+    /// address and frees the page.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AddrNotAvailable` if the address is outside of the user
+    /// address space or if its page is not mapped.
+    pub fn unmap(&mut self, va: VirtualAddr) -> io::Result<()> {
+        let va = UserPageTable::user_offset(va)?;
+        if self.table.is_invalid(va) {
+            return ioerr!(AddrNotAvailable);
+        }
+
+        let (l2_index, l3_index) = PageTable::locate(va);
+        let address = self.table.l3[l2_index].entries[l3_index].address() as u64;
+        self.table.set_entry(va, RawL3Entry::new(0));
+        self.pages -= 1;
+
+        tracepoint::record(Tracepoint::PageFree { address });
+        unsafe { ALLOCATOR.dealloc(address as *mut u8, Page::layout()) };
+        Ok(())
+    }
 
-        return unsafe { core::slice::from_raw_parts_mut(page, PAGE_SIZE) };
+    /// Returns the number of pages mapped.
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Returns the most pages that were mapped at once.
+    pub fn max_pages(&self) -> usize {
+        self.max_pages
+    }
+
+    /// Returns the permissions of the page containing the given user virtual
+    /// address, or `None` if it is not mapped.
+    pub fn perm(&self, virtual_address: VirtualAddr) -> Option<PagePerm> {
+        let va = VirtualAddr::from(virtual_address.page_aligned());
+        let va = UserPageTable::user_offset(va).ok()?;
+        let (l2_index, l3_index) = PageTable::locate(va);
+        let l3_entry = &self.l3[l2_index].entries[l3_index];
+        if l3_entry.is_valid() {
+            Some(l3_entry.perm())
+        } else {
+            None
+        }
     }
 
+    /// Returns the offset of a page aligned user virtual address from
+    /// `USER_IMG_BASE`, as used to index the page table.
+    fn user_offset(va: VirtualAddr) -> io::Result<VirtualAddr> {
+        if va.as_usize() < USER_IMG_BASE {
+            return ioerr!(AddrNotAvailable);
+        }
+
+        if va.as_usize() % PAGE_SIZE != 0 {
+            return ioerr!(InvalidInput);
+        }
+
+        Ok(va.sub(VirtualAddr::from(USER_IMG_BASE)))
+    }
+
+    /// Returns the physical address the given user virtual address maps to.
+    ///
+    /// Returns an `AddrNotAvailable` error if the address is outside of the
+    /// user address space or if its page has not been allocated.
     pub fn translate(&self, virtual_address: VirtualAddr) -> io::Result<PhysicalAddr> {
-        let page_aligned = VirtualAddr::from(virtual_address.page_aligned());
+        if virtual_address.as_usize() < USER_IMG_BASE {
+            return ioerr!(AddrNotAvailable);
+        }
+
+        let page_aligned = VirtualAddr::from(virtual_address.page_aligned())
+            .sub(VirtualAddr::from(USER_IMG_BASE));
         let (l2_index, l3_index) = PageTable::locate(page_aligned);
         let l3_entry = &self.l3[l2_index].entries[l3_index];
         if l3_entry.is_valid() {
@@ -361,7 +489,7 @@ impl Deref for UserPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.table
     }
 }
 
@@ -373,7 +501,7 @@ impl DerefMut for KernPageTable {
 
 impl DerefMut for UserPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.table
     }
 }
 
@@ -384,6 +512,7 @@ impl Drop for UserPageTable {
             if entry.is_valid() {
                 let address = entry.0.get_value(RawL3Entry::ADDR) << PAGE_ALIGN;
 
+                tracepoint::record(Tracepoint::PageFree { address });
                 unsafe {
                     ALLOCATOR.dealloc(address as *mut u8, Page::layout())
                 }
@@ -0,0 +1,221 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+use kernel_api::{OsError, OsResult};
+
+use crate::memory::{PhysicalAddr, UserPageTable, VirtualAddr};
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+
+/// A range of bytes in the address space of a user process.
+///
+/// User memory is never dereferenced directly. Every access walks the
+/// process's `UserPageTable`, so a buffer may span any number of pages and a
+/// bad pointer results in a `BadAddress` error instead of a kernel fault.
+#[derive(Copy, Clone, Debug)]
+pub struct UserSlice {
+    address: u64,
+    len: usize,
+}
+
+impl UserSlice {
+    /// Returns a new `UserSlice` of `len` bytes starting at `address`.
+    pub fn new(address: u64, len: usize) -> UserSlice {
+        UserSlice { address, len }
+    }
+
+    /// Returns the length of the slice in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks that every byte of the slice lies in user space and is mapped in
+    /// `vmap`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadAddress` if the slice starts below `USER_IMG_BASE`, wraps
+    /// around the address space, or covers a page that is not mapped.
+    pub fn validate(&self, vmap: &UserPageTable) -> OsResult<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        if (self.address as usize) < USER_IMG_BASE ||
+            self.address.checked_add(self.len as u64 - 1).is_none() {
+            return Err(OsError::BadAddress);
+        }
+
+        for (va, _) in self.chunks() {
+            vmap.translate(va).map_err(|_| OsError::BadAddress)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the slice out of user space into `buf`. `buf` must be the same
+    /// length as the slice.
+    pub fn copy_into(&self, vmap: &UserPageTable, buf: &mut [u8]) -> OsResult<()> {
+        if buf.len() != self.len {
+            return Err(OsError::InvalidArgument);
+        }
+
+        self.validate(vmap)?;
+        for (va, range) in self.chunks() {
+            let page = user_bytes(vmap, va, range.len())?;
+            buf[range].copy_from_slice(page);
+        }
+
+        Ok(())
+    }
+
+    /// Copies the slice out of user space into a newly allocated vector.
+    pub fn to_vec(&self, vmap: &UserPageTable) -> OsResult<Vec<u8>> {
+        self.validate(vmap)?;
+        let mut buf = vec![0u8; self.len];
+        self.copy_into(vmap, buf.as_mut_slice())?;
+        Ok(buf)
+    }
+
+    /// Checks that the slice is valid as `validate` does, and that user space
+    /// may write to every page of it.
+    pub fn validate_writable(&self, vmap: &UserPageTable) -> OsResult<()> {
+        self.validate(vmap)?;
+        for (va, _) in self.chunks() {
+            match vmap.perm(va) {
+                Some(perm) if perm.is_writable() => {}
+                _ => return Err(OsError::BadAddress),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies `buf` into the start of the slice. `buf` must not be longer than
+    /// the slice.
+    pub fn copy_from(&self, vmap: &UserPageTable, buf: &[u8]) -> OsResult<()> {
+        if buf.len() > self.len {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let target = UserSlice::new(self.address, buf.len());
+        target.validate_writable(vmap)?;
+        for (va, range) in target.chunks() {
+            let page = user_bytes(vmap, va, range.len())?;
+            page.copy_from_slice(&buf[range]);
+        }
+
+        Ok(())
+    }
+
+    /// Copies `buf` into the start of the slice like `copy_from`, but also into
+    /// pages user space may only read or execute. The bytes are made visible to
+    /// instruction fetch, so code written this way is what gets executed.
+    pub fn overwrite(&self, vmap: &UserPageTable, buf: &[u8]) -> OsResult<()> {
+        if buf.len() > self.len {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let target = UserSlice::new(self.address, buf.len());
+        target.validate(vmap)?;
+        for (va, range) in target.chunks() {
+            let page = user_bytes(vmap, va, range.len())?;
+            page.copy_from_slice(&buf[range]);
+            unsafe {
+                aarch64::sync_instruction(page.as_ptr() as u64);
+                aarch64::sync_instruction(page.as_ptr() as u64 + page.len() as u64 - 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the page sized pieces of the slice, as the
+    /// virtual address each piece starts at and its range within the slice.
+    fn chunks(&self) -> impl Iterator<Item=(VirtualAddr, core::ops::Range<usize>)> {
+        let address = self.address;
+        let len = self.len;
+        let mut start = 0;
+
+        core::iter::from_fn(move || {
+            if start >= len {
+                return None;
+            }
+
+            let va = VirtualAddr::from(address.wrapping_add(start as u64));
+            let end = min(len, start + PAGE_SIZE - va.offset() as usize);
+            let range = start..end;
+            start = end;
+            Some((va, range))
+        })
+    }
+}
+
+/// A pointer to a `T` in the address space of a user process.
+///
+/// `T` must be plain old data: every bit pattern of the right size has to be a
+/// valid `T`.
+#[derive(Debug)]
+pub struct UserPtr<T> {
+    address: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        UserPtr { address: self.address, _marker: PhantomData }
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    /// Returns a new `UserPtr` pointing at `address`.
+    pub fn new(address: u64) -> UserPtr<T> {
+        UserPtr {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the bytes of user memory the pointer refers to.
+    pub fn as_slice(&self) -> UserSlice {
+        UserSlice::new(self.address, size_of::<T>())
+    }
+
+    /// Reads the value out of user space.
+    pub fn read(&self, vmap: &UserPageTable) -> OsResult<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        self.as_slice().copy_into(vmap, bytes)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Writes `value` into user space.
+    pub fn write(&self, vmap: &UserPageTable, value: &T) -> OsResult<()> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+        };
+        self.as_slice().copy_from(vmap, bytes)
+    }
+
+    /// Writes `value` into user space like `write`, even where user space may
+    /// only read or execute. See `UserSlice::overwrite`.
+    pub fn poke(&self, vmap: &UserPageTable, value: &T) -> OsResult<()> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+        };
+        self.as_slice().overwrite(vmap, bytes)
+    }
+}
+
+/// Returns the kernel view of `len` bytes of user memory starting at `va`. The
+/// bytes must not cross a page boundary.
+fn user_bytes(vmap: &UserPageTable, va: VirtualAddr, len: usize) -> OsResult<&mut [u8]> {
+    let physical: PhysicalAddr = vmap.translate(va).map_err(|_| OsError::BadAddress)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(physical.as_usize() as *mut u8, len) })
+}
@@ -1,8 +1,73 @@
-use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use alloc::format;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::cmp::min;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 
+use filesystem::CharDevice;
+use pi::timer;
+use shim::{io, ioerr};
+
+use crate::multiprocessing::mutex::Mutex;
 use crate::param::NCORES;
+use crate::process::{CoreQueue, Id, ProcessRef};
 use crate::traps::irq::LocalIrq;
 
+/// The processes waiting for a core, ordered by the scheduling policy, with
+/// the deadlines of the core. The scheduler sets up the queues when it is
+/// initialized.
+pub(crate) type RunQueue = Mutex<Option<CoreQueue>>;
+
+/// The process running on a core. Only the core itself reads or sets it, with
+/// interrupts masked, so it needs no lock.
+pub(crate) struct Current(UnsafeCell<Option<(Id, ProcessRef)>>);
+
+unsafe impl Sync for Current {}
+
+/// Counters of how a core spent its time.
+pub struct Utilisation {
+    /// Microseconds spent running processes
+    pub busy: AtomicU64,
+    /// Microseconds spent waiting for a process to run
+    pub idle: AtomicU64,
+    /// Number of times a process was switched to
+    pub switches: AtomicU64,
+}
+
+impl Utilisation {
+    const fn new() -> Utilisation {
+        Utilisation {
+            busy: AtomicU64::new(0),
+            idle: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+        }
+    }
+}
+
+/// The value of `UserClock::since` while the running process is in the kernel.
+const IN_KERNEL: u64 = u64::MAX;
+
+/// Splits the time the process running on a core spends between user space
+/// and the kernel. The scheduler takes the time spent in user space when it
+/// switches the process out and charges the rest to the kernel.
+pub struct UserClock {
+    /// When, in microseconds since boot, the process last returned to user
+    /// space, or `IN_KERNEL` while it is in the kernel
+    since: AtomicU64,
+    /// Microseconds the process spent in user space since they were taken
+    elapsed: AtomicU64,
+}
+
+impl UserClock {
+    const fn new() -> UserClock {
+        UserClock {
+            since: AtomicU64::new(IN_KERNEL),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+}
+
 /// A struct to track per-core data.
 #[repr(align(512))]
 pub struct PerCore {
@@ -12,6 +77,16 @@ pub struct PerCore {
     mmu_ready: AtomicBool,
     /// Local IRQ handler registry
     irq: LocalIrq,
+    /// The processes queued to run on this core and its deadlines
+    run_queue: RunQueue,
+    /// When, in microseconds since boot, this core next balances its load
+    next_balance: AtomicU64,
+    /// How this core spent its time
+    utilisation: Utilisation,
+    /// How the process running on this core spent its time
+    user_clock: UserClock,
+    /// The process running on this core
+    current: Current,
 }
 
 static PER_CORE_DATA: [PerCore; NCORES] = [
@@ -19,21 +94,41 @@ static PER_CORE_DATA: [PerCore; NCORES] = [
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        run_queue: Mutex::new(None),
+        next_balance: AtomicU64::new(0),
+        utilisation: Utilisation::new(),
+        user_clock: UserClock::new(),
+        current: Current(UnsafeCell::new(None)),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        run_queue: Mutex::new(None),
+        next_balance: AtomicU64::new(0),
+        utilisation: Utilisation::new(),
+        user_clock: UserClock::new(),
+        current: Current(UnsafeCell::new(None)),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        run_queue: Mutex::new(None),
+        next_balance: AtomicU64::new(0),
+        utilisation: Utilisation::new(),
+        user_clock: UserClock::new(),
+        current: Current(UnsafeCell::new(None)),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        run_queue: Mutex::new(None),
+        next_balance: AtomicU64::new(0),
+        utilisation: Utilisation::new(),
+        user_clock: UserClock::new(),
+        current: Current(UnsafeCell::new(None)),
     },
 ];
 
@@ -79,3 +174,110 @@ pub fn local_irq() -> &'static LocalIrq {
     let cpu = aarch64::affinity();
     &PER_CORE_DATA[cpu].irq
 }
+
+/// Returns the run queue of `core`.
+pub(crate) fn run_queue(core: usize) -> &'static RunQueue {
+    &PER_CORE_DATA[core].run_queue
+}
+
+/// Returns the process running on the current core and its ID.
+pub(crate) fn current() -> Option<(Id, ProcessRef)> {
+    let cpu = aarch64::affinity();
+    unsafe { (*PER_CORE_DATA[cpu].current.0.get()).clone() }
+}
+
+/// Sets the process running on the current core and returns the one before.
+pub(crate) fn set_current(current: Option<(Id, ProcessRef)>) -> Option<(Id, ProcessRef)> {
+    let cpu = aarch64::affinity();
+    unsafe { mem::replace(&mut *PER_CORE_DATA[cpu].current.0.get(), current) }
+}
+
+/// Returns when, in microseconds since boot, the current core next balances
+/// its load.
+pub fn next_balance() -> &'static AtomicU64 {
+    let cpu = aarch64::affinity();
+    &PER_CORE_DATA[cpu].next_balance
+}
+
+/// Returns the utilisation counters of `core`.
+pub fn utilisation(core: usize) -> &'static Utilisation {
+    &PER_CORE_DATA[core].utilisation
+}
+
+/// Notes that the process running on the current core entered the kernel from
+/// user space.
+pub fn enter_kernel() {
+    let clock = &PER_CORE_DATA[aarch64::affinity()].user_clock;
+    let since = clock.since.swap(IN_KERNEL, Ordering::Relaxed);
+    if since != IN_KERNEL {
+        let now = timer::current_time().as_micros() as u64;
+        clock.elapsed.fetch_add(now.saturating_sub(since), Ordering::Relaxed);
+    }
+}
+
+/// Notes that the process running on the current core returns to user space.
+pub fn enter_user() {
+    let now = timer::current_time().as_micros() as u64;
+    PER_CORE_DATA[aarch64::affinity()].user_clock.since.store(now, Ordering::Relaxed);
+}
+
+/// Takes the microseconds the process running on the current core spent in
+/// user space since they were last taken.
+pub fn take_user_time() -> u64 {
+    PER_CORE_DATA[aarch64::affinity()].user_clock.elapsed.swap(0, Ordering::Relaxed)
+}
+
+/// The `/cpus` pseudo-file.
+///
+/// Reading it returns a line for each core with the utilisation counters and
+/// the number of processes on its run queue when it is first read, and then
+/// the end of the file.
+#[derive(Clone, Default)]
+pub struct UtilisationFile {
+    text: Option<Vec<u8>>,
+    position: usize,
+}
+
+impl UtilisationFile {
+    pub fn new() -> UtilisationFile {
+        UtilisationFile::default()
+    }
+
+    fn snapshot() -> Vec<u8> {
+        let mut text = Vec::new();
+        for core in 0..NCORES {
+            let utilisation = utilisation(core);
+            let queued = run_queue(core).lock().as_ref().map_or(0, |queue| queue.len());
+            let line = format!("core {}: busy {}us idle {}us switches {} queued {}\n",
+                               core,
+                               utilisation.busy.load(Ordering::Relaxed),
+                               utilisation.idle.load(Ordering::Relaxed),
+                               utilisation.switches.load(Ordering::Relaxed),
+                               queued);
+            text.extend_from_slice(line.as_bytes());
+        }
+        text
+    }
+}
+
+impl io::Read for UtilisationFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let text = self.text.get_or_insert_with(UtilisationFile::snapshot);
+        let amount = min(buf.len(), text.len() - self.position);
+        buf[..amount].copy_from_slice(&text[self.position..self.position + amount]);
+        self.position += amount;
+        Ok(amount)
+    }
+}
+
+impl io::Write for UtilisationFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CharDevice for UtilisationFile {}
@@ -22,11 +22,33 @@ pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK;
 //0xffff_ffff_ffff_0000
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+/// Where the kernel starts looking for free space when a process maps memory
+/// without asking for an address.
+pub const USER_MMAP_BASE: usize = USER_IMG_BASE + USER_MAX_VM_SIZE / 4;
+/// The size of the user stack mapped for each thread a process spawns.
+pub const USER_THREAD_STACK_SIZE: usize = 4 * PAGE_SIZE;
 
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
 pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
 
-/// The `tick` time. TODO: relower
-pub const TICK: Duration = Duration::from_secs(1);
+/// The number of process IDs. At most this many processes and threads exist
+/// at once. Must be a multiple of 64.
+pub const PID_MAX: usize = 1024;
+
+/// The ID of init, the first process, which the children of a process that
+/// exits are reparented to.
+pub const INIT_ID: u64 = 0;
+
+/// How long a process runs before it is preempted for the next one.
+pub const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// How often a core checks whether to take a process from a busier core.
+pub const BALANCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An affinity mask with every core, which processes start with.
+pub const ALL_CORES: u64 = (1 << NCORES) - 1;
+
+/// The number of tracepoint records kept for each core.
+pub const TRACE_RING_SIZE: usize = 512;
 
@@ -0,0 +1,70 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+
+use filesystem::fs2::{DirEntry2, EntryKind, FileSystem2};
+use filesystem::path::Path;
+use kernel_api::{DirEntry, OsError, OsResult, StatKind};
+use shim::{io, newioerr};
+
+use crate::FILESYSTEM;
+
+/// An open directory. The listing is taken on the first read and handed out
+/// a few entries at a time.
+#[derive(Clone)]
+pub(crate) struct DirectoryResource {
+    path: Path,
+    entries: Option<Vec<DirEntry2>>,
+    position: usize,
+}
+
+impl DirectoryResource {
+    pub(crate) fn new(path: Path) -> Self {
+        DirectoryResource {
+            path,
+            entries: None,
+            position: 0,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Encodes as many of the remaining entries as fit into `buf` and returns
+    /// the number of bytes used. Returns `0` once every entry has been read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `buf` cannot hold even the next entry.
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> OsResult<usize> {
+        if self.entries.is_none() {
+            let entries = FILESYSTEM.borrow().open(&self.path)?
+                .into_directory().ok_or(newioerr!(NotADirectory))?
+                .list()?;
+            self.entries = Some(entries);
+        }
+
+        let entries = self.entries.as_ref().unwrap();
+        let mut written = 0;
+        while let Some(entry) = entries.get(self.position) {
+            let entry = DirEntry {
+                kind: match entry.kind {
+                    EntryKind::File => StatKind::File,
+                    EntryKind::Directory => StatKind::Directory,
+                    EntryKind::CharDevice => StatKind::CharDevice,
+                },
+                name: entry.name.as_str(),
+            };
+
+            match entry.encode(&mut buf[written..]) {
+                Some(amount) => written += amount,
+                None if written == 0 => return Err(OsError::InvalidArgument),
+                None => break,
+            }
+
+            self.position += 1;
+        }
+
+        Ok(written)
+    }
+}
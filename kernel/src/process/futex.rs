@@ -0,0 +1,46 @@
+use alloc::collections::{BTreeMap, VecDeque};
+
+use crate::process::Id;
+
+/// The processes blocked in `futex_wait`, queued in the order they started
+/// waiting. Queues are keyed on the physical address of the word waited on, so
+/// processes that share memory meet at the same futex wherever it is mapped.
+///
+/// A process that stops waiting without being woken, because it timed out, was
+/// interrupted or died, may linger in its queue. Wakers skip such entries by
+/// checking `Process::futex`.
+#[derive(Debug, Default)]
+pub(crate) struct FutexTable {
+    waiters: BTreeMap<u64, VecDeque<Id>>,
+}
+
+impl FutexTable {
+    pub(crate) fn new() -> Self {
+        FutexTable::default()
+    }
+
+    /// Queues `pid` on the futex at the physical address `key`. A process
+    /// waits on one futex at a time, so any older entry for it is dropped.
+    pub(crate) fn park(&mut self, key: u64, pid: Id) {
+        self.remove(pid);
+        self.waiters.entry(key).or_default().push_back(pid);
+    }
+
+    /// Removes the next process queued on the futex at `key` and returns it.
+    pub(crate) fn pop(&mut self, key: u64) -> Option<Id> {
+        let queue = self.waiters.get_mut(&key)?;
+        let pid = queue.pop_front();
+        if queue.is_empty() {
+            self.waiters.remove(&key);
+        }
+        pid
+    }
+
+    /// Removes `pid` from every queue.
+    pub(crate) fn remove(&mut self, pid: Id) {
+        for queue in self.waiters.values_mut() {
+            queue.retain(|id| *id != pid);
+        }
+        self.waiters.retain(|_, queue| !queue.is_empty());
+    }
+}
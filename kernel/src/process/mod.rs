@@ -1,10 +1,13 @@
-pub use crate::param::TICK;
+pub use crate::param::TIME_SLICE;
 
 pub use self::process::{Id, Process};
+pub(crate) use self::process::ProcessRef;
 pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
 pub use self::state::State;
 pub use self::resource::ResourceId;
+pub use self::wait::{channel_of, Channel};
+pub(crate) use self::run_queue::CoreQueue;
 
 mod process;
 mod scheduler;
@@ -12,4 +15,14 @@ mod stack;
 mod state;
 mod resource;
 mod pipe;
+mod directory;
+mod signal;
+mod futex;
+mod pid;
+mod policy;
+mod run_queue;
+mod thread;
+mod timer;
+mod trace;
+mod wait;
 
@@ -0,0 +1,65 @@
+use crate::param::PID_MAX;
+use crate::process::Id;
+
+const WORDS: usize = PID_MAX / 64;
+
+/// Allocates process IDs from a bitmap with a bit per ID, set while the ID is
+/// in use.
+///
+/// IDs are handed out in increasing order from the last one allocated and wrap
+/// around at `PID_MAX`, so the ID of a process that was reaped is only reused
+/// once every ID after it was tried. This keeps a process that still holds
+/// the ID, such as a tracer, from seeing it on another process soon after.
+#[derive(Debug)]
+pub(crate) struct PidAllocator {
+    used: [u64; WORDS],
+    /// The ID to try first
+    next: usize,
+}
+
+impl PidAllocator {
+    pub(crate) fn new() -> Self {
+        PidAllocator {
+            used: [0; WORDS],
+            next: 0,
+        }
+    }
+
+    /// Returns a free ID and marks it used, or `None` if all `PID_MAX` IDs are
+    /// in use.
+    pub(crate) fn allocate(&mut self) -> Option<Id> {
+        let start = self.next / 64;
+        let offset = self.next % 64;
+
+        // The first word is visited twice: for the IDs from `next` on, and
+        // again after wrapping around for the IDs before it.
+        for i in 0..=WORDS {
+            let word = (start + i) % WORDS;
+            let mut free = !self.used[word];
+            if i == 0 {
+                free &= !0 << offset;
+            } else if i == WORDS {
+                free &= !(!0 << offset);
+            }
+
+            if free != 0 {
+                let bit = free.trailing_zeros() as usize;
+                self.used[word] |= 1 << bit;
+
+                let pid = word * 64 + bit;
+                self.next = (pid + 1) % PID_MAX;
+                return Some(pid as Id);
+            }
+        }
+
+        None
+    }
+
+    /// Marks `id` free for reuse.
+    pub(crate) fn free(&mut self, id: Id) {
+        let id = id as usize;
+        if id < PID_MAX {
+            self.used[id / 64] &= !(1 << (id % 64));
+        }
+    }
+}
@@ -5,15 +5,26 @@ use alloc::vec::Vec;
 use core::cmp::min;
 
 
-use filesystem::fs2::File2;
+use filesystem::fs2::{File2, Readiness};
 
 use shim::{io, ioerr};
 use shim::io::{Seek, SeekFrom};
 
 use crate::multiprocessing::mutex::Mutex;
+use crate::process::{channel_of, Channel};
+use crate::SCHEDULER;
 
-pub(crate) struct Pipe(Vec<u8>);
+pub(crate) struct Pipe {
+    buffer: Vec<u8>,
+    /// The number of open reading ends
+    readers: usize,
+    /// The number of open writing ends
+    writers: usize,
+}
 
+/// An end of a pipe. Reading an empty pipe fails with `WouldBlock` while it
+/// has writers, so the reader blocks on the pipe's wait channel, which is
+/// woken by every write and when an end closes.
 pub(crate) enum PipeResource {
     Writer(Arc<Mutex<Pipe>>),
     Reader(Arc<Mutex<Pipe>>),
@@ -21,26 +32,68 @@ pub(crate) enum PipeResource {
 
 impl PipeResource {
     pub(crate) fn new_pair() -> (Self, Self) {
-        let pipe = Arc::new(Mutex::new(Pipe(Vec::new())));
+        let pipe = Arc::new(Mutex::new(Pipe { buffer: Vec::new(), readers: 1, writers: 1 }));
         let writer = PipeResource::Writer(pipe.clone());
         let reader = PipeResource::Reader(pipe);
         (writer, reader)
     }
+
+    fn pipe(&self) -> &Arc<Mutex<Pipe>> {
+        match self {
+            PipeResource::Writer(pipe) | PipeResource::Reader(pipe) => pipe,
+        }
+    }
+
+    fn channel(&self) -> Channel {
+        channel_of(&**self.pipe())
+    }
 }
 
 impl Drop for PipeResource {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        match self {
+            PipeResource::Writer(pipe) => pipe.lock().writers -= 1,
+            PipeResource::Reader(pipe) => pipe.lock().readers -= 1,
+        }
+        SCHEDULER.wake(self.channel());
+    }
 }
 
 impl File2 for PipeResource {
     fn duplicate(&mut self) -> io::Result<Box<dyn File2>> {
         Ok(Box::new(match self {
-            PipeResource::Writer(writer) =>
-                PipeResource::Writer(writer.clone()),
-            PipeResource::Reader(reader) =>
-                PipeResource::Reader(reader.clone()),
+            PipeResource::Writer(writer) => {
+                writer.lock().writers += 1;
+                PipeResource::Writer(writer.clone())
+            }
+            PipeResource::Reader(reader) => {
+                reader.lock().readers += 1;
+                PipeResource::Reader(reader.clone())
+            }
         }))
     }
+
+    /// The reading end is readable once data is buffered or every writer has
+    /// closed, at which point it also reports a hangup. The buffer is
+    /// unbounded, so the writing end is always writable, and hangs up once
+    /// every reader has closed.
+    fn readiness(&mut self) -> io::Result<Readiness> {
+        Ok(match self {
+            PipeResource::Writer(pipe) => {
+                let pipe = pipe.lock();
+                Readiness { readable: false, writable: true, hangup: pipe.readers == 0 }
+            }
+            PipeResource::Reader(pipe) => {
+                let pipe = pipe.lock();
+                let hangup = pipe.writers == 0;
+                Readiness { readable: !pipe.buffer.is_empty() || hangup, writable: false, hangup }
+            }
+        })
+    }
+
+    fn wait_channel(&self) -> Option<usize> {
+        Some(self.channel())
+    }
 }
 
 impl io::Read for PipeResource {
@@ -51,9 +104,12 @@ impl io::Read for PipeResource {
             }
             PipeResource::Reader(pipe_arc) => {
                 let mut pipe = pipe_arc.lock();
-                let amount = min(pipe.0.len(), buf.len());
-                buf[..amount].copy_from_slice(&pipe.0.as_slice()[..amount]);
-                pipe.0.drain(0..amount);
+                if pipe.buffer.is_empty() && pipe.writers > 0 {
+                    return ioerr!(WouldBlock);
+                }
+                let amount = min(pipe.buffer.len(), buf.len());
+                buf[..amount].copy_from_slice(&pipe.buffer.as_slice()[..amount]);
+                pipe.buffer.drain(0..amount);
                 Ok(amount)
             }
         }
@@ -64,8 +120,8 @@ impl io::Write for PipeResource {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
             PipeResource::Writer(pipe_arc) => {
-                let mut pipe = pipe_arc.lock();
-                pipe.0.extend_from_slice(buf);
+                pipe_arc.lock().buffer.extend_from_slice(buf);
+                SCHEDULER.wake(self.channel());
                 Ok(buf.len())
             }
             PipeResource::Reader(_pipe_arc) => {
@@ -81,6 +137,6 @@ impl io::Write for PipeResource {
 
 impl Seek for PipeResource {
     fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
-        ioerr!(Unsupported)
+        ioerr!(NotSeekable)
     }
 }
\ No newline at end of file
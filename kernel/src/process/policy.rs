@@ -0,0 +1,151 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use core::cmp::max;
+use core::fmt;
+use core::time::Duration;
+
+use kernel_api::{NICE_MAX, NICE_MIN};
+
+use crate::process::Id;
+
+/// The weight of a process with a nice value of 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// The weights of the nice values from `NICE_MIN` to `NICE_MAX`. Each step
+/// changes the share of the CPU a process gets by about 10%.
+const WEIGHTS: [u64; (NICE_MAX - NICE_MIN + 1) as usize] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+/// What a scheduling policy keeps in each process.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Entity {
+    /// From `NICE_MIN` to `NICE_MAX`; lower values get more of the CPU
+    pub(crate) nice: i64,
+    /// The time the process ran in nanoseconds, scaled by its weight
+    pub(crate) vruntime: u64,
+    /// When the process was last switched to
+    pub(crate) started: Duration,
+}
+
+impl Entity {
+    fn weight(&self) -> u64 {
+        WEIGHTS[(self.nice - NICE_MIN) as usize]
+    }
+
+    /// Accounts for the process having run for `ran`, scaled by its weight.
+    /// The virtual runtime is kept whatever the policy, but only `FairShare`
+    /// orders by it.
+    pub(crate) fn charge(&mut self, ran: Duration) {
+        let ran = ran.as_nanos() as u64;
+        self.vruntime += ran * NICE_0_WEIGHT / self.weight();
+    }
+}
+
+/// Decides the order in which the processes that can run get the CPU.
+///
+/// The scheduler queues a process with `enqueue` when it becomes ready or is
+/// scheduled out, and takes the next one to run with `dequeue`. A process is
+/// queued at most once at a time.
+pub(crate) trait Policy: fmt::Debug + Send {
+    /// Queues the process `id`.
+    fn enqueue(&mut self, id: Id, entity: &mut Entity);
+
+    /// Takes the process to run next off the queue.
+    fn dequeue(&mut self) -> Option<Id>;
+
+    /// Returns the number of processes queued.
+    fn len(&self) -> usize;
+
+    /// Called when the process gives up the CPU before being queued again, so
+    /// it runs after the processes already queued.
+    fn yielded(&mut self, _entity: &mut Entity) {}
+}
+
+/// Runs processes in the order they were queued, ignoring nice values.
+#[derive(Debug, Default)]
+pub(crate) struct RoundRobin {
+    queue: VecDeque<Id>,
+}
+
+impl RoundRobin {
+    pub(crate) fn new() -> Self {
+        RoundRobin::default()
+    }
+}
+
+impl Policy for RoundRobin {
+    fn enqueue(&mut self, id: Id, _entity: &mut Entity) {
+        self.queue.push_back(id);
+    }
+
+    fn dequeue(&mut self) -> Option<Id> {
+        self.queue.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// Shares the CPU between processes in proportion to the weights of their nice
+/// values.
+///
+/// Each process accumulates a virtual runtime, the time it ran divided by its
+/// weight, and the process with the least virtual runtime runs next. A process
+/// that waited is queued with no less than the least virtual runtime, less
+/// half a time slice, so it runs soon after it wakes up but cannot claim the
+/// time it spent waiting.
+#[derive(Debug, Default)]
+pub(crate) struct FairShare {
+    /// Queued processes keyed by virtual runtime and the order they were
+    /// queued in
+    queue: BTreeMap<(u64, u64), Id>,
+    /// Counts the processes queued, to break ties in the order they came
+    sequence: u64,
+    /// The virtual runtime of the last process taken off the queue
+    min_vruntime: u64,
+    /// How far behind `min_vruntime` a waking process is queued
+    wakeup_credit: u64,
+}
+
+impl FairShare {
+    pub(crate) fn new(time_slice: Duration) -> Self {
+        FairShare {
+            wakeup_credit: time_slice.as_nanos() as u64 / 2,
+            ..FairShare::default()
+        }
+    }
+}
+
+impl Policy for FairShare {
+    fn enqueue(&mut self, id: Id, entity: &mut Entity) {
+        let floor = self.min_vruntime.saturating_sub(self.wakeup_credit);
+        entity.vruntime = max(entity.vruntime, floor);
+
+        self.queue.insert((entity.vruntime, self.sequence), id);
+        self.sequence += 1;
+    }
+
+    fn dequeue(&mut self) -> Option<Id> {
+        let (&key, _) = self.queue.iter().next()?;
+        self.min_vruntime = max(self.min_vruntime, key.0);
+        self.queue.remove(&key)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn yielded(&mut self, entity: &mut Entity) {
+        if let Some((&(last, _), _)) = self.queue.iter().next_back() {
+            entity.vruntime = max(entity.vruntime, last);
+        }
+    }
+}
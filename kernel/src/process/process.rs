@@ -1,5 +1,6 @@
 use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::borrow::{Borrow};
 use core::mem;
@@ -10,23 +11,35 @@ use core::slice::from_raw_parts;
 
 use aarch64;
 use aarch64::SPSR_EL1;
-use filesystem::fs2::FileSystem2;
-use filesystem::path::Path;
-use kernel_api::{OsError, OsResult};
+use filesystem::fs2::{Directory2, Entry2, EntryKind, File2, FileSystem2, Metadata2, OpenOptions2, Readiness, Timestamp2};
+use filesystem::path::{Component, Path};
+use kernel_api::{DateTime, OsError, OsResult, PollDescriptor, Signal, Stat, StatKind, TraceEvent, Usage};
+use kernel_api::{POLL_HANGUP, POLL_INVALID, POLL_READ, POLL_WRITE};
+use kernel_api::{OPEN_APPEND, OPEN_CLOSE_ON_EXEC, OPEN_CREATE, OPEN_EXCLUSIVE, OPEN_READ, OPEN_TRUNCATE, OPEN_WRITE};
 use shim::{io, newioerr};
-use shim::io::{Write};
+use shim::io::{SeekFrom, Write};
 
 use crate::{FILESYSTEM, VMM};
 use crate::memory::*;
+use crate::multiprocessing::mutex::Mutex;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{Channel, Stack, State};
+use crate::process::directory::DirectoryResource;
 use crate::process::pipe::PipeResource;
+use crate::process::policy::Entity;
+use crate::process::signal::{Delivery, Signals};
+use crate::process::thread::ThreadGroup;
+use crate::process::trace::{Resume, Tracee, Tracer};
 use crate::process::resource::{Resource, ResourceId, ResourceList};
 use crate::traps::TrapFrame;
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
+/// A process in the process table. Each is locked on its own, so a core can
+/// switch to a process or run its system calls without the scheduler lock.
+pub(crate) type ProcessRef = Arc<Mutex<Process>>;
+
 /// A structure that represents the complete state of a process.
 #[derive(Debug)]
 pub struct Process {
@@ -35,18 +48,40 @@ pub struct Process {
     /// The memory allocation used for the process's stack.
     /// TODO: remove this its not useful
     pub stack: Stack,
-    /// The page table describing the Virtual Memory of the process
-    pub vmap: Box<UserPageTable>,
+    /// The page table describing the Virtual Memory of the process, shared by
+    /// its threads
+    pub vmap: Arc<Mutex<UserPageTable>>,
     /// The scheduling state of the process.
     pub state: State,
-    /// The resources (files) open by a process
-    pub(crate) resources: ResourceList,
-    /// Parent process
-    pub(crate) parent: Option<Id>,
-    /// Last dead process
-    pub(crate) dead_children: Vec<Id>,
+    /// The resources (files) open by a process, shared by its threads
+    pub(crate) resources: Arc<Mutex<ResourceList>>,
     /// Current Working Directory
     current_directory: Path,
+    /// Pending and blocked signals, and how to handle them, shared by its
+    /// threads
+    pub(crate) signals: Signals,
+    /// The physical address of the futex the process is blocked on
+    pub(crate) futex: Option<u64>,
+    /// The child `wait` is blocked on, or `WAIT_ANY`, and the user address to
+    /// store what it used at, or 0
+    pub(crate) reaping: Option<(u64, u64)>,
+    /// The threads of the process
+    pub(crate) threads: Arc<Mutex<ThreadGroup>>,
+    /// The user stack the kernel mapped for a thread made by `spawn_thread`
+    thread_stack: Option<VirtualAddr>,
+    /// The tracing state if the process is traced
+    pub(crate) traced: Option<Tracee>,
+    /// The processes this process traces
+    pub(crate) tracing: Tracer,
+    /// The nice value and what the scheduling policy keeps
+    pub(crate) scheduling: Entity,
+    /// The cores the process may run on, a bit per core
+    pub(crate) affinity: u64,
+    /// The core the process last ran or was queued on
+    pub(crate) core: Option<usize>,
+    /// The time the process ran and the times it was switched to and made a
+    /// system call; its pages are counted by `vmap`
+    pub(crate) usage: Usage,
 }
 
 impl Process {
@@ -60,12 +95,21 @@ impl Process {
         Ok(Process {
             context: Box::new(Default::default()),
             stack,
-            vmap: Box::new(UserPageTable::new()),
+            vmap: Arc::new(Mutex::new(UserPageTable::new())),
             state: State::Ready,
-            resources: ResourceList::new(),
-            parent: None,
-            dead_children: Vec::new(),
+            resources: Arc::new(Mutex::new(ResourceList::new())),
             current_directory: Path::root(),
+            signals: Signals::new(),
+            futex: None,
+            reaping: None,
+            threads: Arc::new(Mutex::new(ThreadGroup::new(0))),
+            thread_stack: None,
+            traced: None,
+            tracing: Tracer::new(),
+            scheduling: Entity::default(),
+            affinity: ALL_CORES,
+            core: None,
+            usage: Usage::default(),
         })
     }
 
@@ -86,7 +130,7 @@ impl Process {
         p.context.sp = Process::get_stack_top().as_u64();
         p.context.elr = Process::get_image_base().as_u64();
         p.context.ttbr0 = VMM.get_baddr().as_u64();
-        p.context.ttbr1 = p.vmap.get_baddr().as_u64();
+        p.context.ttbr1 = p.vmap.lock().get_baddr().as_u64();
         p.context.spsr = SPSR_EL1::F | SPSR_EL1::A | SPSR_EL1::D;
 
         Ok(p)
@@ -96,15 +140,18 @@ impl Process {
     /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
     /// permission to load file's contents.
     fn do_load(pn: &Path) -> OsResult<Process> {
-        let mut process = Process::new()?;
-        process.vmap.alloc(Process::get_stack_base(), PagePerm::RW);
-        let user_image = process.vmap.alloc(Process::get_image_base(), PagePerm::RWX);
+        let process = Process::new()?;
+        {
+            let mut vmap = process.vmap.lock();
+            vmap.alloc(Process::get_stack_base(), PagePerm::RW);
+            let user_image = vmap.alloc(Process::get_image_base(), PagePerm::RWX);
 
-        let mut file = FILESYSTEM.borrow().open(pn)
-            .map_err(|_| OsError::IoError)?
-            .into_file().ok_or(OsError::NoEntry)?;
+            let mut file = FILESYSTEM.borrow().open(pn)
+                .map_err(|_| OsError::IoError)?
+                .into_file().ok_or(OsError::NoEntry)?;
 
-        file.read(user_image).map_err(|_e| OsError::IoError)?;
+            file.read(user_image).map_err(|_e| OsError::IoError)?;
+        }
         Ok(process)
     }
 
@@ -165,57 +212,314 @@ impl Process {
         }
     }
 
+    /// Delivers pending signals on the way back to user space. See
+    /// `Signals::deliver`.
+    pub(crate) fn deliver_signals(&mut self) -> Option<Delivery> {
+        self.signals.deliver(&mut self.context)
+    }
+
+    /// Handles a `brk` instruction. A traced process stops for its tracer with
+    /// the program counter still on the instruction, any other process is
+    /// sent `Signal::Trap`.
+    pub(crate) fn breakpoint(&mut self) {
+        match self.traced.as_mut() {
+            Some(traced) => traced.stop_at(TraceEvent::Breakpoint),
+            None => self.signals.force(Signal::Trap),
+        }
+    }
+
+    /// Handles the software step exception taken once a traced process has
+    /// executed the instruction it was resumed for.
+    pub(crate) fn stepped(&mut self) {
+        if let Some(traced) = self.traced.as_mut() {
+            traced.stop_at(TraceEvent::Step);
+        }
+    }
+
+    /// Called on entry to the system call `number`. Returns `true` if the
+    /// process is traced and has to report the call before it is made. See
+    /// `Tracee::enter_syscall`. Otherwise the call is counted.
+    pub(crate) fn enter_syscall(&mut self, number: u16) -> bool {
+        let report = match self.traced.as_mut() {
+            Some(traced) => traced.enter_syscall(number, &mut self.context),
+            None => false,
+        };
+        if !report {
+            self.usage.syscalls += 1;
+        }
+        report
+    }
+
+    /// Whether the process is traced and was resumed for a single instruction.
+    pub(crate) fn stepping(&self) -> bool {
+        self.traced.as_ref().map_or(false, |traced| traced.stepping())
+    }
+
+    /// Resumes a process stopped for its tracer as `resume` asks.
+    pub(crate) fn resume_traced(&mut self, resume: Resume) {
+        if let Some(traced) = self.traced.as_mut() {
+            traced.resume(resume, &mut self.context);
+            self.state = State::Ready;
+        }
+    }
+
+    /// Stops the process being traced, resuming it if it is stopped for its
+    /// tracer. Returns whether it was resumed.
+    pub(crate) fn untrace(&mut self) -> bool {
+        if let Some(mut traced) = self.traced.take() {
+            traced.resume(Resume::Continue, &mut self.context);
+            if let State::Traced = self.state {
+                self.state = State::Ready;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Opens `path_name` with `flags`, a combination of the `OPEN_*`
+    /// constants. Directories can only be opened for reading.
     //TODO: limit number of open files
-    pub fn open(&mut self, path_name: String) -> OsResult<ResourceId> {
+    pub fn open(&mut self, path_name: String, flags: u64) -> OsResult<ResourceId> {
+        let path = self.resolve(path_name)?;
+        let options = OpenOptions2 {
+            read: flags & OPEN_READ != 0,
+            write: flags & OPEN_WRITE != 0,
+            append: flags & OPEN_APPEND != 0,
+            truncate: flags & OPEN_TRUNCATE != 0,
+            create: flags & OPEN_CREATE != 0,
+            create_new: flags & OPEN_CREATE != 0 && flags & OPEN_EXCLUSIVE != 0,
+        };
+
+        let resource = match FILESYSTEM.borrow().open_file(&path, &options) {
+            Ok(file) => Resource::File(file),
+            Err(err) if err.kind() == io::ErrorKind::IsADirectory && !options.writes() => {
+                Resource::Directory(DirectoryResource::new(path))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut resources = self.resources.lock();
+        let id = resources.insert(resource);
+        if flags & OPEN_CLOSE_ON_EXEC != 0 {
+            resources.set_close_on_exec(id, true)?;
+        }
+        Ok(id)
+    }
+
+    pub fn stat(&mut self, path_name: String) -> OsResult<Stat> {
+        let path = self.resolve(path_name)?;
+        let metadata = FILESYSTEM.borrow().metadata(&path)?;
+        Ok(to_stat(metadata.as_ref()))
+    }
+
+    pub fn fstat(&mut self, id: ResourceId) -> OsResult<Stat> {
+        match self.resources.lock().get(id)? {
+            Resource::File(ref mut file) => {
+                Ok(to_stat(file.metadata()?.as_ref()))
+            }
+            Resource::Directory(directory) => {
+                Ok(to_stat(FILESYSTEM.borrow().metadata(directory.path())?.as_ref()))
+            }
+        }
+    }
+
+    /// Fills in `ready` for each of `descriptors` and returns how many are
+    /// ready for something. Unknown descriptors are reported as
+    /// `POLL_INVALID` rather than failing the whole call.
+    pub fn poll(&mut self, descriptors: &mut [PollDescriptor]) -> OsResult<usize> {
+        let mut resources = self.resources.lock();
+        for descriptor in descriptors.iter_mut() {
+            let readiness = match resources.get(ResourceId::from(descriptor.descriptor)) {
+                Ok(Resource::File(file)) => file.readiness()?,
+                Ok(Resource::Directory(_)) => Readiness { readable: true, writable: false, hangup: false },
+                Err(_) => {
+                    descriptor.ready = POLL_INVALID;
+                    continue;
+                }
+            };
+
+            let mut ready = 0;
+            if readiness.readable {
+                ready |= POLL_READ;
+            }
+            if readiness.writable {
+                ready |= POLL_WRITE;
+            }
+            if readiness.hangup {
+                ready |= POLL_HANGUP;
+            }
+            descriptor.ready = ready & (descriptor.events | POLL_HANGUP);
+        }
+
+        Ok(descriptors.iter().filter(|descriptor| descriptor.ready != 0).count())
+    }
+
+    /// Returns the wait channel that is woken when the readiness of the
+    /// resource `id` changes, or `None` if it never blocks or does not exist.
+    pub fn wait_channel(&mut self, id: ResourceId) -> Option<Channel> {
+        match self.resources.lock().get(id) {
+            Ok(Resource::File(file)) => file.wait_channel(),
+            _ => None,
+        }
+    }
+
+    pub fn create_file(&mut self, path_name: String) -> OsResult<()> {
+        let (mut parent, name) = open_parent(&self.resolve(path_name)?)?;
+        Ok(parent.create_file(name.as_str())?)
+    }
+
+    pub fn create_directory(&mut self, path_name: String) -> OsResult<()> {
+        let (mut parent, name) = open_parent(&self.resolve(path_name)?)?;
+        Ok(parent.create_directory(name.as_str())?)
+    }
+
+    pub fn remove_file(&mut self, path_name: String) -> OsResult<()> {
+        let (mut parent, name) = open_parent(&self.resolve(path_name)?)?;
+        if parent.metadata(name.as_str())?.kind() == EntryKind::Directory {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(parent.remove(name.as_str())?)
+    }
+
+    pub fn remove_directory(&mut self, path_name: String) -> OsResult<()> {
+        let (mut parent, name) = open_parent(&self.resolve(path_name)?)?;
+        if parent.metadata(name.as_str())?.kind() != EntryKind::Directory {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(parent.remove(name.as_str())?)
+    }
+
+    pub fn rename(&mut self, from: String, to: String) -> OsResult<()> {
+        let from = self.resolve(from)?;
+        let to = self.resolve(to)?;
+        Ok(FILESYSTEM.borrow().move_entry(&from, &to)?)
+    }
+
+    /// Makes the directory at `path_name` the current directory.
+    pub fn change_directory(&mut self, path_name: String) -> OsResult<()> {
+        let path = self.resolve(path_name)?;
+        FILESYSTEM.borrow().open(&path)?
+            .into_directory().ok_or(OsError::InvalidArgument)?;
+        self.current_directory = path;
+        Ok(())
+    }
+
+    pub fn current_directory(&self) -> &Path {
+        &self.current_directory
+    }
+
+    /// Opens the program `name` for `execute`. Names containing a `/` are
+    /// resolved like any other path. Bare names are looked up in each
+    /// directory of the `PATH` variable in `environment` in turn, or in the
+    /// root directory if there is no `PATH`.
+    fn open_program(&self, name: String, environment: &[String]) -> OsResult<Box<dyn File2>> {
+        if name.contains('/') {
+            return FILESYSTEM.borrow().open(&self.resolve(name)?)?
+                .into_file().ok_or(OsError::InvalidArgument);
+        }
+
+        let search_path = environment.iter()
+            .find_map(|variable| variable.strip_prefix("PATH="))
+            .unwrap_or("/");
+        let name = Path::try_from(name)?;
+        for directory in search_path.split(':').filter(|directory| !directory.is_empty()) {
+            let mut path = match self.resolve(directory.to_string()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            path.append(&name);
+
+            if let Ok(Entry2::File(file)) = FILESYSTEM.borrow().open(&path) {
+                return Ok(file);
+            }
+        }
+
+        Err(OsError::NoEntry)
+    }
+
+    /// Turns `path_name` into an absolute path. Relative paths are taken from
+    /// the current directory, and `.` and `..` components are removed.
+    fn resolve(&self, path_name: String) -> OsResult<Path> {
         let path = Path::try_from(path_name)?;
-        let file = FILESYSTEM.borrow()
-            .open(&path)?
-            .into_file().ok_or(newioerr!(NotFound))?;
-        Ok(self.resources.insert(Resource::File(file)))
+        let path = match path.at(0) {
+            Some(Component::Root) => path,
+            _ => {
+                let mut absolute = self.current_directory.clone();
+                absolute.append(&path);
+                absolute
+            }
+        };
+        Ok(path.simplify()?)
     }
 
     pub fn close(&mut self, id: ResourceId) -> OsResult<()> {
-        self.resources.remove(id)
+        self.resources.lock().remove(id)
     }
 
     //TODO: fix seek/clean and make write have the same semantics
     pub fn read(&mut self, id: ResourceId, buffer: &mut [u8]) -> OsResult<usize> {
-        match self.resources.get(id)? {
+        match self.resources.lock().get(id)? {
             Resource::File(ref mut file) => {
                 match file.read(buffer) {
                     Ok(value) => Ok(value),
                     Err(err) => Err(err.into())
                 }
             }
+            Resource::Directory(_) => Err(OsError::InvalidArgument),
         }
     }
 
     pub fn write(&mut self, id: ResourceId, buffer: &[u8]) -> OsResult<usize> {
-        match self.resources.get(id)? {
+        match self.resources.lock().get(id)? {
             Resource::File(ref mut file) => {
                 match file.write(buffer) {
                     Ok(value) => Ok(value),
                     Err(err) => Err(err.into())
                 }
             }
+            Resource::Directory(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    pub fn seek(&mut self, id: ResourceId, position: SeekFrom) -> OsResult<u64> {
+        match self.resources.lock().get(id)? {
+            Resource::File(ref mut file) => {
+                match file.seek(position) {
+                    Ok(value) => Ok(value),
+                    Err(err) => Err(err.into())
+                }
+            }
+            Resource::Directory(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    pub fn read_directory(&mut self, id: ResourceId, buffer: &mut [u8]) -> OsResult<usize> {
+        match self.resources.lock().get(id)? {
+            Resource::File(_) => Err(OsError::InvalidArgument),
+            Resource::Directory(directory) => directory.read(buffer),
         }
     }
 
     pub fn pipe(&mut self) -> OsResult<(ResourceId, ResourceId)> {
         let (writer, reader) = PipeResource::new_pair();
-        let writer_id = self.resources.insert(Resource::File(Box::new(writer)));
-        let reader_id = self.resources.insert(Resource::File(Box::new(reader)));
+        let mut resources = self.resources.lock();
+        let writer_id = resources.insert(Resource::File(Box::new(writer)));
+        let reader_id = resources.insert(Resource::File(Box::new(reader)));
         Ok((writer_id, reader_id))
     }
 
     pub fn duplicate(&mut self, id: ResourceId, new_id: ResourceId) -> OsResult<()> {
-        let resource = self.resources.get(id)?;
+        let mut resources = self.resources.lock();
+        let resource = resources.get(id)?;
         let duplicate = match resource {
             Resource::File(file) => {
                 Resource::File(file.duplicate()?)
             }
+            Resource::Directory(directory) => {
+                Resource::Directory(directory.clone())
+            }
         };
-        self.resources.insert_with_id(new_id, duplicate)
+        resources.insert_with_id(new_id, duplicate)
     }
 
     pub fn fork(&mut self, id: Id) -> OsResult<Process> {
@@ -223,50 +527,112 @@ impl Process {
         let mut new_process = Process {
             context: Box::new(*self.context),
             stack,
-            vmap: Box::new(UserPageTable::new()),
+            vmap: Arc::new(Mutex::new(UserPageTable::new())),
             state: State::Ready,
-            resources: self.resources.duplicate()?,
-            parent: Some(self.context.tpidr),
-            dead_children: Vec::new(),
+            resources: Arc::new(Mutex::new(self.resources.lock().duplicate()?)),
             current_directory: self.current_directory.clone(),
+            signals: self.signals.for_child(),
+            futex: None,
+            reaping: None,
+            threads: Arc::new(Mutex::new(ThreadGroup::new(id))),
+            thread_stack: None,
+            traced: None,
+            tracing: Tracer::new(),
+            scheduling: self.scheduling,
+            affinity: self.affinity,
+            core: None,
+            usage: Usage::default(),
         };
 
         new_process.context.xs[0] = 0;
         new_process.context.xs[1] = 1;
         new_process.context.xs[7] = OsError::Ok as u64;
         new_process.context.ttbr0 = VMM.get_baddr().as_u64();
-        new_process.context.ttbr1 = new_process.vmap.get_baddr().as_u64();
+        new_process.context.ttbr1 = new_process.vmap.lock().get_baddr().as_u64();
         new_process.context.tpidr = id;
-        for (va, entry) in self.vmap.allocated_iter() {
-            let page = new_process.vmap.alloc(va, PagePerm::RWX);
+
+        let mut new_vmap = new_process.vmap.lock();
+        for (va, entry) in self.vmap.lock().allocated_iter() {
+            let page = new_vmap.alloc(va, entry.perm());
             let source = unsafe { from_raw_parts(entry.address() as *const u8, PAGE_SIZE) };
             page.copy_from_slice(source);
         }
+        drop(new_vmap);
 
         Ok(new_process)
     }
 
-    pub fn execute(&mut self, arguments: &[u8], environment: &[u8]) -> OsResult<()> {
-        let argument_vec = parse_execute(arguments);
-        let _environment_vec = parse_execute(environment);
+    /// Creates a thread `id` of this process. It shares the address space,
+    /// resources, working directory and signal actions of the process, and
+    /// starts at `entry` with `argument` in `x0`, `tls` in `TPIDR_EL0` and a
+    /// newly mapped stack of `USER_THREAD_STACK_SIZE` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoMemory` or `NoVmSpace` if there is no room for the stack.
+    pub fn spawn_thread(&mut self, id: Id, entry: u64, argument: u64, tls: u64)
+                        -> OsResult<Process> {
+        let stack = Stack::new().ok_or(OsError::NoMemory)?;
+        let user_stack = self.map_memory(None, USER_THREAD_STACK_SIZE, PagePerm::RW)?;
 
-        let path = Path::try_from(argument_vec.first()
-            .ok_or(newioerr!(InvalidFilename))?
-            .clone())?;
-        let mut absolute_path = Path::root();
-        absolute_path.append(&path);
+        let mut context = Box::new(TrapFrame::default());
+        context.ttbr0 = self.context.ttbr0;
+        context.ttbr1 = self.context.ttbr1;
+        context.spsr = self.context.spsr;
+        context.elr = entry;
+        context.sp = user_stack.as_u64() + USER_THREAD_STACK_SIZE as u64;
+        context.tpidr = id;
+        context.tls = tls;
+        context.xs[0] = argument;
 
-        let mut program_file = FILESYSTEM.borrow().open(&absolute_path)?
-            .into_file().ok_or(newioerr!(InvalidFilename))?;
+        self.threads.lock().add(id);
 
-        self.vmap = Box::new(UserPageTable::new());
+        Ok(Process {
+            context,
+            stack,
+            vmap: self.vmap.clone(),
+            state: State::Ready,
+            resources: self.resources.clone(),
+            current_directory: self.current_directory.clone(),
+            signals: self.signals.for_thread(),
+            futex: None,
+            reaping: None,
+            threads: self.threads.clone(),
+            thread_stack: Some(user_stack),
+            traced: None,
+            tracing: Tracer::new(),
+            scheduling: self.scheduling,
+            affinity: self.affinity,
+            core: None,
+            usage: Usage::default(),
+        })
+    }
 
-        let stack = self.vmap.alloc(Process::get_stack_base(), PagePerm::RW);
-        let mut stack_data = Vec::new();
-        info!("args bytes {:?}", &(arguments.len() as u64).to_be_bytes());
-        info!("args len {:x}", arguments.len());
+    /// Ends this thread of a multithreaded process, leaving `value` for
+    /// `thread_join`. The user stack `spawn_thread` mapped is unmapped.
+    pub(crate) fn exit_thread(&mut self, value: u64) {
+        if let Some(user_stack) = self.thread_stack.take() {
+            let _ = self.unmap_memory(user_stack, USER_THREAD_STACK_SIZE);
+        }
+        self.threads.lock().exit(self.context.tpidr, value);
+    }
+
+    pub fn execute(&mut self, arguments: &[u8], environment: &[u8]) -> OsResult<()> {
+        let argument_vec = parse_execute(arguments);
+        let environment_vec = parse_execute(environment);
+
+        if self.threads.lock().live().len() > 1 {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let program = argument_vec.first().ok_or(newioerr!(InvalidFilename))?.clone();
+        let mut program_file = self.open_program(program, environment_vec.as_slice())?;
 
+        // The arguments and then the environment are copied to the top of the
+        // stack, each as NUL separated strings. The top 16 bytes hold their
+        // lengths, and `kernel_api::env` reads them back from there.
         //TODO: clean this
+        let mut stack_data = Vec::new();
         stack_data.extend_from_slice(&(arguments.len() as u64).to_be_bytes());
         stack_data.extend_from_slice(&(environment.len() as u64).to_be_bytes());
         arguments.split(|x| *x == 0).for_each(|arg|
@@ -274,21 +640,187 @@ impl Process {
         environment.split(|x| *x == 0).for_each(|arg|
             stack_data.extend(arg.iter().rev().chain(&[0])));
 
+        if stack_data.len() > PAGE_SIZE {
+            return Err(OsError::InvalidArgument);
+        }
+
+        // The new image is loaded into its own page table, so the process is
+        // left as it was if loading fails.
+        let mut vmap = UserPageTable::new();
+        let stack = vmap.alloc(Process::get_stack_base(), PagePerm::RW);
         let stack_size = stack.len();
         stack_data.reverse();
         stack[stack_size - stack_data.len()..].copy_from_slice(stack_data.as_slice());
-
-        let user_image = self.vmap.alloc(Process::get_image_base(), PagePerm::RWX);
+        let user_image = vmap.alloc(Process::get_image_base(), PagePerm::RWX);
         program_file.read(user_image)?;
 
+        self.vmap = Arc::new(Mutex::new(vmap));
+        self.resources.lock().close_on_exec();
+        self.signals.reset_handlers();
+        self.thread_stack = None;
+
         self.context.sp = Process::get_stack_top().as_u64() - (stack_data.len() as u64);
         self.context.elr = Process::get_image_base().as_u64();
         self.context.ttbr0 = VMM.get_baddr().as_u64();
-        self.context.ttbr1 = self.vmap.get_baddr().as_u64();
+        self.context.ttbr1 = self.vmap.lock().get_baddr().as_u64();
+        self.context.tls = 0;
         self.context.spsr = SPSR_EL1::F | SPSR_EL1::A | SPSR_EL1::D;
 
+        if let Some(traced) = self.traced.as_mut() {
+            traced.stop_at(TraceEvent::Exec);
+        }
+
+        Ok(())
+    }
+
+    /// Maps `length` bytes of zeroed memory with the permissions in `perm`,
+    /// rounded up to whole pages. If `address` is `Some`, the memory is mapped
+    /// there, otherwise the kernel picks the lowest free range at or above
+    /// `USER_MMAP_BASE`. Returns the address of the mapping.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `length` is zero or overflows when rounded
+    /// up or `address` is not page aligned, `NoVmSpace` if the range is not
+    /// free or no free range is large enough, and `NoMemory` if the pages
+    /// could not be allocated.
+    pub fn map_memory(&mut self, address: Option<VirtualAddr>, length: usize, perm: PagePerm)
+                      -> OsResult<VirtualAddr> {
+        if length == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let pages = length.checked_add(PAGE_SIZE - 1).ok_or(OsError::InvalidArgument)? / PAGE_SIZE;
+
+        let start = match address {
+            Some(address) => {
+                if address.as_usize() % PAGE_SIZE != 0 {
+                    return Err(OsError::InvalidArgument);
+                }
+                if !self.is_range_free(address.as_usize(), pages) {
+                    return Err(OsError::NoVmSpace);
+                }
+                address.as_usize()
+            }
+            None => self.find_free_range(pages).ok_or(OsError::NoVmSpace)?,
+        };
+
+        for page in 0..pages {
+            let va = VirtualAddr::from(start + page * PAGE_SIZE);
+            let mut vmap = self.vmap.lock();
+            if vmap.map(va, perm).is_err() {
+                for mapped in 0..page {
+                    let _ = vmap.unmap(VirtualAddr::from(start + mapped * PAGE_SIZE));
+                }
+                return Err(OsError::NoMemory);
+            }
+        }
+
+        Ok(VirtualAddr::from(start))
+    }
+
+    /// Unmaps and frees every mapped page in the `length` bytes at `address`,
+    /// rounded up to whole pages. Pages in the range that are not mapped are
+    /// skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `length` is zero or overflows when rounded
+    /// up, or if `address` is not page aligned or the range is not inside the
+    /// user address space.
+    pub fn unmap_memory(&mut self, address: VirtualAddr, length: usize) -> OsResult<()> {
+        if length == 0 || address.as_usize() % PAGE_SIZE != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let pages = length.checked_add(PAGE_SIZE - 1).ok_or(OsError::InvalidArgument)? / PAGE_SIZE;
+        if !Process::is_user_range(address.as_usize(), pages) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        for page in 0..pages {
+            let va = VirtualAddr::from(address.as_usize() + page * PAGE_SIZE);
+            let mut vmap = self.vmap.lock();
+            if vmap.perm(va).is_some() {
+                vmap.unmap(va)?;
+            }
+        }
+
         Ok(())
     }
+
+    /// Returns whether the `pages` pages starting at `start` lie in user space
+    /// and none of them is mapped.
+    fn is_range_free(&self, start: usize, pages: usize) -> bool {
+        let vmap = self.vmap.lock();
+        Process::is_user_range(start, pages) && (0..pages)
+            .all(|page| vmap.perm(VirtualAddr::from(start + page * PAGE_SIZE)).is_none())
+    }
+
+    /// Returns the lowest page aligned address at or above `USER_MMAP_BASE`
+    /// followed by `pages` unmapped pages.
+    fn find_free_range(&self, pages: usize) -> Option<usize> {
+        let vmap = self.vmap.lock();
+        let mut start = USER_MMAP_BASE;
+        while Process::is_user_range(start, pages) {
+            let used = (0..pages)
+                .map(|page| start + page * PAGE_SIZE)
+                .find(|&va| vmap.perm(VirtualAddr::from(va)).is_some());
+            match used {
+                Some(va) => start = va + PAGE_SIZE,
+                None => return Some(start),
+            }
+        }
+
+        None
+    }
+
+    /// Returns whether the `pages` pages starting at `start` lie in user space.
+    fn is_user_range(start: usize, pages: usize) -> bool {
+        let length = match pages.checked_mul(PAGE_SIZE) {
+            Some(length) => length,
+            None => return false,
+        };
+        start >= USER_IMG_BASE && length <= USER_MAX_VM_SIZE - (start - USER_IMG_BASE)
+    }
+}
+
+/// Opens the directory that contains `path`, which must be absolute and
+/// simplified, and returns it along with the name of the final component.
+fn open_parent(path: &Path) -> OsResult<(Box<dyn Directory2>, String)> {
+    match path.components().last() {
+        Some(Component::Child(name)) => {
+            let parent = path.prefix(path.len() - 1).ok_or(OsError::InvalidArgument)?;
+            let directory = FILESYSTEM.borrow().open(&parent)?
+                .into_directory().ok_or(OsError::InvalidArgument)?;
+            Ok((directory, name.clone()))
+        }
+        _ => Err(OsError::InvalidArgument),
+    }
+}
+
+fn to_stat(metadata: &dyn Metadata2) -> Stat {
+    let date_time = |timestamp: Timestamp2| DateTime::new(timestamp.year as u16,
+        timestamp.month, timestamp.day, timestamp.hour, timestamp.minute, timestamp.second);
+
+    let mut flags = 0;
+    if metadata.read_only() {
+        flags |= Stat::READ_ONLY;
+    }
+    if metadata.hidden() {
+        flags |= Stat::HIDDEN;
+    }
+
+    Stat {
+        size: metadata.size(),
+        kind: match metadata.kind() {
+            EntryKind::File => StatKind::File,
+            EntryKind::Directory => StatKind::Directory,
+            EntryKind::CharDevice => StatKind::CharDevice,
+        },
+        flags,
+        created: date_time(metadata.created()),
+        accessed: date_time(metadata.accessed()),
+        modified: date_time(metadata.modified()),
+    }
 }
 
 fn parse_execute(data: &[u8]) -> Vec<String> {
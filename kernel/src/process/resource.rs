@@ -7,6 +7,8 @@ use core::fmt::Formatter;
 use filesystem::fs2::File2;
 use kernel_api::{OsError, OsResult};
 
+use crate::process::directory::DirectoryResource;
+
 #[derive(Clone, Copy, PartialOrd, PartialEq, Debug)]
 pub struct ResourceId(u64);
 
@@ -36,17 +38,21 @@ impl PartialOrd<u64> for ResourceId {
 
 pub enum Resource {
     File(Box<dyn File2>),
+    Directory(DirectoryResource),
 }
 
 pub struct ResourceEntry {
     pub(crate) id: ResourceId,
     pub(crate) resource: Resource,
+    /// Whether the entry is closed by `execute`
+    pub(crate) close_on_exec: bool,
 }
 
 impl fmt::Debug for ResourceEntry {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("ResourceEntry")
             .field("id", &self.id)
+            .field("close_on_exec", &self.close_on_exec)
             .finish()
     }
 }
@@ -71,16 +77,16 @@ impl ResourceList {
                 } else {
                     None
                 }
-            }).next().unwrap_or(0);
+            }).next().unwrap_or(self.list.len());
         let id = ResourceId::from(index as u64);
-        self.list.insert(index, ResourceEntry { id, resource });
+        self.list.insert(index, ResourceEntry { id, resource, close_on_exec: false });
         id
     }
 
     pub(crate) fn insert_with_id(&mut self, id: ResourceId, resource: Resource) -> OsResult<()> {
         let index = self.list.iter().enumerate()
             .filter_map(|(i, resource)| {
-                if resource.id <= i as u64 {
+                if id <= resource.id {
                     Some(i)
                 } else {
                     None
@@ -92,6 +98,7 @@ impl ResourceList {
                 self.list.push(ResourceEntry {
                     id,
                     resource,
+                    close_on_exec: false,
                 });
 
                 Ok(())
@@ -104,6 +111,7 @@ impl ResourceList {
                 self.list.insert(idx, ResourceEntry {
                     id,
                     resource,
+                    close_on_exec: false,
                 });
 
                 Ok(())
@@ -128,22 +136,36 @@ impl ResourceList {
     pub(crate) fn get(&mut self, id: ResourceId) -> OsResult<&mut Resource> {
         self.list.iter_mut().filter_map(|resource| {
             if resource.id == id {
-                None
-            } else {
                 Some(&mut resource.resource)
+            } else {
+                None
             }
         }).next().ok_or(OsError::UnknownResourceId)
     }
 
+    pub(crate) fn set_close_on_exec(&mut self, id: ResourceId, close_on_exec: bool) -> OsResult<()> {
+        let entry = self.list.iter_mut().find(|entry| entry.id == id)
+            .ok_or(OsError::UnknownResourceId)?;
+        entry.close_on_exec = close_on_exec;
+        Ok(())
+    }
+
+    /// Closes every entry opened with `OPEN_CLOSE_ON_EXEC`.
+    pub(crate) fn close_on_exec(&mut self) {
+        self.list.retain(|entry| !entry.close_on_exec);
+    }
+
     pub(crate) fn duplicate(&mut self) -> OsResult<ResourceList> {
         let list: Vec<ResourceEntry> = self.list.iter_mut().map_while(|entry| {
             let resource = match &mut entry.resource {
                 Resource::File(ref mut file) => Resource::File(file.duplicate().ok()?),
+                Resource::Directory(directory) => Resource::Directory(directory.clone()),
             };
 
             Some(ResourceEntry {
                 id: entry.id,
                 resource,
+                close_on_exec: entry.close_on_exec,
             })
         }).collect();
 
@@ -0,0 +1,91 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::process::{Id, Process, ProcessRef};
+use crate::process::policy::{Entity, Policy};
+use crate::process::timer::TimerQueue;
+
+/// What a process brings onto a run queue, so that a core can pick, steal or
+/// move it and switch to it without the scheduler lock.
+#[derive(Clone, Debug)]
+pub(crate) struct Queued {
+    /// A copy of what the policy keeps in the process. The queue's copy is the
+    /// one that counts until the process is switched to.
+    pub(crate) entity: Entity,
+    /// The cores the process could run on when it was queued
+    pub(crate) affinity: u64,
+    /// The process itself
+    pub(crate) process: ProcessRef,
+}
+
+impl Queued {
+    /// Returns what `process`, locked from `entry`, brings onto a run queue.
+    pub(crate) fn of(entry: &ProcessRef, process: &Process) -> Queued {
+        Queued {
+            entity: process.scheduling,
+            affinity: process.affinity,
+            process: entry.clone(),
+        }
+    }
+}
+
+/// The part of the scheduler that belongs to one core: the processes queued
+/// to run on it in the order its policy gives, the process running on it and
+/// the deadlines of the processes that blocked on it.
+#[derive(Debug)]
+pub(crate) struct CoreQueue {
+    policy: Box<dyn Policy>,
+    queued: BTreeMap<Id, Queued>,
+    /// The process last switched to on the core and when
+    pub(crate) running: Option<(Id, Duration)>,
+    /// The deadlines of the processes that blocked on the core
+    pub(crate) timers: TimerQueue,
+}
+
+impl CoreQueue {
+    pub(crate) fn new(policy: Box<dyn Policy>) -> CoreQueue {
+        CoreQueue {
+            policy,
+            queued: BTreeMap::new(),
+            running: None,
+            timers: TimerQueue::default(),
+        }
+    }
+
+    /// Returns the number of processes queued.
+    pub(crate) fn len(&self) -> usize {
+        self.policy.len()
+    }
+
+    /// Queues the process `id`.
+    pub(crate) fn enqueue(&mut self, id: Id, mut queued: Queued) {
+        self.policy.enqueue(id, &mut queued.entity);
+        self.queued.insert(id, queued);
+    }
+
+    /// Takes the first process, in the order the policy gives, that may run
+    /// on `core`. The processes before it that may not are taken off the
+    /// queue too and returned for the caller to put back or move elsewhere.
+    pub(crate) fn take(&mut self, core: usize) -> (Option<(Id, Queued)>, Vec<(Id, Queued)>) {
+        let mut skipped = Vec::new();
+        while let Some(id) = self.policy.dequeue() {
+            let queued = match self.queued.remove(&id) {
+                Some(queued) => queued,
+                None => continue,
+            };
+            if queued.affinity & (1 << core) != 0 {
+                return (Some((id, queued)), skipped);
+            }
+            skipped.push((id, queued));
+        }
+        (None, skipped)
+    }
+
+    /// Prepares `entity` of a process that gives up the core to be queued
+    /// after the processes already queued.
+    pub(crate) fn yielded(&mut self, entity: &mut Entity) {
+        self.policy.yielded(entity);
+    }
+}
@@ -1,23 +1,44 @@
 use alloc::boxed::Box;
-use alloc::collections::vec_deque::VecDeque;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 use core::arch::asm;
+use core::cmp::{min, Reverse};
 use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 
 
 use aarch64;
 use aarch64::{SP};
 
-use pi::local_interrupt::{local_tick_in, LocalController, LocalInterrupt};
+use kernel_api::{NICE_MAX, NICE_MIN, OsError, OsResult, Registers, Signal, TraceEvent, TraceRequest, Usage};
+use kernel_api::{USAGE_CHILDREN, USAGE_NEXT, USAGE_SELF, WAIT_ANY};
+use lineage::Lineage;
+use pi::local_interrupt::{local_tick_in, local_tick_stop, LocalController, LocalInterrupt};
+use pi::timer;
 use shim::{io, newioerr};
 
 use crate::{SCHEDULER, VMM};
-use crate::multiprocessing::mutex::Mutex;
-use crate::multiprocessing::per_core::local_irq;
+use crate::multiprocessing::mutex::{Mutex, MutexGuard};
+use crate::memory::{UserPtr, VirtualAddr};
+use crate::multiprocessing::per_core::{current, enter_user, local_irq, next_balance, run_queue, set_current, take_user_time, utilisation};
 use crate::param::*;
-use crate::process::{Id, Process, State};
+use crate::process::{Channel, CoreQueue, Id, Process, ProcessRef, State};
+use crate::process::futex::FutexTable;
+use crate::process::pid::PidAllocator;
+use crate::process::policy::{FairShare, Policy, RoundRobin};
+use crate::process::thread::ThreadGroup;
+use crate::process::signal::Delivery;
+use crate::process::run_queue::Queued;
+use crate::process::trace::{self, Resume, Tracee};
+use crate::process::state::EventPollFn;
+use crate::process::wait::WaitQueues;
+use crate::tracepoint::{self, Tracepoint};
 use crate::traps::irq::IrqHandlerRegistry;
-use crate::traps::TrapFrame;
+use crate::traps::{returns_to_user, TrapFrame};
 
 extern "C" {
     fn _start();
@@ -26,29 +47,86 @@ extern "C" {
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
-pub struct GlobalScheduler(Mutex<Option<Box<Scheduler>>>);
+pub struct GlobalScheduler {
+    scheduler: Mutex<Option<Box<Scheduler>>>,
+    /// Channels woken by a core holding the scheduler lock or the lock of the
+    /// process it runs a system call for, which wakes them once it lets go
+    woken: Mutex<Vec<Channel>>,
+    /// Whether each core holds one of those locks and leaves the channels it
+    /// wakes in `woken`
+    deferring: [AtomicBool; NCORES],
+}
+
+/// The `deferring` flag of a core that holds neither lock.
+const NOT_DEFERRING: AtomicBool = AtomicBool::new(false);
 
 impl GlobalScheduler {
     /// Returns an uninitialized wrapper around a local scheduler.
     pub const fn uninitialized() -> GlobalScheduler {
-        GlobalScheduler(Mutex::new(None))
+        GlobalScheduler {
+            scheduler: Mutex::new(None),
+            woken: Mutex::new(Vec::new()),
+            deferring: [NOT_DEFERRING; NCORES],
+        }
     }
 
     /// Enters a critical region and execute the provided closure with a mutable
-    /// reference to the inner scheduler.
+    /// reference to the inner scheduler. Channels woken in the region are
+    /// woken before it is left.
     pub fn critical<F, R>(&self, f: F) -> R
         where
             F: FnOnce(&mut Scheduler) -> R,
     {
-        let mut guard = self.0.lock();
-        f(guard.as_mut().expect("scheduler uninitialized"))
+        let core = aarch64::affinity();
+        let mut guard = self.scheduler.lock();
+        self.deferring[core].store(true, Ordering::SeqCst);
+        let scheduler = guard.as_mut().expect("scheduler uninitialized");
+        let result = f(scheduler);
+
+        loop {
+            let woken = mem::take(&mut *self.woken.lock());
+            if woken.is_empty() {
+                break;
+            }
+            for channel in woken {
+                scheduler.wake(channel);
+            }
+        }
+
+        self.deferring[core].store(false, Ordering::SeqCst);
+        result
+    }
+
+    /// Wakes the processes blocked on `channel`, queueing those whose event
+    /// has arrived to run.
+    ///
+    /// This may be called with the scheduler lock or the lock of the running
+    /// process held, as when a pipe is written to by a system call, in which
+    /// case the processes are woken when the lock is let go of, or from an
+    /// interrupt handler.
+    pub fn wake(&self, channel: Channel) {
+        self.woken.lock().push(channel);
+        if !self.deferring[aarch64::affinity()].load(Ordering::SeqCst) {
+            self.critical(|_| ());
+        }
+        aarch64::sev();
+    }
+
+    /// Blocks the process running with `tf` on `channels` until `poll` returns
+    /// `true`, and switches `tf` to the next process. For more details, see
+    /// the documentation on `Scheduler::block()`.
+    pub fn block(&self, channels: &[Channel], deadline: Option<Duration>, poll: EventPollFn,
+                 tf: &mut TrapFrame) -> Id {
+        self.critical(|scheduler| scheduler.block(channels, deadline, poll, tf));
+        aarch64::sev();
+        self.switch_to(tf)
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
     pub fn add(&self, mut process: Process) -> Option<Id> {
         process.context.ttbr0 = VMM.get_baddr().as_u64();
-        process.context.ttbr1 = process.vmap.get_baddr().as_u64();
+        process.context.ttbr1 = process.vmap.lock().get_baddr().as_u64();
         process.context.elr = USER_IMG_BASE as u64;
 
         let id = self.critical(move |scheduler| scheduler.add(process));
@@ -57,38 +135,97 @@ impl GlobalScheduler {
     }
 
     pub fn fork(&self, tf: &mut TrapFrame) -> Option<Id> {
-        let id = self.critical(|scheduler| {
-            *scheduler.find_process(tf.tpidr)?.context = *tf;
-            let id = scheduler.fork(tf.tpidr)?;
-            *tf = *scheduler.find_process(tf.tpidr)?.context;
-            Some(id)
-        })?;
+        let id = self.critical(|scheduler| scheduler.fork(tf))?;
 
         aarch64::sev();
         Some(id)
     }
 
+    /// Creates a thread of the process running with `tf` and returns its ID.
+    /// For more details, see the documentation on `Scheduler::spawn_thread()`.
+    pub fn spawn_thread(&self, tf: &mut TrapFrame, entry: u64, argument: u64, tls: u64)
+                        -> OsResult<Id> {
+        let id = self.critical(|scheduler| scheduler.spawn_thread(tf, entry, argument, tls))?;
+        aarch64::sev();
+        Ok(id)
+    }
+
+    /// Ends the thread running with `tf` with `value` and switches `tf` to the
+    /// next process. For more details, see the documentation on
+    /// `Scheduler::exit_thread()`.
+    pub fn exit_thread(&self, tf: &mut TrapFrame, value: u64) -> Option<Id> {
+        let id = self.critical(|scheduler| scheduler.exit_thread(tf, value));
+        aarch64::sev();
+        self.switch_to(tf);
+        id
+    }
+
     /// Performs a context switch using `tf` by setting the state of the current
     /// process to `new_state`, saving `tf` into the current process, and
-    /// restoring the next process's trap frame into `tf`. For more details, see
-    /// the documentation on `Scheduler::schedule_out()` and `Scheduler::switch_to()`.
+    /// restoring the next process's trap frame into `tf`. Only the process
+    /// and the run queues are locked, not the scheduler. For more details,
+    /// see the documentation on `schedule_out()` and `switch_to()`.
     pub fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Id {
-        self.critical(|scheduler| scheduler.schedule_out(new_state, tf));
+        if let Some(entry) = running(tf) {
+            schedule_out(&entry, &mut entry.lock(), new_state, tf);
+        }
         aarch64::sev();
         self.switch_to(tf)
     }
 
+    /// Gives up the CPU for the process running with `tf` and switches `tf` to
+    /// the next process, which may be the same one. The process is scheduled
+    /// out as `Ready` and queued after the processes already on the run queue
+    /// of the current core.
+    pub fn yield_now(&self, tf: &mut TrapFrame) -> Id {
+        if let Some(entry) = running(tf) {
+            let mut process = entry.lock();
+            with_queue(aarch64::affinity(), |queue| queue.yielded(&mut process.scheduling));
+            schedule_out(&entry, &mut process, State::Ready, tf);
+        }
+        aarch64::sev();
+        self.switch_to(tf)
+    }
+
+    /// Sets the nice value of the process `pid` to `nice`. For more details,
+    /// see the documentation on `Scheduler::set_priority()`.
+    pub fn set_priority(&self, pid: Id, nice: i64) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.set_priority(pid, nice))
+    }
+
+    /// Lets the process `pid` run only on the cores in `mask`. For more
+    /// details, see the documentation on `Scheduler::set_affinity()`.
+    pub fn set_affinity(&self, pid: Id, mask: u64) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.set_affinity(pid, mask))
+    }
+
+    /// Returns the cores the process `pid` may run on, a bit per core.
+    pub fn affinity(&self, pid: Id) -> OsResult<u64> {
+        self.critical(|scheduler| scheduler.affinity(pid))
+    }
+
     /// Loops until it finds the next process to schedule.
-    /// Call `wfi()` in the loop when no process is ready.
-    /// For more details, see the documentation on `Scheduler::switch_to()`.
+    /// Call `wfe()` in the loop when no process is ready, with interrupts
+    /// unmasked so that one can wake a process. Wakers signal an event once
+    /// they queued one. The process is picked with the run queues locked one
+    /// at a time, see `pick()`, and `tf` is switched to it with only the
+    /// process locked, see `run()`. The scheduler lock is not taken. The
+    /// local timer is set to preempt the process after `TIME_SLICE`, or
+    /// earlier for a deadline of the core. An idle core only sets it for a
+    /// deadline.
     ///
     /// Returns the process's ID when a ready process is found.
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
+        set_current(None);
         loop {
-            let rtn = self.critical(|scheduler| {
-                scheduler.switch_to(tf)
-            });
-            if let Some(id) = rtn {
+            let core = aarch64::affinity();
+            let (next, deadline) = pick(core);
+            if let Some((id, queued)) = next {
+                let started = queued.entity.started;
+                if !run(core, id, queued, tf) {
+                    continue;
+                }
+                set_timer(core, Some(started + TIME_SLICE), deadline);
                 trace!(
                     "[core-{}] switch_to {:?}, pc: {:x}, lr: {:x}, x29: {:x}, x28: {:x}, x27: {:x}",
                     aarch64::affinity(),
@@ -102,17 +239,109 @@ impl GlobalScheduler {
                 return id;
             }
 
+            set_timer(core, None, deadline);
+            let idle = timer::current_time();
+            aarch64::enable_irq_interrupt();
             aarch64::wfe();
+            aarch64::disable_irq_interrupt();
+            let idle = timer::current_time().saturating_sub(idle);
+            utilisation(aarch64::affinity()).idle.fetch_add(idle.as_micros() as u64, Ordering::Relaxed);
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentation on `Scheduler::kill()`.
+    /// Kills currently running process with exit status `status` and returns
+    /// that process's ID. For more details, see the documentation on
+    /// `Scheduler::kill()`.
     #[must_use]
-    pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| {
-            scheduler.kill(tf)
-        })
+    pub fn kill(&self, tf: &mut TrapFrame, status: u64) -> Option<Id> {
+        let id = self.critical(|scheduler| {
+            scheduler.kill(tf, status)
+        });
+        aarch64::sev();
+        id
+    }
+
+    /// Sends `signal` to the process `pid`. For more details, see the
+    /// documentation on `Scheduler::signal()`.
+    pub fn signal(&self, pid: Id, signal: Signal) -> OsResult<()> {
+        let result = self.critical(|scheduler| scheduler.signal(pid, signal));
+        aarch64::sev();
+        result
+    }
+
+    /// Blocks the process running with `tf` on the futex at `address` if it
+    /// holds `expected`, and switches `tf` to the next process. For more
+    /// details, see the documentation on `Scheduler::futex_wait()`.
+    pub fn futex_wait(&self, tf: &mut TrapFrame, address: u64, expected: u32,
+                      deadline: Option<Duration>) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.futex_wait(tf, address, expected, deadline))?;
+        aarch64::sev();
+        self.switch_to(tf);
+        Ok(())
+    }
+
+    /// Wakes up to `count` processes blocked on the futex at `address`. For
+    /// more details, see the documentation on `Scheduler::futex_wake()`.
+    pub fn futex_wake(&self, tf: &TrapFrame, address: u64, count: u64) -> OsResult<u64> {
+        let result = self.critical(|scheduler| scheduler.futex_wake(tf, address, count));
+        aarch64::sev();
+        result
+    }
+
+    /// Reaps an exited child of the process running with `tf`, blocking for
+    /// one unless `no_hang`. Returns whether `tf` was switched to the next
+    /// process. For more details, see the documentation on
+    /// `Scheduler::wait()`.
+    pub fn wait(&self, tf: &mut TrapFrame, pid: Id, no_hang: bool, usage: u64) -> OsResult<bool> {
+        let blocked = self.critical(|scheduler| scheduler.wait(tf, pid, no_hang, usage))?;
+        if blocked {
+            aarch64::sev();
+            self.switch_to(tf);
+        }
+        Ok(blocked)
+    }
+
+    /// Returns what a process used and its ID, as `who` selects. For more
+    /// details, see the documentation on `Scheduler::usage()`.
+    pub fn usage(&self, tf: &TrapFrame, who: u64, pid: Id) -> OsResult<(Id, Usage)> {
+        self.critical(|scheduler| scheduler.usage(tf, who, pid))
+    }
+
+    /// Carries out the trace `request` of the process running with `tf`. For
+    /// more details, see the documentation on `Scheduler::trace()`.
+    pub fn trace(&self, tf: &TrapFrame, request: TraceRequest, pid: Id, address: u64,
+                 data: u64) -> OsResult<u64> {
+        let result = self.critical(|scheduler| scheduler.trace(tf, request, pid, address, data));
+        aarch64::sev();
+        result
+    }
+
+    /// Prepares the process about to return to user space with `tf`. A traced
+    /// process with an event to report stops for its tracer, and otherwise the
+    /// pending signals are delivered. If the process stops or is terminated,
+    /// `tf` is switched to the next process, which is prepared in turn.
+    ///
+    /// Returns whether the process that returns is being single-stepped.
+    pub fn return_to_user(&self, tf: &mut TrapFrame) -> bool {
+        loop {
+            if self.critical(|scheduler| scheduler.trace_stop(tf)) {
+                aarch64::sev();
+                self.switch_to(tf);
+                continue;
+            }
+
+            match self.on_process(tf, |process| (process.deliver_signals(), process.stepping())) {
+                Ok((Some(Delivery::Terminate(status)), _)) => {
+                    let _ = self.kill(tf, status);
+                    self.switch_to(tf);
+                }
+                Ok((Some(Delivery::Stop), _)) => {
+                    self.switch(State::Stopped, tf);
+                }
+                Ok((None, stepping)) => return stepping,
+                Err(_) => return false,
+            }
+        }
     }
 
     /// Starts executing processes in user space using timer interrupt based
@@ -123,6 +352,7 @@ impl GlobalScheduler {
 
         let mut trap_frame: TrapFrame = Default::default();
         self.switch_to(&mut trap_frame);
+        enter_user();
 
         unsafe {
             SP.set((&mut trap_frame) as *const TrapFrame as u64);
@@ -141,54 +371,166 @@ impl GlobalScheduler {
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
-    /// The timer should be configured in a way that `CntpnsIrq` interrupt fires
-    /// every `TICK` duration, which is defined in `param.rs`.
+    /// The `CntpnsIrq` interrupt is raised when the running process used up
+    /// its `TIME_SLICE`, which is defined in `param.rs`, or a process blocked
+    /// on the core reaches its deadline. The timer is set every time a process
+    /// is switched to. See `timer_interrupt()`.
     pub fn initialize_local_timer_interrupt(&self) {
         let core = aarch64::affinity();
         let mut controller = LocalController::new(core);
         controller.enable_local_timer();
 
         local_irq().register(LocalInterrupt::CntPnsIrq, Box::new(|tf| {
-            let core = aarch64::affinity();
-            SCHEDULER.switch(State::Ready, tf);
-            local_tick_in(core, TICK);
+            SCHEDULER.timer_interrupt(tf);
         }));
-        local_tick_in(core, TICK);
+    }
+
+    /// Handles the local timer interrupt of the current core: wakes the
+    /// processes whose deadline passed, preempts the process running with
+    /// `tf` if its time slice is used up, and otherwise sets the timer for
+    /// whatever is due next.
+    ///
+    /// The tick only locks the run queue of the core, and takes the scheduler
+    /// lock only if a deadline passed.
+    fn timer_interrupt(&self, tf: &mut TrapFrame) {
+        let core = aarch64::affinity();
+        let now = timer::current_time();
+        let (expired, slice_end, deadline) = with_queue(core, |queue| {
+            let expired = queue.timers.expire(now);
+            let slice_end = match queue.running {
+                Some((id, started)) if id == tf.tpidr && returns_to_user(tf) => Some(started + TIME_SLICE),
+                _ => None,
+            };
+            (expired, slice_end, queue.timers.next())
+        });
+        if !expired.is_empty() {
+            self.critical(|scheduler| scheduler.wake_expired(now, expired));
+            aarch64::sev();
+        }
+
+        match slice_end {
+            Some(slice_end) if slice_end <= now => {
+                self.switch(State::Ready, tf);
+            }
+            slice_end => set_timer(core, slice_end, deadline),
+        }
     }
 
     /// Initializes the scheduler and add userspace processes to the Scheduler.
     pub unsafe fn initialize(&self) {
-        *self.0.lock() = Some(Scheduler::new());
+        *self.scheduler.lock() = Some(Scheduler::new());
     }
 
+    /// Runs `on` on the process running with `tf`, with `tf` saved into it
+    /// and restored from it afterwards. Only that process is locked, and the
+    /// channels `on` wakes are woken once it is let go of.
     pub fn on_process<T: FnOnce(&mut Process) -> R, R>(&self, tf: &mut TrapFrame, on: T) -> io::Result<R> {
-        self.critical(|scheduler| -> io::Result<R> {
-            let process = scheduler.find_process(tf.tpidr)
-                .ok_or(newioerr!(NotFound))?;
+        let entry = running(tf).ok_or(newioerr!(NotFound))?;
+        let core = aarch64::affinity();
+        self.deferring[core].store(true, Ordering::SeqCst);
+        let result = {
+            let mut process = entry.lock();
             *process.context = *tf;
-            let result = on(process);
+            let result = on(&mut process);
             *tf = *process.context;
+            result
+        };
+        self.deferring[core].store(false, Ordering::SeqCst);
 
-            Ok(result)
-        })
+        if !self.woken.lock().is_empty() {
+            self.critical(|_| ());
+        }
+        Ok(result)
     }
 }
 
 /// Internal scheduler struct which is not thread-safe.
+///
+/// Every process and thread is kept in `processes`, keyed by its ID. The
+/// `Ready` processes are queued on the run queue of a core in `PerCore`, whose
+/// policy decides the order they get that core in. A process is queued on the
+/// core it last ran on if its affinity allows, where its data may still be
+/// cached. A core with nothing to run steals from the others, and every
+/// `BALANCE_INTERVAL` it takes a process from the busiest core if that one has
+/// two more queued. Running, stopped and traced processes are off the queues
+/// and are put back when they are scheduled out or resumed.
+///
+/// A `Waiting` process is off the run queues too. It is kept on the wait
+/// queue of each channel it blocked on, and whatever it waits for wakes the
+/// channel or the process itself, which polls its event function and queues
+/// it to run once the event has arrived.
+///
+/// Each process is locked on its own, and the run queues hold it along with
+/// its ID. A core picks the next process and takes its timer tick with only
+/// its own run queue locked, steals with only the queue of the core it steals
+/// from locked, and switches between processes and runs their system calls
+/// with only the processes it switches from and to locked, one at a time. The
+/// scheduler lock guards the process table, the lineage, the wait queues and
+/// the futexes, and is taken for whatever involves another process.
+///
+/// Locks are taken in this order: the scheduler, then a process, then a run
+/// queue. No two run queues are locked at once, and a core only locks two
+/// processes while it holds the scheduler lock. The thread group, address
+/// space and resources of a process are locked after it, and no process is
+/// locked while one of them is held.
+///
+/// The kernel shares the CPU fairly by nice value with `FairShare`, or, built
+/// with the `round-robin` feature, takes turns with `RoundRobin`.
 pub struct Scheduler {
-    processes: VecDeque<Process>,
-    last_id: Option<Id>,
+    processes: BTreeMap<Id, ProcessRef>,
+    pids: PidAllocator,
+    futexes: FutexTable,
+    waits: WaitQueues,
+    /// The parent of each process, and the exit status of each zombie with
+    /// what it used
+    lineage: Lineage<(u64, Usage)>,
 }
 
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
+    /// Returns a new `Scheduler` with empty run queues.
     fn new() -> Box<Scheduler> {
+        for core in 0..NCORES {
+            let policy: Box<dyn Policy> = if cfg!(feature = "round-robin") {
+                Box::new(RoundRobin::new())
+            } else {
+                Box::new(FairShare::new(TIME_SLICE))
+            };
+            *run_queue(core).lock() = Some(CoreQueue::new(policy));
+        }
+
         Box::new(Scheduler {
-            processes: VecDeque::new(),
-            last_id: None,
+            processes: BTreeMap::new(),
+            pids: PidAllocator::new(),
+            futexes: FutexTable::new(),
+            waits: WaitQueues::new(),
+            lineage: Lineage::new(INIT_ID),
         })
     }
 
+    /// Adds `process` with the ID `id` to the table and queues it to run.
+    fn insert(&mut self, id: Id, process: Process) {
+        let entry = Arc::new(Mutex::new(process));
+        self.processes.insert(id, entry.clone());
+        enqueue(&entry, &mut entry.lock());
+    }
+
+    /// Removes the process running with `tf` from the table, saving `tf` into
+    /// it. Returns the process, now `Dead`, which is dropped once the current
+    /// core switches away from it. Its ID is left for the caller to free.
+    fn remove(&mut self, tf: &TrapFrame) -> Option<ProcessRef> {
+        let entry = self.processes.remove(&tf.tpidr)?;
+        {
+            let mut process = entry.lock();
+            self.forget_wait(tf.tpidr, &process);
+            account(&mut process);
+            process.state = State::Dead;
+            *process.context = *tf;
+        }
+        self.futexes.remove(tf.tpidr);
+
+        Some(entry)
+    }
+
     /// Adds a process to the scheduler's queue and returns that process's ID if
     /// a new process can be scheduled. The process ID is newly allocated for
     /// the process and saved in its `trap_frame`. If no further processes can
@@ -197,127 +539,877 @@ impl Scheduler {
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
     fn add(&mut self, mut process: Process) -> Option<Id> {
-        let new_pid = self.new_pid()?;
+        let new_pid = self.pids.allocate()?;
 
         (*process.context).tpidr = new_pid;
-        self.processes.push_back(process);
+        *process.threads.lock() = ThreadGroup::new(new_pid);
+        self.lineage.add(new_pid, None);
+        self.insert(new_pid, process);
 
-        self.last_id = Some(new_pid);
         Some(new_pid)
     }
 
-    fn fork(&mut self, process_id: Id) -> Option<Id> {
-        let new_pid = self.new_pid()?;
-
-        let new_process = self.processes.iter_mut()
-            .find(|process| process.context.tpidr == process_id)?
-            .fork(new_pid).ok()?;
+    /// Forks the process running with `tf`, saving `tf` into it first, and
+    /// queues the child. Returns the ID of the child, or `None` if there is no
+    /// ID or memory left for it.
+    fn fork(&mut self, tf: &mut TrapFrame) -> Option<Id> {
+        let entry = self.processes.get(&tf.tpidr)?.clone();
+        let mut parent = entry.lock();
+        *parent.context = *tf;
+        let group = parent.threads.lock().id;
+        let new_pid = self.pids.allocate()?;
+        let new_process = match parent.fork(new_pid) {
+            Ok(new_process) => new_process,
+            Err(_) => {
+                self.pids.free(new_pid);
+                return None;
+            }
+        };
+        *tf = *parent.context;
+        drop(parent);
+        self.lineage.add(new_pid, Some(group));
 
-        self.processes.push_back(new_process);
+        self.insert(new_pid, new_process);
         Some(new_pid)
     }
 
-    fn new_pid(&mut self) -> Option<Id> {
-        self.last_id = match self.last_id {
-            None => Some(Id::from(0u64)),
-            Some(pid) => {
-                if pid == u64::MAX {
-                    return None;
+    /// Creates a thread of the process running with `tf`, sharing its address
+    /// space and resources, and queues it. Returns the ID of the thread.
+    fn spawn_thread(&mut self, tf: &mut TrapFrame, entry: u64, argument: u64, tls: u64)
+                    -> OsResult<Id> {
+        let process = self.processes.get(&tf.tpidr).ok_or(OsError::NoEntry)?.clone();
+        let new_id = self.pids.allocate().ok_or(OsError::NoMemory)?;
+        let thread = match process.lock().spawn_thread(new_id, entry, argument, tls) {
+            Ok(thread) => thread,
+            Err(e) => {
+                self.pids.free(new_id);
+                return Err(e);
+            }
+        };
+
+        self.insert(new_id, thread);
+        Ok(new_id)
+    }
+
+    /// Ends the thread running with `tf`, leaving `value` for `thread_join`,
+    /// and removes it from the queue. The first thread of a process cannot end
+    /// on its own, so for it this kills the whole process with status `value`.
+    ///
+    /// Returns the ID of the thread, or `None` if there is no such thread.
+    fn exit_thread(&mut self, tf: &mut TrapFrame, value: u64) -> Option<Id> {
+        if self.group_of(tf.tpidr)? == tf.tpidr {
+            return self.kill(tf, value);
+        }
+
+        self.find_process(tf.tpidr)?.exit_thread(value);
+        let thread = self.remove(tf)?;
+        self.pids.free(tf.tpidr);
+
+        let live = {
+            let thread = thread.lock();
+            let mut group = thread.threads.lock();
+            group.ended.add(&thread.usage);
+            group.live().to_vec()
+        };
+        for joiner in live {
+            self.wake_process(joiner);
+        }
+
+        Some(tf.tpidr)
+    }
+
+    /// Blocks the process running with `tf` until `poll` returns `true`,
+    /// queueing it on `channels` to be polled whenever one is woken and, if
+    /// `deadline` is given, on the timers of the current core to be polled
+    /// once it passes. Schedules the process out and polls it once, in case
+    /// its event arrived before it blocked. Blocking and the first poll happen
+    /// in one critical section, so a wakeup after the caller last checked
+    /// cannot be missed.
+    fn block(&mut self, channels: &[Channel], deadline: Option<Duration>, poll: EventPollFn,
+             tf: &mut TrapFrame) -> bool {
+        let entry = match self.processes.get(&tf.tpidr) {
+            Some(entry) => entry.clone(),
+            None => return false,
+        };
+
+        for &channel in channels {
+            self.waits.add(channel, tf.tpidr);
+        }
+        if let Some(deadline) = deadline {
+            with_queue(aarch64::affinity(), |queue| queue.timers.add(deadline, tf.tpidr));
+        }
+        schedule_out(&entry, &mut entry.lock(), State::Waiting(poll), tf);
+        self.wake_process(tf.tpidr);
+        true
+    }
+
+    /// Wakes the processes in `expired`, whose deadlines on the current core
+    /// passed by `now` and were taken off its timers by the tick.
+    fn wake_expired(&mut self, now: Duration, expired: Vec<(Duration, Id)>) {
+        for (deadline, id) in expired {
+            let micros = (now - deadline).as_micros() as u64;
+            tracepoint::record(Tracepoint::TimerWake { pid: id, micros });
+            self.wake_process(id);
+        }
+    }
+
+    /// Takes the process `id`, locked as `process`, off the wait queues and
+    /// the timers of the core it blocked on.
+    fn forget_wait(&mut self, id: Id, process: &Process) {
+        self.waits.remove(id);
+        if let Some(core) = process.core {
+            with_queue(core, |queue| queue.timers.remove(id));
+        }
+    }
+
+    /// Wakes the processes blocked on `channel`. See `wake_process()`.
+    fn wake(&mut self, channel: Channel) {
+        for id in self.waits.waiters(channel) {
+            self.wake_process(id);
+        }
+    }
+
+    /// Polls the process `id` if it is waiting and, if its event has arrived,
+    /// takes it off the wait queues and the timers and queues it to run.
+    /// Returns whether it was made ready.
+    fn wake_process(&mut self, id: Id) -> bool {
+        let entry = match self.processes.get(&id) {
+            Some(entry) => entry.clone(),
+            None => return false,
+        };
+        let mut process = entry.lock();
+        let ready = matches!(process.state, State::Waiting(_)) && process.is_ready();
+
+        if ready {
+            self.forget_wait(id, &process);
+            enqueue(&entry, &mut process);
+        }
+        ready
+    }
+
+    /// Makes the waiting process `id` ready without polling it, for wakers that
+    /// already stored its result, and queues it to run.
+    fn make_ready(&mut self, id: Id) {
+        if let Some(entry) = self.processes.get(&id).cloned() {
+            let mut process = entry.lock();
+            process.state = State::Ready;
+            self.forget_wait(id, &process);
+            enqueue(&entry, &mut process);
+        }
+    }
+
+    /// Sets the nice value of the process `pid` to `nice`, which the policy
+    /// takes into account from the next time the process is charged.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `nice` is not from `NICE_MIN` to
+    /// `NICE_MAX` and `NoEntry` if there is no process `pid`.
+    fn set_priority(&mut self, pid: Id, nice: i64) -> OsResult<()> {
+        if !(NICE_MIN..=NICE_MAX).contains(&nice) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        self.find_process(pid).ok_or(OsError::NoEntry)?.scheduling.nice = nice;
+        Ok(())
+    }
+
+    /// Lets the process `pid` run only on the cores in `mask`, a bit per core.
+    /// A process queued or running on a core it may no longer use moves when
+    /// it is next scheduled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `mask` has none of the cores and `NoEntry`
+    /// if there is no process `pid`.
+    fn set_affinity(&mut self, pid: Id, mask: u64) -> OsResult<()> {
+        let mask = mask & ALL_CORES;
+        if mask == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+
+        self.find_process(pid).ok_or(OsError::NoEntry)?.affinity = mask;
+        Ok(())
+    }
+
+    /// Returns the cores the process `pid` may run on, a bit per core, or
+    /// `NoEntry` if there is no such process.
+    fn affinity(&self, pid: Id) -> OsResult<u64> {
+        Ok(self.find_process(pid).ok_or(OsError::NoEntry)?.affinity)
+    }
+
+    /// Kills currently running process by removing it from the process table
+    /// as `Dead` state. Releases all process resources held by the process,
+    /// drops the dead process's instance, and returns the dead process's
+    /// process ID. The process stays a zombie holding `status` and what it
+    /// used, including the children it reaped, with its ID taken, until its
+    /// parent reaps it with `wait`; its children are
+    /// reparented to init. A tracer is told of the exit with
+    /// `TraceEvent::Exited`; it and the parent are woken if they are waiting
+    /// for that. Processes it traced are detached.
+    ///
+    /// The other threads of the process are sent `Signal::Kill` and die with
+    /// the same status when they next return to user space. The address space
+    /// and resources they share are freed once the last of them is dropped.
+    fn kill(&mut self, tf: &mut TrapFrame, status: u64) -> Option<Id> {
+        let entry = self.remove(tf)?;
+        let process = entry.lock();
+        let pid = tf.tpidr;
+
+        let (status, threads, mut usage) = {
+            let mut group = process.threads.lock();
+            group.remove(pid);
+            group.ended.add(&process.usage);
+            let mut usage = group.ended;
+            usage.add(&group.reaped);
+            (*group.exit_status.get_or_insert(status), group.live().to_vec(), usage)
+        };
+        for thread in threads {
+            if let Some(live) = self.find_process(thread) {
+                usage.add(&live.usage);
+            }
+            let _ = self.signal(thread, Signal::Kill);
+        }
+        usage.max_pages = process.vmap.lock().max_pages() as u64;
+
+        let exit = self.lineage.exit(pid, (status, usage));
+        for released in exit.released {
+            self.pids.free(released);
+        }
+        for reaper in exit.reapers {
+            self.reap_for(reaper);
+        }
+
+        if let Some(tracer) = process.traced.as_ref().map(|traced| traced.tracer) {
+            let reported = match self.find_process(tracer) {
+                Some(mut tracer) => {
+                    tracer.tracing.report(pid, TraceEvent::Exited(status));
+                    true
+                }
+                None => false,
+            };
+            if reported {
+                self.wake_process(tracer);
+            }
+        }
+        for &tracee in process.tracing.tracees() {
+            if let Some(entry) = self.processes.get(&tracee) {
+                let mut traced = entry.lock();
+                if traced.untrace() {
+                    enqueue(entry, &mut traced);
+                }
+            }
+        }
+
+        Some(pid)
+    }
+
+    /// Marks `signal` pending for the process `pid`. A stopped process is
+    /// resumed by `Continue` and `Kill`, and one stopped for its tracer only by
+    /// `Kill`. A process blocked in a system call is
+    /// woken with an `Interrupted` error if the signal would be acted on, so
+    /// the signal is delivered when it returns to user space.
+    ///
+    /// Returns `NoEntry` if there is no process `pid`.
+    fn signal(&mut self, pid: Id, signal: Signal) -> OsResult<()> {
+        let mut process = self.find_process(pid).ok_or(OsError::NoEntry)?;
+        process.signals.raise(signal);
+
+        let wake = match process.state {
+            State::Stopped => signal == Signal::Continue || signal == Signal::Kill,
+            State::Traced => signal == Signal::Kill,
+            State::Waiting(_) => {
+                let deliverable = process.signals.deliverable();
+                if deliverable {
+                    process.context.xs[7] = OsError::Interrupted as u64;
+                    process.futex = None;
+                    process.reaping = None;
                 }
-                Some(Id::from(pid + 1))
+                deliverable
             }
+            _ => false,
         };
+        drop(process);
 
-        self.last_id
+        if wake {
+            self.make_ready(pid);
+        }
+        Ok(())
     }
 
-    /// Finds the currently running process, sets the current process's state
-    /// to `new_state`, prepares the context switch on `tf` by saving `tf`
-    /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// Checks that the `u32` at `address` in the process running with `tf`
+    /// holds `expected` and, if so, schedules the process out to wait on it
+    /// until it is woken by `futex_wake`, interrupted by a signal or, if
+    /// `deadline` is given, the deadline passes. It then fails with
+    /// `IoErrorTimedOut`.
     ///
-    /// If the `processes` queue is empty or there is no current process,
-    /// returns `false`. Otherwise, returns `true`.
-    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
-        for (i, process) in self.processes.iter_mut().enumerate() {
-            if process.context.tpidr == tf.tpidr {
-                process.state = new_state;
-                *process.context = *tf;
-                let p = self.processes.remove(i).unwrap();
-                self.processes.push_back(p);
-
-                return true;
+    /// The check and the wait happen in one critical section, so a wake that
+    /// follows a change of the value cannot be missed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `address` is not aligned, `BadAddress` if
+    /// it is not mapped and `WouldBlock` if the value is not `expected`.
+    fn futex_wait(&mut self, tf: &mut TrapFrame, address: u64, expected: u32,
+                  deadline: Option<Duration>) -> OsResult<()> {
+        let key = {
+            let mut process = self.find_process(tf.tpidr).ok_or(OsError::NoEntry)?;
+            let key = futex_key(&process, address)?;
+            if UserPtr::<u32>::new(address).read(&process.vmap.lock())? != expected {
+                return Err(OsError::WouldBlock);
+            }
+            process.futex = Some(key);
+            key
+        };
+        self.futexes.park(key, tf.tpidr);
+
+        let waiting: EventPollFn = Box::new(move |process| {
+            match deadline {
+                Some(deadline) if deadline <= timer::current_time() => {
+                    process.futex = None;
+                    process.context.xs[7] = OsError::IoErrorTimedOut as u64;
+                    true
+                }
+                _ => false,
             }
+        });
+        self.block(&[], deadline, waiting, tf);
+        Ok(())
+    }
+
+    /// Makes up to `count` of the processes waiting on the futex at `address`
+    /// in the process running with `tf` ready, in the order they started
+    /// waiting. Returns how many were woken.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `address` is not aligned and `BadAddress`
+    /// if it is not mapped.
+    fn futex_wake(&mut self, tf: &TrapFrame, address: u64, count: u64) -> OsResult<u64> {
+        let key = futex_key(&*self.find_process(tf.tpidr).ok_or(OsError::NoEntry)?, address)?;
+
+        let mut woken = 0;
+        while woken < count {
+            let pid = match self.futexes.pop(key) {
+                Some(pid) => pid,
+                None => break,
+            };
+
+            match self.find_process(pid) {
+                Some(mut waiter) if waiter.futex == Some(key) => {
+                    waiter.futex = None;
+                    waiter.context.xs[7] = OsError::Ok as u64;
+                }
+                _ => continue,
+            }
+            self.make_ready(pid);
+            woken += 1;
         }
 
-        false
+        Ok(woken)
     }
 
-    /// Finds the next process to switch to, brings the next process to the
-    /// front of the `processes` queue, changes the next process's state to
-    /// `Running`, and performs context switch by restoring the next process`s
-    /// trap frame into `tf`.
+    /// Reaps an exited child of the process running with `tf`: `pid`, or any
+    /// child if it is `WAIT_ANY`. The child's ID and exit status are stored
+    /// in `tf` for `wait` to return, and what it used at the user address
+    /// `usage` unless it is 0. Its ID is freed.
     ///
-    /// If there is no process to switch to, returns `None`. Otherwise, returns
-    /// `Some` of the next process`s process ID.
-    fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        let mut j = self.processes.len();
-        for (i, process) in self.processes.iter_mut().enumerate() {
-            if process.is_ready() {
-                j = i;
-                break;
+    /// If no matching child has exited yet, stores that none was reaped if
+    /// `no_hang` is set. Otherwise the process blocks until `kill` reaps a
+    /// child for it, or a signal interrupts it. Returns whether it blocked.
+    ///
+    /// Children belong to the process, so any of its threads can reap them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if no child matches `pid`, and `BadAddress` if
+    /// `usage` cannot be written, in which case the child is reaped anyway.
+    fn wait(&mut self, tf: &mut TrapFrame, pid: Id, no_hang: bool, usage: u64) -> OsResult<bool> {
+        let group = self.group_of(tf.tpidr).ok_or(OsError::NoEntry)?;
+        let selector = if pid == WAIT_ANY { None } else { Some(pid) };
+        match self.lineage.reap(group, selector).map_err(|_| OsError::NoEntry)? {
+            Some((id, (status, used))) => {
+                tf.xs[0] = id;
+                tf.xs[1] = status;
+                tf.xs[2] = 1;
+                self.collect(tf.tpidr, id, &used, usage)?;
+                Ok(false)
             }
+            None if no_hang => {
+                tf.xs[2] = 0;
+                Ok(false)
+            }
+            None => {
+                self.find_process(tf.tpidr).ok_or(OsError::NoEntry)?.reaping = Some((pid, usage));
+                self.block(&[], None, Box::new(|_| false), tf);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reaps a child for each thread of the process `parent` blocked in
+    /// `wait` on a child that has exited, and makes it ready.
+    fn reap_for(&mut self, parent: Id) {
+        let threads = match self.find_process(parent) {
+            Some(process) => process.threads.lock().live().to_vec(),
+            None => return,
         };
 
-        if j == self.processes.len() {
-            None
-        } else {
-            let mut process = self.processes.remove(j)?;
-            process.state = State::Running;
-            let id = process.context.tpidr;
-            (*tf) = *process.context;
-            self.processes.push_front(process);
+        for id in threads {
+            let (pid, usage) = match self.find_process(id).and_then(|waiter| waiter.reaping) {
+                Some(reaping) => reaping,
+                None => continue,
+            };
+            let selector = if pid == WAIT_ANY { None } else { Some(pid) };
+            let (child, (status, used)) = match self.lineage.reap(parent, selector) {
+                Ok(Some(reaped)) => reaped,
+                _ => continue,
+            };
+            let result = match self.collect(id, child, &used, usage) {
+                Ok(()) => OsError::Ok,
+                Err(err) => err,
+            };
 
-            Some(id)
+            if let Some(mut waiter) = self.find_process(id) {
+                waiter.reaping = None;
+                waiter.context.xs[0] = child;
+                waiter.context.xs[1] = status;
+                waiter.context.xs[2] = 1;
+                waiter.context.xs[7] = result as u64;
+            }
+            self.make_ready(id);
         }
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Releases all process resources held by the process,
-    /// removes the dead process from the queue, drops the dead process's
-    /// instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        self.schedule_out(State::Dead, tf);
+    /// Finishes reaping `child` for the thread `waiter`: frees the child's
+    /// ID, adds what it used to the children of the waiter's process, and
+    /// stores it at the waiter's user address `usage` unless it is 0.
+    fn collect(&mut self, waiter: Id, child: Id, used: &Usage, usage: u64) -> OsResult<()> {
+        self.pids.free(child);
+
+        let process = self.find_process(waiter).ok_or(OsError::NoEntry)?;
+        process.threads.lock().reaped.add(used);
+        if usage != 0 {
+            UserPtr::<Usage>::new(usage).write(&process.vmap.lock(), used)?;
+        }
+        Ok(())
+    }
+
+    /// Returns what a process used, with its ID: with `USAGE_SELF` the
+    /// process running with `tf`, with `USAGE_CHILDREN` the children it
+    /// reaped, and with `USAGE_NEXT` the process with the lowest ID from
+    /// `pid` on. The time its threads ran since they were last switched to is
+    /// not counted yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if there is no such process and `InvalidArgument`
+    /// if `who` is none of these.
+    fn usage(&mut self, tf: &TrapFrame, who: u64, pid: Id) -> OsResult<(Id, Usage)> {
+        let group = match who {
+            USAGE_SELF | USAGE_CHILDREN => self.group_of(tf.tpidr).ok_or(OsError::NoEntry)?,
+            USAGE_NEXT => *self.processes.range(pid..)
+                .find(|(id, process)| process.lock().threads.lock().id == **id)
+                .ok_or(OsError::NoEntry)?.0,
+            _ => return Err(OsError::InvalidArgument),
+        };
 
-        let process = self.processes.pop_back()?;
-        let pid = process.context.tpidr;
+        if who == USAGE_CHILDREN {
+            let reaped = self.find_process(group).ok_or(OsError::NoEntry)?.threads.lock().reaped;
+            return Ok((group, reaped));
+        }
+        Ok((group, self.group_usage(group).ok_or(OsError::NoEntry)?))
+    }
 
-        if let Some(parent_id) = process.parent {
-            if let Some(parent) = self.find_process(parent_id) {
-                parent.dead_children.push(process.context.tpidr);
+    /// Returns what the process `group` used so far: the time and counts of
+    /// its threads, including those that ended, and the pages mapped in its
+    /// address space.
+    fn group_usage(&self, group: Id) -> Option<Usage> {
+        let (mut usage, live, vmap) = {
+            let process = self.find_process(group)?;
+            let threads = process.threads.lock();
+            (threads.ended, threads.live().to_vec(), process.vmap.clone())
+        };
+
+        for id in live {
+            if let Some(thread) = self.find_process(id) {
+                usage.add(&thread.usage);
             }
         }
 
-        Some(pid)
+        let vmap = vmap.lock();
+        usage.pages = vmap.pages() as u64;
+        usage.max_pages = vmap.max_pages() as u64;
+        Some(usage)
+    }
+
+    /// Returns the ID of the process the thread `id` belongs to, which is its
+    /// own ID for the first thread.
+    fn group_of(&self, id: Id) -> Option<Id> {
+        self.find_process(id).map(|process| process.threads.lock().id)
+    }
+
+    /// Carries out the trace `request` of the process running with `tf` on the
+    /// process `pid`, with `address` and `data` where the request takes them.
+    /// Returns the word read for `TraceRequest::Read` and `0` otherwise.
+    ///
+    /// A process can trace its children, by attaching to them or by their
+    /// asking for it with `TraceRequest::Me`. Requests other than `Detach`
+    /// need the process to be stopped for its tracer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if `pid` is not a child of the caller for `Attach`,
+    /// or is not traced by the caller for the other requests, and
+    /// `InvalidArgument` if the process to trace already is traced or the
+    /// process is not stopped. Accesses to memory fail with `BadAddress`.
+    fn trace(&mut self, tf: &TrapFrame, request: TraceRequest, pid: Id, address: u64,
+             data: u64) -> OsResult<u64> {
+        let caller = tf.tpidr;
+        match request {
+            TraceRequest::Me => {
+                let group = self.group_of(caller).ok_or(OsError::NoEntry)?;
+                let parent = self.lineage.parent(group).ok_or(OsError::NoEntry)?;
+                return self.start_trace(parent, caller, None).map(|_| 0);
+            }
+            TraceRequest::Attach => {
+                let group = self.group_of(caller).ok_or(OsError::NoEntry)?;
+                if !self.lineage.children(group).contains(&pid) {
+                    return Err(OsError::NoEntry);
+                }
+                return self.start_trace(caller, pid, Some(TraceEvent::Attached)).map(|_| 0);
+            }
+            _ => {}
+        }
+
+        let vmap = self.find_process(caller).ok_or(OsError::NoEntry)?.vmap.clone();
+        let entry = self.processes.get(&pid).cloned().ok_or(OsError::NoEntry)?;
+        let mut tracee = entry.lock();
+        if tracee.traced.as_ref().map(|traced| traced.tracer) != Some(caller) {
+            return Err(OsError::NoEntry);
+        }
+
+        if request == TraceRequest::Detach {
+            if tracee.untrace() {
+                enqueue(&entry, &mut tracee);
+            }
+            drop(tracee);
+            self.find_process(caller).ok_or(OsError::NoEntry)?.tracing.remove(pid);
+            return Ok(0);
+        }
+
+        if !matches!(tracee.state, State::Traced) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        match request {
+            TraceRequest::Continue | TraceRequest::Syscall | TraceRequest::Step => {
+                tracee.resume_traced(match request {
+                    TraceRequest::Continue => Resume::Continue,
+                    TraceRequest::Syscall => Resume::Syscall,
+                    _ => Resume::Step,
+                });
+                enqueue(&entry, &mut tracee);
+            }
+            TraceRequest::GetRegisters => {
+                let registers = trace::registers(&tracee.context);
+                UserPtr::<Registers>::new(address).write(&vmap.lock(), &registers)?;
+            }
+            TraceRequest::SetRegisters => {
+                let registers = UserPtr::<Registers>::new(address).read(&vmap.lock())?;
+                trace::set_registers(&mut tracee.context, &registers);
+            }
+            TraceRequest::Read => {
+                return UserPtr::<u64>::new(address).read(&tracee.vmap.lock());
+            }
+            TraceRequest::Write => {
+                UserPtr::<u64>::new(address).poke(&tracee.vmap.lock(), &data)?;
+            }
+            TraceRequest::Me | TraceRequest::Attach | TraceRequest::Detach => unreachable!(),
+        }
+
+        Ok(0)
+    }
+
+    /// Makes `tracer` trace `tracee`, which stops for `event` if one is given.
+    fn start_trace(&mut self, tracer: Id, tracee: Id, event: Option<TraceEvent>) -> OsResult<()> {
+        if self.find_process(tracer).is_none() {
+            return Err(OsError::NoEntry);
+        }
+
+        {
+            let mut process = self.find_process(tracee).ok_or(OsError::NoEntry)?;
+            if process.traced.is_some() {
+                return Err(OsError::InvalidArgument);
+            }
+
+            let mut traced = Tracee::new(tracer);
+            if let Some(event) = event {
+                traced.stop_at(event);
+            }
+            process.traced = Some(traced);
+        }
+
+        self.find_process(tracer).ok_or(OsError::NoEntry)?.tracing.add(tracee);
+        Ok(())
+    }
+
+    /// Stops the process about to return to user space with `tf` if it is
+    /// traced and has an event to report, which is queued for its tracer to
+    /// collect with `TraceWait`. Returns whether the process stopped, in which
+    /// case `tf` has to be switched to the next process.
+    fn trace_stop(&mut self, tf: &mut TrapFrame) -> bool {
+        let pid = tf.tpidr;
+        let entry = match self.processes.get(&pid) {
+            Some(entry) => entry.clone(),
+            None => return false,
+        };
+        let (tracer, event) = match entry.lock().traced.as_mut() {
+            Some(traced) => match traced.next_stop() {
+                Some(event) => (traced.tracer, event),
+                None => return false,
+            },
+            None => return false,
+        };
+
+        match self.find_process(tracer) {
+            Some(mut process) => process.tracing.report(pid, event),
+            None => return false,
+        }
+        self.wake_process(tracer);
+        schedule_out(&entry, &mut entry.lock(), State::Traced, tf);
+        true
+    }
+
+    /// Locks the process with the ID `id`, the tpidr saved in its trap frame.
+    pub fn find_process(&self, id: Id) -> Option<MutexGuard<'_, Process>> {
+        self.processes.get(&id).map(|process| process.lock())
+    }
+}
+
+/// Returns the process running on the current core if it runs with `tf`.
+fn running(tf: &TrapFrame) -> Option<ProcessRef> {
+    match current() {
+        Some((id, process)) if id == tf.tpidr => Some(process),
+        _ => None,
+    }
+}
+
+/// Sets the state of `process`, locked from `entry` and running with `tf` on
+/// the current core, to `new_state`, saves `tf` into it and charges it for
+/// the time it ran. A `Ready` process is put back on a run queue, see
+/// `enqueue()`. Neither the scheduler nor another process is locked.
+fn schedule_out(entry: &ProcessRef, process: &mut Process, new_state: State, tf: &TrapFrame) {
+    let ran = account(process);
+    process.scheduling.charge(ran);
+
+    let ready = matches!(new_state, State::Ready);
+    process.state = new_state;
+    *process.context = *tf;
+    if ready {
+        enqueue(entry, process);
+    }
+}
+
+/// Puts `process`, locked from `entry`, on the run queue of the core it last
+/// ran on, or of the least loaded core its affinity allows if it may not run
+/// there or has not run yet.
+fn enqueue(entry: &ProcessRef, process: &mut Process) {
+    let core = match process.core {
+        Some(core) if process.affinity & (1 << core) != 0 => core,
+        _ => least_loaded(process.affinity),
+    };
+    process.core = Some(core);
+    let queued = Queued::of(entry, process);
+    with_queue(core, |queue| queue.enqueue(process.context.tpidr, queued));
+}
+
+/// Switches `core` to the process `id`, which `pick()` took off a run queue
+/// with what the queue kept in `queued`: changes its state to `Running`,
+/// makes it the current process of the core and performs context switch by
+/// restoring its trap frame into `tf`. Only the process is locked.
+///
+/// A process that is no longer ready is dropped, and one that may no longer
+/// run on the core is queued on a core it may run on. Returns whether `tf`
+/// was switched to the process.
+fn run(core: usize, id: Id, queued: Queued, tf: &mut TrapFrame) -> bool {
+    let entry = queued.process;
+    {
+        let mut process = entry.lock();
+        if !matches!(process.state, State::Ready) {
+            return false;
+        }
+
+        process.scheduling.vruntime = queued.entity.vruntime;
+        if process.affinity & (1 << core) == 0 {
+            enqueue(&entry, &mut process);
+            return false;
+        }
+
+        process.state = State::Running;
+        process.core = Some(core);
+        process.scheduling.started = queued.entity.started;
+        process.usage.switches += 1;
+        utilisation(core).switches.fetch_add(1, Ordering::Relaxed);
+        tracepoint::record(Tracepoint::Switch { pid: id });
+        (*tf) = *process.context;
+    }
+
+    set_current(Some((id, entry)));
+    true
+}
+
+/// Runs `f` with the run queue of `core` locked.
+fn with_queue<R>(core: usize, f: impl FnOnce(&mut CoreQueue) -> R) -> R {
+    let mut queue = run_queue(core).lock();
+    f(queue.as_mut().expect("run queue uninitialized"))
+}
+
+/// Takes the next process to run on `core` off the run queues, with the time
+/// it is switched to in its entity, and returns it with the next deadline of
+/// the core.
+///
+/// The core first balances its load if it is time to. It then takes the next
+/// process that may run on it from its own run queue, moving the processes
+/// before it that may no longer run there to a core they may run on, or, if
+/// there is none, steals one from another core. The scheduler lock is not
+/// taken, and only one run queue is locked at a time.
+fn pick(core: usize) -> (Option<(Id, Queued)>, Option<Duration>) {
+    balance(core);
+
+    let now = timer::current_time();
+    let (next, misplaced, deadline) = with_queue(core, |queue| {
+        let (next, misplaced) = queue.take(core);
+        (next, misplaced, queue.timers.next())
+    });
+    for (id, queued) in misplaced {
+        with_queue(least_loaded(queued.affinity), |queue| queue.enqueue(id, queued));
     }
 
-    /// Finds a process corresponding with tpidr saved in a trap frame.
-    /// Panics if the search fails.
-    pub fn find_process(&mut self, id: Id) -> Option<&mut Process> {
-        self.processes.iter_mut().find(|process| process.context.tpidr == id)
+    let mut next = next.or_else(|| steal(core));
+    if let Some((_, queued)) = next.as_mut() {
+        queued.entity.started = now;
     }
+    with_queue(core, |queue| queue.running = next.as_ref().map(|(id, _)| (*id, now)));
+    (next, deadline)
+}
+
+/// Takes the first process on the run queue of `from` that may run on `core`,
+/// leaving the others queued. Only that run queue is locked.
+fn take_from(from: usize, core: usize) -> Option<(Id, Queued)> {
+    with_queue(from, |queue| {
+        let (next, skipped) = queue.take(core);
+        for (id, queued) in skipped {
+            queue.enqueue(id, queued);
+        }
+        next
+    })
+}
+
+/// Takes a process that may run on `core` from the run queue of another core,
+/// trying the cores with the most processes queued first.
+fn steal(core: usize) -> Option<(Id, Queued)> {
+    let mut victims: Vec<usize> = (0..NCORES)
+        .filter(|&other| other != core && queued(other) > 0)
+        .collect();
+    victims.sort_by_key(|&other| Reverse(queued(other)));
+
+    victims.into_iter().find_map(|other| take_from(other, core))
+}
+
+/// Moves a process that may run on `core` from the run queue of the busiest
+/// core to that of `core` if the busiest has at least two more processes
+/// queued. Does nothing if `core` balanced its load less than
+/// `BALANCE_INTERVAL` ago.
+fn balance(core: usize) {
+    let now = timer::current_time();
+    let next_balance = next_balance();
+    if (now.as_micros() as u64) < next_balance.load(Ordering::Relaxed) {
+        return;
+    }
+    next_balance.store((now + BALANCE_INTERVAL).as_micros() as u64, Ordering::Relaxed);
+
+    let load = queued(core);
+    let busiest = (0..NCORES)
+        .filter(|&other| other != core)
+        .max_by_key(|&other| queued(other));
+    let busiest = match busiest {
+        Some(busiest) if queued(busiest) >= load + 2 => busiest,
+        _ => return,
+    };
+
+    if let Some((id, queued)) = take_from(busiest, core) {
+        with_queue(core, |queue| queue.enqueue(id, queued));
+    }
+}
+
+/// Returns the number of processes on the run queue of `core`.
+fn queued(core: usize) -> usize {
+    with_queue(core, |queue| queue.len())
+}
+
+/// Returns the core with the fewest processes queued of those in `affinity`.
+fn least_loaded(affinity: u64) -> usize {
+    (0..NCORES)
+        .filter(|&core| affinity & (1 << core) != 0)
+        .min_by_key(|&core| queued(core))
+        .unwrap_or(0)
+}
+
+/// Sets the local timer of `core` for the earlier of `slice_end`, when the
+/// running process is to be preempted, and `deadline`, the next deadline of
+/// the processes blocked on the core. With neither, the timer is stopped, so
+/// an idle core with nothing due takes no timer interrupts.
+fn set_timer(core: usize, slice_end: Option<Duration>, deadline: Option<Duration>) {
+    let due = match (slice_end, deadline) {
+        (Some(slice_end), Some(deadline)) => Some(min(slice_end, deadline)),
+        (due, None) | (None, due) => due,
+    };
+
+    match due {
+        Some(due) => local_tick_in(core, due.saturating_sub(timer::current_time())),
+        None => local_tick_stop(core),
+    }
+}
+
+/// Adds the time `process` ran since it was switched to to the busy time of
+/// the current core and to the usage of the process, split between user space
+/// and the kernel, and returns it.
+fn account(process: &mut Process) -> Duration {
+    let ran = timer::current_time().saturating_sub(process.scheduling.started);
+    let micros = ran.as_micros() as u64;
+    utilisation(aarch64::affinity()).busy.fetch_add(micros, Ordering::Relaxed);
+
+    let user = min(take_user_time(), micros);
+    process.usage.user_time += user;
+    process.usage.system_time += micros - user;
+    ran
+}
+
+/// Returns the key of the futex at the user address `address` of `process`:
+/// the physical address of the word.
+fn futex_key(process: &Process, address: u64) -> OsResult<u64> {
+    if address % 4 != 0 {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let physical = process.vmap.lock().translate(VirtualAddr::from(address))
+        .map_err(|_| OsError::BadAddress)?;
+    Ok(physical.as_u64())
 }
 
 impl fmt::Debug for Scheduler {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let len = self.processes.len();
-        write!(f, "  [Scheduler] {} processes in the queue\n", len)?;
-        for i in 0..len {
-            write!(
-                f,
-                "    queue[{}]: proc({:3})-{:?} \n",
-                i, self.processes[i].context.tpidr, self.processes[i].state
-            )?;
+        write!(f, "  [Scheduler] {} processes\n", len)?;
+        for (id, process) in self.processes.iter() {
+            match process.try_lock() {
+                Some(process) => write!(f, "    proc({:3})-{:?} on {:?}\n", id, process.state, process.core)?,
+                None => write!(f, "    proc({:3})-<locked>\n", id)?,
+            }
+        }
+        for core in 0..NCORES {
+            write!(f, "    core {}: {} queued\n", core, queued(core))?;
         }
         Ok(())
     }
@@ -0,0 +1,266 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult, Signal};
+use kernel_api::{SIGNAL_BLOCK, SIGNAL_DEFAULT, SIGNAL_IGNORE, SIGNAL_SET_MASK, SIGNAL_UNBLOCK};
+
+use crate::multiprocessing::mutex::Mutex;
+use crate::traps::TrapFrame;
+
+/// The most signal handlers that may be running at once in one process. A
+/// process that goes deeper is terminated.
+const MAX_NESTED_HANDLERS: usize = 16;
+
+/// Space left below the interrupted stack pointer before a handler's frame.
+const HANDLER_STACK_GAP: u64 = 128;
+
+/// How a process has asked for a signal to be handled.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum SignalAction {
+    Default,
+    Ignore,
+    /// Call `handler` with the signal number in `x0` and `restorer` as its
+    /// return address. `restorer` is expected to call sigreturn.
+    Handler { handler: u64, restorer: u64 },
+}
+
+/// What a signal does to a process that has not set an action for it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(signal: Signal) -> DefaultAction {
+    match signal {
+        Signal::Child => DefaultAction::Ignore,
+        Signal::Continue => DefaultAction::Continue,
+        Signal::Stop | Signal::TerminalStop => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// What the scheduler has to do with a process after delivering it a signal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    /// Kill the process with the given exit status.
+    Terminate(u64),
+    /// Stop the process until it is sent `Signal::Continue`.
+    Stop,
+}
+
+/// A context interrupted by a signal handler, put back by sigreturn. It is
+/// kept in the kernel so a handler cannot forge the registers it returns to.
+#[derive(Clone, Debug)]
+struct SavedContext {
+    context: TrapFrame,
+    blocked: u64,
+}
+
+/// The signal state of a thread: which signals are pending and blocked, and
+/// what to do with each. The actions belong to the process and are shared by
+/// its threads.
+#[derive(Debug)]
+pub(crate) struct Signals {
+    pending: u64,
+    blocked: u64,
+    actions: Arc<Mutex<[SignalAction; 32]>>,
+    saved: Vec<SavedContext>,
+}
+
+impl Signals {
+    pub(crate) fn new() -> Self {
+        Signals {
+            pending: 0,
+            blocked: 0,
+            actions: Arc::new(Mutex::new([SignalAction::Default; 32])),
+            saved: Vec::new(),
+        }
+    }
+
+    /// The signal state a forked child starts with: a copy of the actions and
+    /// the same blocked signals, but nothing pending.
+    pub(crate) fn for_child(&self) -> Self {
+        Signals {
+            pending: 0,
+            blocked: self.blocked,
+            actions: Arc::new(Mutex::new(*self.actions.lock())),
+            saved: self.saved.clone(),
+        }
+    }
+
+    /// The signal state a new thread starts with: the actions of its process
+    /// and the same blocked signals, with nothing pending and no handler
+    /// running.
+    pub(crate) fn for_thread(&self) -> Self {
+        Signals {
+            pending: 0,
+            blocked: self.blocked,
+            actions: self.actions.clone(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Resets handlers to the default action, as the code they pointed at is
+    /// gone after `execute`. Ignored signals stay ignored.
+    pub(crate) fn reset_handlers(&mut self) {
+        for action in self.actions.lock().iter_mut() {
+            if let SignalAction::Handler { .. } = action {
+                *action = SignalAction::Default;
+            }
+        }
+        self.saved.clear();
+    }
+
+    /// Marks `signal` as pending. A continue signal discards pending stop
+    /// signals and the other way around.
+    pub(crate) fn raise(&mut self, signal: Signal) {
+        match signal {
+            Signal::Continue => {
+                self.pending &= !(Signal::Stop.mask() | Signal::TerminalStop.mask());
+            }
+            Signal::Stop | Signal::TerminalStop => {
+                self.pending &= !Signal::Continue.mask();
+            }
+            _ => {}
+        }
+
+        self.pending |= signal.mask();
+    }
+
+    /// Raises a signal caused by the process itself, such as a fault. It
+    /// cannot be blocked or ignored, since the process could not make progress.
+    pub(crate) fn force(&mut self, signal: Signal) {
+        self.blocked &= !signal.mask();
+        let mut actions = self.actions.lock();
+        if let SignalAction::Ignore = actions[signal as usize] {
+            actions[signal as usize] = SignalAction::Default;
+        }
+        drop(actions);
+        self.raise(signal);
+    }
+
+    /// Returns whether a pending signal would do anything if delivered now.
+    pub(crate) fn deliverable(&self) -> bool {
+        (1..32).filter_map(Signal::from_u64)
+            .any(|signal| self.is_deliverable(signal) && !self.is_ignored(signal))
+    }
+
+    pub(crate) fn set_action(&mut self, signal: Signal, handler: u64, restorer: u64) -> OsResult<()> {
+        if !signal.can_catch() {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let action = match handler {
+            SIGNAL_DEFAULT => SignalAction::Default,
+            SIGNAL_IGNORE => SignalAction::Ignore,
+            handler => SignalAction::Handler { handler, restorer },
+        };
+        self.actions.lock()[signal as usize] = action;
+
+        if self.is_ignored(signal) {
+            self.pending &= !signal.mask();
+        }
+        Ok(())
+    }
+
+    /// Updates the blocked signals with `mask` as `how` says and returns the
+    /// previous set. `Kill` and `Stop` are never blocked.
+    pub(crate) fn set_blocked(&mut self, how: u64, mask: u64) -> OsResult<u64> {
+        let previous = self.blocked;
+        self.blocked = match how {
+            SIGNAL_BLOCK => self.blocked | mask,
+            SIGNAL_UNBLOCK => self.blocked & !mask,
+            SIGNAL_SET_MASK => mask,
+            _ => return Err(OsError::InvalidArgument),
+        } & !(Signal::Kill.mask() | Signal::Stop.mask());
+
+        Ok(previous)
+    }
+
+    /// Delivers pending signals to the process whose registers are `context`.
+    /// Ignored signals are dropped. For a handled signal, `context` is saved
+    /// and rewritten to enter the handler, and any further signals wait until
+    /// the process next returns to user space.
+    ///
+    /// Returns what the scheduler has to do if a default action terminates
+    /// or stops the process.
+    pub(crate) fn deliver(&mut self, context: &mut TrapFrame) -> Option<Delivery> {
+        while let Some(signal) = self.take() {
+            let action = self.actions.lock()[signal as usize];
+            match action {
+                SignalAction::Ignore => {}
+                SignalAction::Handler { handler, restorer } => {
+                    if self.saved.len() >= MAX_NESTED_HANDLERS {
+                        return Some(Delivery::Terminate(exit_status(signal)));
+                    }
+
+                    self.saved.push(SavedContext {
+                        context: *context,
+                        blocked: self.blocked,
+                    });
+                    self.blocked |= signal.mask();
+
+                    context.sp = (context.sp - HANDLER_STACK_GAP) & !0xF;
+                    context.elr = handler;
+                    context.xs[0] = signal as u64;
+                    context.xs[30] = restorer;
+                    return None;
+                }
+                SignalAction::Default => match default_action(signal) {
+                    DefaultAction::Terminate => {
+                        return Some(Delivery::Terminate(exit_status(signal)));
+                    }
+                    DefaultAction::Stop => return Some(Delivery::Stop),
+                    DefaultAction::Ignore | DefaultAction::Continue => {}
+                },
+            }
+        }
+
+        None
+    }
+
+    /// Puts back the context the innermost running handler interrupted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if no handler is running.
+    pub(crate) fn restore(&mut self, context: &mut TrapFrame) -> OsResult<()> {
+        let saved = self.saved.pop().ok_or(OsError::InvalidArgument)?;
+        *context = saved.context;
+        self.blocked = saved.blocked;
+        Ok(())
+    }
+
+    fn is_deliverable(&self, signal: Signal) -> bool {
+        self.pending & !self.blocked & signal.mask() != 0
+    }
+
+    fn is_ignored(&self, signal: Signal) -> bool {
+        let action = self.actions.lock()[signal as usize];
+        match action {
+            SignalAction::Default => {
+                let action = default_action(signal);
+                action == DefaultAction::Ignore || action == DefaultAction::Continue
+            }
+            SignalAction::Ignore => true,
+            SignalAction::Handler { .. } => false,
+        }
+    }
+
+    /// Removes the lowest numbered pending signal that is not blocked and
+    /// returns it.
+    fn take(&mut self) -> Option<Signal> {
+        let signal = (1..32).filter_map(Signal::from_u64)
+            .find(|signal| self.is_deliverable(*signal))?;
+        self.pending &= !signal.mask();
+        Some(signal)
+    }
+}
+
+/// The exit status of a process terminated by `signal`, as shells report it.
+fn exit_status(signal: Signal) -> u64 {
+    128 + signal as u64
+}
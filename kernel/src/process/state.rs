@@ -4,10 +4,11 @@ use core::fmt;
 use crate::process::Process;
 
 /// Type of a function used to determine if a process is ready to be scheduled
-/// again. The scheduler calls this function when it is the process's turn to
-/// execute. If the function returns `true`, the process is scheduled. If it
-/// returns `false`, the process is not scheduled, and this function will be
-/// called on the next time slice.
+/// again. The scheduler calls this function when the process blocks and every
+/// time it is woken, by a wait channel it blocked on or directly by whatever
+/// it waits for. If the function returns `true`, the process is queued to run.
+/// If it returns `false`, the process stays off the run queues until the next
+/// wakeup.
 pub type EventPollFn = Box<dyn FnMut(&mut Process) -> bool + Send>;
 
 /// The scheduling state of a process.
@@ -18,6 +19,10 @@ pub enum State {
     Waiting(EventPollFn),
     /// The process is currently running.
     Running,
+    /// The process was stopped by a signal and waits for `Signal::Continue`.
+    Stopped,
+    /// The process stopped for its tracer and waits to be resumed by it.
+    Traced,
     /// The process is currently dead (ready to be reclaimed).
     Dead,
 }
@@ -27,6 +32,8 @@ impl fmt::Debug for State {
         match *self {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
+            State::Stopped => write!(f, "State::Stopped"),
+            State::Traced => write!(f, "State::Traced"),
             State::Waiting(_) => write!(f, "State::Waiting"),
             State::Dead => write!(f, "State::Dead"),
         }
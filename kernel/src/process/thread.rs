@@ -0,0 +1,79 @@
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult, Usage};
+
+use crate::process::Id;
+
+/// The bookkeeping shared by the threads of one process.
+#[derive(Debug)]
+pub(crate) struct ThreadGroup {
+    /// The process id, which is the thread id of the first thread
+    pub(crate) id: Id,
+    /// Threads that are still running
+    live: Vec<Id>,
+    /// Threads that have called `thread_exit`, with the value they passed it
+    exited: Vec<(Id, u64)>,
+    /// The exit status of the process once any of its threads has exited it
+    pub(crate) exit_status: Option<u64>,
+    /// What the threads that ended used
+    pub(crate) ended: Usage,
+    /// What the children the process reaped used
+    pub(crate) reaped: Usage,
+}
+
+impl ThreadGroup {
+    /// Returns the group of a new process whose first thread is `id`.
+    pub(crate) fn new(id: Id) -> Self {
+        let mut live = Vec::new();
+        live.push(id);
+        ThreadGroup {
+            id,
+            live,
+            exited: Vec::new(),
+            exit_status: None,
+            ended: Usage::default(),
+            reaped: Usage::default(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, id: Id) {
+        self.live.push(id);
+    }
+
+    /// Records that the thread `id` called `thread_exit` with `value`, to be
+    /// collected by `join`.
+    pub(crate) fn exit(&mut self, id: Id, value: u64) {
+        self.remove(id);
+        self.exited.push((id, value));
+    }
+
+    /// Forgets the thread `id`, which was killed along with the process.
+    pub(crate) fn remove(&mut self, id: Id) {
+        self.live.retain(|live| *live != id);
+    }
+
+    /// Returns the threads that are still running.
+    pub(crate) fn live(&self) -> &[Id] {
+        self.live.as_slice()
+    }
+
+    /// Takes the value the thread `id` exited with.
+    ///
+    /// Returns `None` if the thread is still running.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if `id` is not a thread of this group or has already
+    /// been joined.
+    pub(crate) fn join(&mut self, id: Id) -> OsResult<Option<u64>> {
+        if let Some(i) = self.exited.iter().position(|(exited, _)| *exited == id) {
+            return Ok(Some(self.exited.remove(i).1));
+        }
+
+        if self.live.contains(&id) {
+            Ok(None)
+        } else {
+            Err(OsError::NoEntry)
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::process::Id;
+
+/// The deadlines of the processes that blocked on one core, earliest first.
+///
+/// A deadline is the time, as read from `pi::timer`, at which a process that
+/// blocked with a timeout is woken to notice it has passed. The core keeps its
+/// local timer programmed for the earliest one, so a process is woken on time
+/// without the core taking a periodic tick.
+#[derive(Debug, Default)]
+pub(crate) struct TimerQueue {
+    deadlines: BTreeSet<(Duration, Id)>,
+}
+
+impl TimerQueue {
+    /// Adds a deadline at `deadline` for the process `id`.
+    pub(crate) fn add(&mut self, deadline: Duration, id: Id) {
+        self.deadlines.insert((deadline, id));
+    }
+
+    /// Removes the deadlines of the process `id`.
+    pub(crate) fn remove(&mut self, id: Id) {
+        self.deadlines.retain(|&(_, waiter)| waiter != id);
+    }
+
+    /// Returns the earliest deadline.
+    pub(crate) fn next(&self) -> Option<Duration> {
+        self.deadlines.iter().next().map(|&(deadline, _)| deadline)
+    }
+
+    /// Takes the deadlines at or before `now` off the queue and returns them
+    /// with their processes, in deadline order.
+    pub(crate) fn expire(&mut self, now: Duration) -> Vec<(Duration, Id)> {
+        let mut expired = Vec::new();
+        while let Some(&(deadline, id)) = self.deadlines.iter().next() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.remove(&(deadline, id));
+            expired.push((deadline, id));
+        }
+        expired
+    }
+}
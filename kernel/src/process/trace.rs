@@ -0,0 +1,192 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use aarch64::SPSR_EL1;
+use kernel_api::{OsError, OsResult, Registers, TraceEvent, WAIT_ANY};
+
+use crate::process::Id;
+use crate::traps::TrapFrame;
+
+/// How a traced process runs once its tracer resumes it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Resume {
+    /// Until its next breakpoint
+    Continue,
+    /// Until it enters or leaves a system call
+    Syscall,
+    /// For a single instruction
+    Step,
+}
+
+/// Where a traced process is in a system call whose entry it reported.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SyscallPhase {
+    Outside,
+    /// The entry was reported and the call is made when the process resumes
+    Entered(u16),
+    /// The call is being made and its exit is reported when it returns
+    Running(u16),
+}
+
+/// The tracing state of a traced process.
+///
+/// A traced process only stops on its way back to user space. The kernel notes
+/// why with `stop_at`, and the scheduler takes the process off the CPU for its
+/// tracer when `next_stop` returns an event. To stop on entry to a system call,
+/// the program counter is put back on the `svc`, so the call is made once the
+/// process is resumed.
+#[derive(Debug)]
+pub(crate) struct Tracee {
+    /// The process tracing this one
+    pub(crate) tracer: Id,
+    resume: Resume,
+    phase: SyscallPhase,
+    /// The event to stop for on the way back to user space
+    pending: Option<TraceEvent>,
+}
+
+impl Tracee {
+    pub(crate) fn new(tracer: Id) -> Self {
+        Tracee {
+            tracer,
+            resume: Resume::Continue,
+            phase: SyscallPhase::Outside,
+            pending: None,
+        }
+    }
+
+    /// Makes the process stop for `event` before it next returns to user space.
+    pub(crate) fn stop_at(&mut self, event: TraceEvent) {
+        self.pending = Some(event);
+    }
+
+    /// Whether the process was resumed for a single instruction.
+    pub(crate) fn stepping(&self) -> bool {
+        self.resume == Resume::Step
+    }
+
+    /// Called when the process running with `context` makes the system call
+    /// `number`. Returns `true` if the call must wait until its entry has been
+    /// reported.
+    pub(crate) fn enter_syscall(&mut self, number: u16, context: &mut TrapFrame) -> bool {
+        match self.phase {
+            SyscallPhase::Entered(entered) => {
+                self.phase = SyscallPhase::Running(entered);
+                false
+            }
+            _ if self.resume == Resume::Syscall => {
+                self.phase = SyscallPhase::Entered(number);
+                self.stop_at(TraceEvent::SyscallEntry(number));
+                context.elr -= 4;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the event the process has to stop for before returning to user
+    /// space, if any: the exit of a system call whose entry was reported, or
+    /// the event passed to `stop_at`.
+    pub(crate) fn next_stop(&mut self) -> Option<TraceEvent> {
+        if let SyscallPhase::Running(number) = self.phase {
+            self.phase = SyscallPhase::Outside;
+            if self.resume == Resume::Syscall {
+                return Some(TraceEvent::SyscallExit(number));
+            }
+        }
+
+        self.pending.take()
+    }
+
+    /// Sets how the process running with `context` runs from now on.
+    pub(crate) fn resume(&mut self, resume: Resume, context: &mut TrapFrame) {
+        self.resume = resume;
+        if resume == Resume::Step {
+            context.spsr |= SPSR_EL1::SS;
+        } else {
+            context.spsr &= !SPSR_EL1::SS;
+        }
+    }
+}
+
+/// Returns the user visible registers saved in `context`.
+pub(crate) fn registers(context: &TrapFrame) -> Registers {
+    Registers {
+        xs: context.xs,
+        sp: context.sp,
+        pc: context.elr,
+        pstate: context.spsr,
+        tls: context.tls,
+    }
+}
+
+/// Replaces the user visible registers saved in `context` with `registers`.
+/// Only the condition flags of `pstate` are taken, so a tracer cannot change
+/// the exception level or interrupt masks the process returns with.
+pub(crate) fn set_registers(context: &mut TrapFrame, registers: &Registers) {
+    context.xs = registers.xs;
+    context.sp = registers.sp;
+    context.elr = registers.pc;
+    context.spsr = (context.spsr & !Registers::FLAGS) | (registers.pstate & Registers::FLAGS);
+    context.tls = registers.tls;
+}
+
+/// The processes a process traces and the events they reported that it has
+/// not collected with `TraceWait` yet.
+#[derive(Debug, Default)]
+pub(crate) struct Tracer {
+    tracees: Vec<Id>,
+    events: VecDeque<(Id, TraceEvent)>,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Tracer::default()
+    }
+
+    /// Returns the processes being traced.
+    pub(crate) fn tracees(&self) -> &[Id] {
+        &self.tracees
+    }
+
+    pub(crate) fn add(&mut self, id: Id) {
+        self.tracees.push(id);
+    }
+
+    /// Forgets the process `id`, which was detached, along with the events it
+    /// reported.
+    pub(crate) fn remove(&mut self, id: Id) {
+        self.tracees.retain(|tracee| *tracee != id);
+        self.events.retain(|(tracee, _)| *tracee != id);
+    }
+
+    /// Queues `event` of the process `id`. A process that exited is no longer
+    /// traced, but its event is kept to be collected.
+    pub(crate) fn report(&mut self, id: Id, event: TraceEvent) {
+        if let TraceEvent::Exited(_) = event {
+            self.tracees.retain(|tracee| *tracee != id);
+        }
+        self.events.push_back((id, event));
+    }
+
+    /// Takes the oldest event of the process `pid`, or of any process traced if
+    /// `pid` is `WAIT_ANY`.
+    ///
+    /// Returns `None` if a matching process is traced but has no event yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if no traced process matches `pid`.
+    pub(crate) fn take(&mut self, pid: u64) -> OsResult<Option<(Id, TraceEvent)>> {
+        let matches = |id: &Id| pid == WAIT_ANY || *id == pid;
+        if let Some(i) = self.events.iter().position(|(id, _)| matches(id)) {
+            return Ok(self.events.remove(i));
+        }
+
+        if self.tracees.iter().any(matches) {
+            Ok(None)
+        } else {
+            Err(OsError::NoEntry)
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::process::Id;
+
+/// Identifies what a blocked process waits for. By convention it is the
+/// address of the object that changes, such as the shared state of a pipe, so
+/// the waiters and the wakers of an object agree on it without registering it
+/// anywhere.
+pub type Channel = usize;
+
+/// Returns the channel of `object`: its address.
+pub fn channel_of<T: ?Sized>(object: &T) -> Channel {
+    object as *const T as *const () as Channel
+}
+
+/// The processes blocked on each wait channel.
+///
+/// A process stays on the queues of the channels it blocked on until it stops
+/// waiting, however it is woken, so a wakeup that finds its event has not
+/// arrived yet leaves it where the next one will find it.
+#[derive(Debug, Default)]
+pub(crate) struct WaitQueues {
+    queues: BTreeMap<Channel, Vec<Id>>,
+}
+
+impl WaitQueues {
+    pub(crate) fn new() -> Self {
+        WaitQueues::default()
+    }
+
+    /// Queues the process `id` on `channel` if it is not already.
+    pub(crate) fn add(&mut self, channel: Channel, id: Id) {
+        let queue = self.queues.entry(channel).or_default();
+        if !queue.contains(&id) {
+            queue.push(id);
+        }
+    }
+
+    /// Returns the processes queued on `channel`, in the order they blocked.
+    pub(crate) fn waiters(&self, channel: Channel) -> Vec<Id> {
+        self.queues.get(&channel).cloned().unwrap_or_default()
+    }
+
+    /// Takes the process `id` off the queue of every channel.
+    pub(crate) fn remove(&mut self, id: Id) {
+        self.queues.retain(|_, queue| {
+            queue.retain(|waiter| *waiter != id);
+            !queue.is_empty()
+        });
+    }
+}
@@ -0,0 +1,274 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt;
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+
+use filesystem::CharDevice;
+use kernel_api::{OsError, Syscall};
+use pi::timer;
+use shim::{io, ioerr};
+
+use crate::console::kprintln;
+use crate::param::{NCORES, TRACE_RING_SIZE};
+
+/// Whether tracepoints are recorded. Recording is switched on and off by
+/// writing to `/trace`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The ring of each core. A core only ever writes to its own ring.
+static RINGS: [Ring; NCORES] = [Ring::new(), Ring::new(), Ring::new(), Ring::new()];
+
+/// An event recorded at a tracepoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tracepoint {
+    /// The scheduler switched the core to the process `pid`.
+    Switch { pid: u64 },
+    /// The process `pid` made the system call `number`.
+    SyscallEntry { pid: u64, number: u16 },
+    /// The system call `number` of the process `pid` returned `status`, the
+    /// value of an `OsError`.
+    SyscallExit { pid: u64, number: u16, status: u64 },
+    /// A global interrupt was dispatched to its handler.
+    Irq { number: u64 },
+    /// A core local interrupt was dispatched to its handler.
+    LocalIrq { number: u64 },
+    /// A user page was allocated at the physical `address`.
+    PageAlloc { address: u64 },
+    /// The user page at the physical `address` was freed.
+    PageFree { address: u64 },
+    /// A sector was read from the SD card in `micros` microseconds.
+    BlockRead { sector: u64, micros: u64 },
+    /// The deadline the process `pid` blocked with passed `micros`
+    /// microseconds before the timer woke it.
+    TimerWake { pid: u64, micros: u64 },
+}
+
+impl Tracepoint {
+    fn encode(self) -> [u64; 4] {
+        match self {
+            Tracepoint::Switch { pid } => [0, pid, 0, 0],
+            Tracepoint::SyscallEntry { pid, number } => [1, pid, number as u64, 0],
+            Tracepoint::SyscallExit { pid, number, status } => [2, pid, number as u64, status],
+            Tracepoint::Irq { number } => [3, number, 0, 0],
+            Tracepoint::LocalIrq { number } => [4, number, 0, 0],
+            Tracepoint::PageAlloc { address } => [5, address, 0, 0],
+            Tracepoint::PageFree { address } => [6, address, 0, 0],
+            Tracepoint::BlockRead { sector, micros } => [7, sector, micros, 0],
+            Tracepoint::TimerWake { pid, micros } => [8, pid, micros, 0],
+        }
+    }
+
+    fn decode(words: [u64; 4]) -> Option<Tracepoint> {
+        let [kind, a, b, c] = words;
+        Some(match kind {
+            0 => Tracepoint::Switch { pid: a },
+            1 => Tracepoint::SyscallEntry { pid: a, number: b as u16 },
+            2 => Tracepoint::SyscallExit { pid: a, number: b as u16, status: c },
+            3 => Tracepoint::Irq { number: a },
+            4 => Tracepoint::LocalIrq { number: a },
+            5 => Tracepoint::PageAlloc { address: a },
+            6 => Tracepoint::PageFree { address: a },
+            7 => Tracepoint::BlockRead { sector: a, micros: b },
+            8 => Tracepoint::TimerWake { pid: a, micros: b },
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Tracepoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Tracepoint::Switch { pid } => write!(f, "switch to {}", pid),
+            Tracepoint::SyscallEntry { pid, number } =>
+                write!(f, "{} enters {:?}", pid, Syscall::from(number)),
+            Tracepoint::SyscallExit { pid, number, status } =>
+                write!(f, "{} leaves {:?}: {:?}", pid, Syscall::from(number), OsError::from(status)),
+            Tracepoint::Irq { number } => write!(f, "irq {}", number),
+            Tracepoint::LocalIrq { number } => write!(f, "local irq {}", number),
+            Tracepoint::PageAlloc { address } => write!(f, "page alloc {:#x}", address),
+            Tracepoint::PageFree { address } => write!(f, "page free {:#x}", address),
+            Tracepoint::BlockRead { sector, micros } =>
+                write!(f, "read sector {} in {}us", sector, micros),
+            Tracepoint::TimerWake { pid, micros } =>
+                write!(f, "timer wakes {} {}us late", pid, micros),
+        }
+    }
+}
+
+/// A tracepoint as read back from a ring.
+#[derive(Copy, Clone, Debug)]
+struct Record {
+    core: usize,
+    /// Microseconds since boot, from `pi::timer`
+    time: u64,
+    tracepoint: Tracepoint,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:>12}us core {}] {}", self.time, self.core, self.tracepoint)
+    }
+}
+
+/// One entry of a ring. `sequence` is odd while the entry is being written and
+/// `2 * (index + 1)` once the record `index` is complete, so readers can tell
+/// a torn or overwritten entry from the one they asked for.
+struct Slot {
+    sequence: AtomicU64,
+    time: AtomicU64,
+    words: [AtomicU64; 4],
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Slot = Slot {
+        sequence: AtomicU64::new(0),
+        time: AtomicU64::new(0),
+        words: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+    };
+}
+
+/// A ring of the last `TRACE_RING_SIZE` records of one core. Writers claim an
+/// index with a single atomic add and never wait, so tracepoints may be hit
+/// with the scheduler lock held or from interrupt handlers.
+struct Ring {
+    /// The index of the next record
+    head: AtomicU64,
+    slots: [Slot; TRACE_RING_SIZE],
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring {
+            head: AtomicU64::new(0),
+            slots: [Slot::EMPTY; TRACE_RING_SIZE],
+        }
+    }
+
+    fn push(&self, time: u64, words: [u64; 4]) {
+        let index = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[index as usize % TRACE_RING_SIZE];
+
+        slot.sequence.store(2 * index + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        slot.time.store(time, Ordering::Relaxed);
+        for (word, value) in slot.words.iter().zip(words) {
+            word.store(value, Ordering::Relaxed);
+        }
+        slot.sequence.store(2 * (index + 1), Ordering::Release);
+    }
+
+    /// Returns the record `index` of `core` if it is complete and has not been
+    /// overwritten.
+    fn get(&self, core: usize, index: u64) -> Option<Record> {
+        let slot = &self.slots[index as usize % TRACE_RING_SIZE];
+        let expected = 2 * (index + 1);
+        if slot.sequence.load(Ordering::Acquire) != expected {
+            return None;
+        }
+
+        let time = slot.time.load(Ordering::Relaxed);
+        let mut words = [0; 4];
+        for (value, word) in words.iter_mut().zip(&slot.words) {
+            *value = word.load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        if slot.sequence.load(Ordering::Relaxed) != expected {
+            return None;
+        }
+
+        Some(Record { core, time, tracepoint: Tracepoint::decode(words)? })
+    }
+
+    /// Returns the records still in the ring, oldest first.
+    fn records(&self, core: usize) -> impl Iterator<Item=Record> + '_ {
+        let head = self.head.load(Ordering::Acquire);
+        let start = head.saturating_sub(TRACE_RING_SIZE as u64);
+        (start..head).filter_map(move |index| self.get(core, index))
+    }
+}
+
+/// Records `tracepoint` in the ring of the current core, stamped with the
+/// current time, if recording is switched on.
+#[inline]
+pub fn record(tracepoint: Tracepoint) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let time = timer::current_time().as_micros() as u64;
+    RINGS[aarch64::affinity()].push(time, tracepoint.encode());
+}
+
+/// Switches recording of tracepoints on or off.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Prints the records still in the rings, core by core. Called on panic, so it
+/// does not allocate.
+pub fn dump() {
+    for (core, ring) in RINGS.iter().enumerate() {
+        for record in ring.records(core) {
+            kprintln!("{}", record);
+        }
+    }
+}
+
+/// The `/trace` pseudo-file.
+///
+/// Reading it returns the records in the rings of every core when it is first
+/// read, merged in time order, one line each, and then the end of the file.
+/// Writing `on` or `off` to it switches recording.
+#[derive(Clone, Default)]
+pub struct TraceFile {
+    text: Option<Vec<u8>>,
+    position: usize,
+}
+
+impl TraceFile {
+    pub fn new() -> TraceFile {
+        TraceFile::default()
+    }
+
+    fn snapshot() -> Vec<u8> {
+        let mut records: Vec<Record> = RINGS.iter().enumerate()
+            .flat_map(|(core, ring)| ring.records(core))
+            .collect();
+        records.sort_by_key(|record| record.time);
+
+        let mut text = Vec::new();
+        for record in records {
+            text.extend_from_slice(format!("{}\n", record).as_bytes());
+        }
+        text
+    }
+}
+
+impl io::Read for TraceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let text = self.text.get_or_insert_with(TraceFile::snapshot);
+        let amount = min(buf.len(), text.len() - self.position);
+        buf[..amount].copy_from_slice(&text[self.position..self.position + amount]);
+        self.position += amount;
+        Ok(amount)
+    }
+}
+
+impl io::Write for TraceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match core::str::from_utf8(buf).map(str::trim) {
+            Ok("on") => set_enabled(true),
+            Ok("off") => set_enabled(false),
+            _ => return ioerr!(InvalidInput),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CharDevice for TraceFile {}
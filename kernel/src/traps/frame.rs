@@ -9,7 +9,12 @@ pub struct TrapFrame {
     pub elr: u64,
     pub spsr: u64,
     pub sp: u64,
+    /// The id of the thread, kept in `TPIDRRO_EL0` so user space can read but
+    /// not change it.
     pub tpidr: u64,
+    /// The thread local storage pointer in `TPIDR_EL0`.
+    pub tls: u64,
+    _reserved: u64,
     pub qs: [u128; 32],
     pub xs: [u64; 31],
     pub xzr: u64,
@@ -24,6 +29,7 @@ impl fmt::Display for TrapFrame {
             .field("xs", &self.xs)
             .field("qs", &self.qs)
             .field("tpidr", &self.tpidr)
+            .field("tls", &self.tls)
             .field("sp", &self.sp)
             .field("spsr", &self.spsr)
             .field("elr", &self.elr)
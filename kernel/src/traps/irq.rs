@@ -35,6 +35,7 @@ impl GlobalIrq {
             Mutex::new(None),
             Mutex::new(None),
             Mutex::new(None),
+            Mutex::new(None),
         ])
     }
 }
@@ -78,6 +79,7 @@ impl Index<Interrupt> for GlobalIrq {
             Gpio2 => 5,
             Gpio3 => 6,
             Uart => 7,
+            Aux => 8,
         };
         &self.0[index]
     }
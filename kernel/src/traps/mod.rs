@@ -1,12 +1,14 @@
 use core::fmt;
 use core::fmt::Formatter;
 
-use aarch64::enable_fiq_interrupt;
+use aarch64::{enable_fiq_interrupt, MDSCR_EL1};
+use kernel_api::Signal;
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use crate::GLOABAL_IRQ;
-use crate::multiprocessing::per_core::local_irq;
+use crate::{GLOABAL_IRQ, SCHEDULER};
+use crate::multiprocessing::per_core::{enter_kernel, enter_user, local_irq};
+use crate::tracepoint::{self, Tracepoint};
 use crate::traps::irq::IrqHandlerRegistry;
 
 pub use self::frame::TrapFrame;
@@ -60,6 +62,9 @@ impl fmt::Display for Info {
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     let syndrome = Syndrome::from(esr);
+    if info.source == Source::LowerAArch64 {
+        enter_kernel();
+    }
 
     //info!("handle_exception {}: {}", info, syndrome);
 
@@ -67,12 +72,23 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
         Kind::Synchronous => {
             enable_fiq_interrupt();
             match syndrome {
+                Syndrome::Brk(_) if info.source == Source::LowerAArch64 => {
+                    let _ = SCHEDULER.on_process(tf, |process| process.breakpoint());
+                }
                 Syndrome::Brk(_) => {
                     tf.elr += 4;
                 }
+                Syndrome::Step if info.source == Source::LowerAArch64 => {
+                    let _ = SCHEDULER.on_process(tf, |process| process.stepped());
+                }
                 Syndrome::Svc(s) => {
                     handle_syscall(s, tf);
                 }
+                _ if info.source == Source::LowerAArch64 => {
+                    if let Some(signal) = fault_signal(syndrome) {
+                        let _ = SCHEDULER.on_process(tf, |process| process.signals.force(signal));
+                    }
+                }
                 _ => {}
             }
         }
@@ -83,6 +99,7 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 let global_controller = Controller::new();
                 for global_interrupt in Interrupt::iter() {
                     if global_controller.is_pending(global_interrupt) {
+                        tracepoint::record(Tracepoint::Irq { number: global_interrupt as u64 });
                         GLOABAL_IRQ.invoke(global_interrupt, tf);
                     }
                 }
@@ -91,6 +108,7 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
             let local_controller = LocalController::new(core);
             for local_int in LocalInterrupt::iter() {
                 if local_controller.is_pending(local_int) {
+                    tracepoint::record(Tracepoint::LocalIrq { number: local_int as u64 });
                     local_irq().invoke(local_int, tf);
                 }
             }
@@ -98,4 +116,36 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
         Kind::Fiq => {}
         _ => {}
     }
+
+    if returns_to_user(tf) {
+        let stepping = SCHEDULER.return_to_user(tf);
+        set_software_step(stepping);
+        enter_user();
+    }
+}
+
+/// Returns the signal a user process receives for causing `syndrome`, if any.
+fn fault_signal(syndrome: Syndrome) -> Option<Signal> {
+    match syndrome {
+        Syndrome::InstructionAbort { .. } | Syndrome::DataAbort { .. } => {
+            Some(Signal::SegmentationFault)
+        }
+        Syndrome::PCAlignmentFault | Syndrome::SpAlignmentFault => Some(Signal::BusError),
+        Syndrome::Unknown | Syndrome::IllegalExecutionState => Some(Signal::IllegalInstruction),
+        _ => None,
+    }
+}
+
+/// Returns whether the exception return for `tf` goes to EL0.
+pub(crate) fn returns_to_user(tf: &TrapFrame) -> bool {
+    tf.spsr & 0b1111 == 0
+}
+
+/// Turns software step on or off for the return to user space. With it on, the
+/// process takes a `Step` exception after one instruction (ref: D2.12).
+fn set_software_step(enabled: bool) {
+    unsafe {
+        let mdscr = MDSCR_EL1.get() & !MDSCR_EL1::SS;
+        MDSCR_EL1.set(if enabled { mdscr | MDSCR_EL1::SS } else { mdscr });
+    }
 }
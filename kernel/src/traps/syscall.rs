@@ -1,16 +1,19 @@
 use alloc::boxed::Box;
 use alloc::string::{String, ToString};
 use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
 use core::time::Duration;
 
 use kernel_api::*;
-use kernel_api::OsError::BadAddress;
 use pi::timer;
+use shim::io::SeekFrom;
 
-use crate::{kprintln, SCHEDULER};
-use crate::memory::{PagePerm, VirtualAddr};
+use crate::SCHEDULER;
+use crate::memory::{PagePerm, UserPtr, UserSlice, VirtualAddr};
 use crate::param::{PAGE_SIZE, USER_IMG_BASE};
-use crate::process::{ResourceId, State};
+use crate::process::{Process, ResourceId};
+use crate::tracepoint::{self, Tracepoint};
 use crate::traps::TrapFrame;
 
 /// Sleep for `ms` milliseconds.
@@ -25,21 +28,20 @@ pub fn sys_sleep(tf: &mut TrapFrame) -> OsResult<()> {
     let started = timer::current_time();
     let sleep_until = started + Duration::from_millis(ms as u64);
 
-    let waiting = State::Waiting(Box::new(move |process| {
+    let waiting = Box::new(move |process: &mut Process| {
         let current_time = timer::current_time();
-        let passed = sleep_until < current_time;
+        let passed = sleep_until <= current_time;
         if passed {
             let millis: u64 = (current_time - started).as_millis() as u64;
-            kprintln!("{}", millis);
             process.context.xs[0] = millis;
-            process.context.xs[8] = 0;
+            process.context.xs[7] = OsError::Ok as u64;
         }
         passed
-    }));
+    });
 
-    SCHEDULER.switch(waiting, tf);
+    SCHEDULER.block(&[], Some(sleep_until), waiting, tf);
 
-    Ok(())
+    blocked_status(tf)
 }
 
 /// Returns current time.
@@ -57,26 +59,52 @@ pub fn sys_time(tf: &mut TrapFrame) -> OsResult<()> {
     Ok(())
 }
 
+/// Returns what a process used.
+///
+/// This system call takes three parameters: `USAGE_SELF` for the calling
+/// process, `USAGE_CHILDREN` for the children it reaped or `USAGE_NEXT` for
+/// the process with the lowest id from the second parameter on, that id, and
+/// the address of the `Usage` to fill in.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the id of the process the usage is of.
+fn sys_getrusage(tf: &mut TrapFrame) -> OsResult<()> {
+    let who = tf.xs[0];
+    let pid = tf.xs[1];
+    let user_usage = UserPtr::<Usage>::new(tf.xs[2]);
+
+    let (id, usage) = SCHEDULER.usage(tf, who, pid)?;
+    SCHEDULER.on_process(tf, |process| user_usage.write(&process.vmap.lock(), &usage))??;
+    tf.xs[0] = id;
+    Ok(())
+}
+
 /// Kills the current process.
 ///
-/// This system call does not take paramer and does not return any value.
+/// This system call takes one parameter: the exit status, which is handed to
+/// the parent through `wait`. It does not return.
 pub fn sys_exit(tf: &mut TrapFrame) -> OsResult<()> {
-    SCHEDULER.kill(tf).expect("failed to kill process");
+    let status = tf.xs[0];
+    SCHEDULER.kill(tf, status).expect("failed to kill process");
     SCHEDULER.switch_to(tf);
 
     Ok(())
 }
 
+/// Opens a file or directory.
+///
+/// This system call takes three parameters: the address and length of the
+/// path, and a combination of the `OPEN_*` flags.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the resource id of the opened entry.
 pub fn sys_open(tf: &mut TrapFrame) -> OsResult<()> {
-    let ptr = tf.xs[0];
-    let len = tf.xs[1] as usize;
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+    let flags = tf.xs[2];
 
-    let mut buffer = vec![0u8; len];
-    copy_from_userspace(tf, ptr, buffer.as_mut_slice())?;
-    let path = String::from_utf8_lossy(buffer.as_slice()).to_string();
-
-    tf.xs[0] = SCHEDULER.on_process(tf, |process| {
-        process.open(path)
+    tf.xs[0] = SCHEDULER.on_process(tf, |process| -> OsResult<ResourceId> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.open(String::from_utf8_lossy(buffer.as_slice()).to_string(), flags)
     })??.into();
 
     Ok(())
@@ -85,37 +113,59 @@ pub fn sys_open(tf: &mut TrapFrame) -> OsResult<()> {
 fn sys_close(tf: &mut TrapFrame) -> OsResult<()> {
     let id = ResourceId::from(tf.xs[0]);
     SCHEDULER.on_process(tf, |process| {
-        process.resources.remove(id)?;
+        process.resources.lock().remove(id)?;
         Ok(())
     })?
 }
 
 pub fn sys_read(tf: &mut TrapFrame) -> OsResult<()> {
-    let descriptor = tf.xs[0];
-    let ptr = tf.xs[1];
-    let len = tf.xs[2] as usize;
-
-    let mut buffer = vec![0u8; len];
-
-    let amount_read = SCHEDULER.on_process(tf, |process| {
-        process.read(ResourceId::from(descriptor), buffer.as_mut_slice())
-    })??;
-
-    copy_into_userspace(tf, ptr, &buffer[0..amount_read])?;
-    tf.xs[0] = amount_read as u64;
-
-    Ok(())
+    let descriptor = ResourceId::from(tf.xs[0]);
+    let user_buffer = UserSlice::new(tf.xs[1], tf.xs[2] as usize);
+
+    let read = move |process: &mut Process| -> OsResult<usize> {
+        user_buffer.validate_writable(&process.vmap.lock())?;
+        let mut buffer = vec![0u8; user_buffer.len()];
+        let amount_read = process.read(descriptor, buffer.as_mut_slice())?;
+        user_buffer.copy_from(&process.vmap.lock(), &buffer[0..amount_read])?;
+        Ok(amount_read)
+    };
+
+    // A read that would block waits on the resource's channel and is retried
+    // whenever it is woken.
+    let channel = match SCHEDULER.on_process(tf, |process| match read(process) {
+        Ok(amount_read) => {
+            process.context.xs[0] = amount_read as u64;
+            Ok(None)
+        }
+        Err(OsError::WouldBlock) => process.wait_channel(descriptor).map(Some).ok_or(OsError::WouldBlock),
+        Err(err) => Err(err),
+    })?? {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+
+    SCHEDULER.block(&[channel], None, Box::new(move |process| {
+        let result = match read(process) {
+            Err(OsError::WouldBlock) => return false,
+            Ok(amount_read) => {
+                process.context.xs[0] = amount_read as u64;
+                OsError::Ok
+            }
+            Err(err) => err,
+        };
+        process.context.xs[7] = result as u64;
+        true
+    }), tf);
+
+    blocked_status(tf)
 }
 
 pub fn sys_write(tf: &mut TrapFrame) -> OsResult<()> {
     let descriptor = tf.xs[0];
-    let ptr = tf.xs[1];
-    let len = tf.xs[2] as usize;
-
-    let mut buffer = vec![0u8; len];
-    copy_from_userspace(tf, ptr, buffer.as_mut_slice())?;
+    let user_buffer = UserSlice::new(tf.xs[1], tf.xs[2] as usize);
 
-    let amount_written = SCHEDULER.on_process(tf, |process| {
+    let amount_written = SCHEDULER.on_process(tf, |process| -> OsResult<usize> {
+        let buffer = user_buffer.to_vec(&process.vmap.lock())?;
         process.write(ResourceId::from(descriptor), buffer.as_slice())
     })??;
     tf.xs[0] = amount_written as u64;
@@ -138,9 +188,111 @@ fn sys_pipe(tf: &mut TrapFrame) -> OsResult<()> {
 /// This system call does not take parameter.
 ///
 /// In addition to the usual status value, this system call returns a
-/// parameter: the current process's ID.
+/// parameter: the current process's ID, which is the ID of its first thread.
 pub fn sys_getpid(tf: &mut TrapFrame) -> OsResult<()> {
-    tf.xs[0] = tf.tpidr;
+    tf.xs[0] = SCHEDULER.on_process(tf, |process| process.threads.lock().id)?;
+    Ok(())
+}
+
+/// Creates a thread in the current process.
+///
+/// This system call takes three parameters: the address the thread starts
+/// at, a value passed to it in `x0` and its thread local storage pointer. The
+/// kernel maps a stack for the thread.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the ID of the new thread.
+fn sys_thread_spawn(tf: &mut TrapFrame) -> OsResult<()> {
+    let entry = tf.xs[0];
+    let argument = tf.xs[1];
+    let tls = tf.xs[2];
+
+    tf.xs[0] = SCHEDULER.spawn_thread(tf, entry, argument, tls)?;
+    Ok(())
+}
+
+/// Ends the calling thread.
+///
+/// This system call takes one parameter: a value handed to the thread that
+/// joins this one. It does not return. Ending the first thread of a process
+/// exits the whole process with the value as its status.
+fn sys_thread_exit(tf: &mut TrapFrame) -> OsResult<()> {
+    let value = tf.xs[0];
+    SCHEDULER.exit_thread(tf, value).expect("failed to exit thread");
+
+    Ok(())
+}
+
+/// Waits for a thread of the current process to end.
+///
+/// This system call takes one parameter: the ID of the thread.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the value the thread passed to thread exit.
+fn sys_thread_join(tf: &mut TrapFrame) -> OsResult<()> {
+    let id = tf.xs[0];
+    if id == tf.tpidr {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let joined = SCHEDULER.on_process(tf, |process| process.threads.lock().join(id))??;
+    if let Some(value) = joined {
+        tf.xs[0] = value;
+        return Ok(());
+    }
+
+    SCHEDULER.block(&[], None, Box::new(move |process| {
+        let result = match process.threads.lock().join(id) {
+            Ok(None) => return false,
+            Ok(Some(value)) => {
+                process.context.xs[0] = value;
+                OsError::Ok
+            }
+            Err(err) => err,
+        };
+        process.context.xs[7] = result as u64;
+        true
+    }), tf);
+
+    blocked_status(tf)
+}
+
+/// Gives up the CPU to the processes waiting to run. The caller runs again
+/// once they had their turn.
+///
+/// This system call does not take parameter.
+fn sys_yield(tf: &mut TrapFrame) -> OsResult<()> {
+    tf.xs[7] = OsError::Ok as u64;
+    SCHEDULER.yield_now(tf);
+
+    blocked_status(tf)
+}
+
+/// Sets the nice value of a process or thread, which weighs its share of the
+/// CPU.
+///
+/// This system call takes two parameters: the ID of the process or thread and
+/// the nice value, from `NICE_MIN` to `NICE_MAX`.
+fn sys_setpriority(tf: &mut TrapFrame) -> OsResult<()> {
+    SCHEDULER.set_priority(tf.xs[0], tf.xs[1] as i64)
+}
+
+/// Sets the cores a process or thread may run on.
+///
+/// This system call takes two parameters: the ID of the process or thread and
+/// a mask with bit `n` set for core `n`.
+fn sys_setaffinity(tf: &mut TrapFrame) -> OsResult<()> {
+    SCHEDULER.set_affinity(tf.xs[0], tf.xs[1])
+}
+
+/// Returns the cores a process or thread may run on.
+///
+/// This system call takes one parameter: the ID of the process or thread.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: a mask with bit `n` set for core `n`.
+fn sys_getaffinity(tf: &mut TrapFrame) -> OsResult<()> {
+    tf.xs[0] = SCHEDULER.affinity(tf.xs[0])?;
     Ok(())
 }
 
@@ -156,7 +308,7 @@ pub fn sys_sbrk(tf: &mut TrapFrame) -> OsResult<()> {
     let result = SCHEDULER.on_process(tf, |process| -> OsResult<(u64, u64)> {
         //TODO: pick a better heap base / allow more sbrks / something might be wrong with is_valid
         let heap_base = USER_IMG_BASE + PAGE_SIZE;
-        process.vmap.alloc(VirtualAddr::from(heap_base), PagePerm::RW);
+        process.vmap.lock().map(VirtualAddr::from(heap_base), PagePerm::RW)?;
         Ok((heap_base as u64, PAGE_SIZE as u64))
     })??;
 
@@ -166,6 +318,62 @@ pub fn sys_sbrk(tf: &mut TrapFrame) -> OsResult<()> {
     Ok(())
 }
 
+/// Maps anonymous, zeroed memory into the address space of the process.
+///
+/// This system call takes three parameters: the address to map at, or zero to
+/// let the kernel pick one, the length in bytes and the `MEMORY_*` protection
+/// bits.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping.
+fn sys_mmap(tf: &mut TrapFrame) -> OsResult<()> {
+    let address = match tf.xs[0] {
+        0 => None,
+        address => Some(VirtualAddr::from(address)),
+    };
+    let length = tf.xs[1] as usize;
+    let perm = page_perm(tf.xs[2])?;
+
+    let mapping = SCHEDULER.on_process(tf, |process| {
+        process.map_memory(address, length, perm)
+    })??;
+
+    tf.xs[0] = mapping.as_u64();
+    Ok(())
+}
+
+/// Unmaps memory from the address space of the process and frees it.
+///
+/// This system call takes two parameters: the page aligned address of the
+/// range and its length in bytes.
+fn sys_munmap(tf: &mut TrapFrame) -> OsResult<()> {
+    let address = VirtualAddr::from(tf.xs[0]);
+    let length = tf.xs[1] as usize;
+
+    SCHEDULER.on_process(tf, |process| {
+        process.unmap_memory(address, length)
+    })??;
+
+    Ok(())
+}
+
+/// Returns the page permissions for the `MEMORY_*` protection bits `protection`.
+fn page_perm(protection: u64) -> OsResult<PagePerm> {
+    if protection & !(MEMORY_READ | MEMORY_WRITE | MEMORY_EXECUTE) != 0 ||
+        protection & MEMORY_READ == 0 {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let writable = protection & MEMORY_WRITE != 0;
+    let executable = protection & MEMORY_EXECUTE != 0;
+    Ok(match (writable, executable) {
+        (false, false) => PagePerm::RO,
+        (false, true) => PagePerm::RX,
+        (true, false) => PagePerm::RW,
+        (true, true) => PagePerm::RWX,
+    })
+}
+
 fn sys_duplicate(tf: &mut TrapFrame) -> OsResult<()> {
     let descriptor = ResourceId::from(tf.xs[0]);
     let new_descriptor = ResourceId::from(tf.xs[1]);
@@ -177,8 +385,365 @@ fn sys_duplicate(tf: &mut TrapFrame) -> OsResult<()> {
     Ok(())
 }
 
-fn sys_seek(_tf: &mut TrapFrame) -> OsResult<()> {
-    Err(OsError::Unknown)
+/// Returns information about the file system entry at a path.
+///
+/// This system call takes three parameters: the address and length of the
+/// path, and the address of a `Stat` to fill in.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_stat(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+    let user_stat = UserPtr::<Stat>::new(tf.xs[2]);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        let stat = process.stat(String::from_utf8_lossy(buffer.as_slice()).to_string())?;
+        user_stat.write(&process.vmap.lock(), &stat)
+    })?
+}
+
+/// Returns information about the entry behind an open resource.
+///
+/// This system call takes two parameters: the resource id and the address of a
+/// `Stat` to fill in.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_fstat(tf: &mut TrapFrame) -> OsResult<()> {
+    let descriptor = ResourceId::from(tf.xs[0]);
+    let user_stat = UserPtr::<Stat>::new(tf.xs[1]);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let stat = process.fstat(descriptor)?;
+        user_stat.write(&process.vmap.lock(), &stat)
+    })?
+}
+
+/// Creates an empty file.
+///
+/// This system call takes two parameters: the address and length of the path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_create(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.create_file(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    })?
+}
+
+/// Creates an empty directory.
+///
+/// This system call takes two parameters: the address and length of the path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_mkdir(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.create_directory(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    })?
+}
+
+/// Removes a file.
+///
+/// This system call takes two parameters: the address and length of the path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_unlink(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.remove_file(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    })?
+}
+
+/// Removes an empty directory.
+///
+/// This system call takes two parameters: the address and length of the path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_rmdir(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.remove_directory(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    })?
+}
+
+/// Moves an entry to a new path on the same mount.
+///
+/// This system call takes four parameters: the address and length of the
+/// current path, followed by the address and length of the new path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_rename(tf: &mut TrapFrame) -> OsResult<()> {
+    let from = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+    let to = UserSlice::new(tf.xs[2], tf.xs[3] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let from = from.to_vec(&process.vmap.lock())?;
+        let to = to.to_vec(&process.vmap.lock())?;
+        process.rename(String::from_utf8_lossy(from.as_slice()).to_string(),
+                       String::from_utf8_lossy(to.as_slice()).to_string())
+    })?
+}
+
+/// Changes the current directory of the calling process.
+///
+/// This system call takes two parameters: the address and length of the path.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_chdir(tf: &mut TrapFrame) -> OsResult<()> {
+    let path = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let buffer = path.to_vec(&process.vmap.lock())?;
+        process.change_directory(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    })?
+}
+
+/// Returns the current directory of the calling process.
+///
+/// This system call takes two parameters: the address and length of a buffer
+/// to write the absolute path into. Fails with `InvalidArgument` if the path
+/// does not fit.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the path in bytes.
+fn sys_getcwd(tf: &mut TrapFrame) -> OsResult<()> {
+    let user_buffer = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+
+    tf.xs[0] = SCHEDULER.on_process(tf, |process| -> OsResult<u64> {
+        let path = process.current_directory().to_string();
+        user_buffer.copy_from(&process.vmap.lock(), path.as_bytes())?;
+        Ok(path.len() as u64)
+    })??;
+
+    Ok(())
+}
+
+/// Sends a signal to a process.
+///
+/// This system call takes two parameters: the id of the process and the
+/// signal number. Fails with `NoEntry` if there is no such process.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_kill(tf: &mut TrapFrame) -> OsResult<()> {
+    let pid = tf.xs[0];
+    let signal = Signal::from_u64(tf.xs[1]).ok_or(OsError::InvalidArgument)?;
+    SCHEDULER.signal(pid, signal)
+}
+
+/// Sets how the calling process handles a signal.
+///
+/// This system call takes three parameters: the signal number, the handler
+/// (`SIGNAL_DEFAULT`, `SIGNAL_IGNORE` or the address of a function) and the
+/// address handlers return to, which must call sigreturn.
+///
+/// This system call does not return any value beyond the usual status value.
+fn sys_sigaction(tf: &mut TrapFrame) -> OsResult<()> {
+    let signal = Signal::from_u64(tf.xs[0]).ok_or(OsError::InvalidArgument)?;
+    let handler = tf.xs[1];
+    let restorer = tf.xs[2];
+
+    SCHEDULER.on_process(tf, |process| {
+        process.signals.set_action(signal, handler, restorer)
+    })?
+}
+
+/// Changes the set of signals blocked by the calling process.
+///
+/// This system call takes two parameters: `SIGNAL_BLOCK`, `SIGNAL_UNBLOCK` or
+/// `SIGNAL_SET_MASK`, and a signal mask.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the previously blocked signals.
+fn sys_sigprocmask(tf: &mut TrapFrame) -> OsResult<()> {
+    let how = tf.xs[0];
+    let mask = tf.xs[1];
+
+    tf.xs[0] = SCHEDULER.on_process(tf, |process| {
+        process.signals.set_blocked(how, mask)
+    })??;
+
+    Ok(())
+}
+
+/// Returns from a signal handler to the context it interrupted.
+///
+/// This system call takes no parameters. On success it does not return to
+/// the caller; every register is restored, including the status register.
+fn sys_sigreturn(tf: &mut TrapFrame) -> OsResult<()> {
+    SCHEDULER.on_process(tf, |process| {
+        process.signals.restore(&mut process.context)
+    })?
+}
+
+/// Blocks until the `u32` at an address stops holding an expected value and
+/// a `FutexWake` on the same address wakes the caller.
+///
+/// This system call takes three parameters: the four byte aligned address,
+/// the expected value and a timeout in milliseconds, or `NO_TIMEOUT`.
+///
+/// It fails with `WouldBlock` without blocking if the value is not the
+/// expected one, with `IoErrorTimedOut` if the timeout passes and with
+/// `Interrupted` if a signal arrives.
+fn sys_futex_wait(tf: &mut TrapFrame) -> OsResult<()> {
+    let address = tf.xs[0];
+    let expected = tf.xs[1] as u32;
+    let deadline = match tf.xs[2] {
+        NO_TIMEOUT => None,
+        ms => Some(timer::current_time() + Duration::from_millis(ms)),
+    };
+
+    SCHEDULER.futex_wait(tf, address, expected, deadline)?;
+    blocked_status(tf)
+}
+
+/// Wakes processes blocked in `FutexWait` on an address.
+///
+/// This system call takes two parameters: the four byte aligned address and
+/// the most processes to wake.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of processes woken.
+fn sys_futex_wake(tf: &mut TrapFrame) -> OsResult<()> {
+    let address = tf.xs[0];
+    let count = tf.xs[1];
+
+    tf.xs[0] = SCHEDULER.futex_wake(tf, address, count)?;
+    Ok(())
+}
+
+/// Waits for any of a set of resources to become ready.
+///
+/// This system call takes three parameters: the address and length of an
+/// array of `PollDescriptor`s, and a timeout in milliseconds, or `NO_TIMEOUT`.
+/// The `ready` field of every descriptor is filled in on return.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of descriptors that are ready, which is zero if the
+/// timeout passed first.
+fn sys_poll(tf: &mut TrapFrame) -> OsResult<()> {
+    let address = tf.xs[0];
+    let count = tf.xs[1] as usize;
+    let deadline = match tf.xs[2] {
+        NO_TIMEOUT => None,
+        ms => Some(timer::current_time() + Duration::from_millis(ms)),
+    };
+    let expired = move || deadline.map_or(false, |deadline| timer::current_time() >= deadline);
+
+    let length = count.checked_mul(size_of::<PollDescriptor>()).ok_or(OsError::InvalidArgument)?;
+    let user_descriptors = UserSlice::new(address, length);
+    let mut descriptors = SCHEDULER.on_process(tf, |process| -> OsResult<Vec<PollDescriptor>> {
+        let vmap = process.vmap.lock();
+        user_descriptors.validate_writable(&vmap)?;
+        (0..count).map(|i| poll_descriptor(address, i).read(&vmap)).collect()
+    })??;
+
+    let ready = SCHEDULER.on_process(tf, |process| {
+        poll_resources(process, address, &mut descriptors, expired())
+    })??;
+    if let Some(ready) = ready {
+        tf.xs[0] = ready as u64;
+        return Ok(());
+    }
+
+    let channels = SCHEDULER.on_process(tf, |process| -> Vec<_> {
+        descriptors.iter()
+            .filter_map(|descriptor| process.wait_channel(ResourceId::from(descriptor.descriptor)))
+            .collect()
+    })?;
+
+    SCHEDULER.block(&channels, deadline, Box::new(move |process| {
+        let result = match poll_resources(process, address, &mut descriptors, expired()) {
+            Ok(None) => return false,
+            Ok(Some(ready)) => {
+                process.context.xs[0] = ready as u64;
+                OsError::Ok
+            }
+            Err(err) => err,
+        };
+        process.context.xs[7] = result as u64;
+        true
+    }), tf);
+
+    blocked_status(tf)
+}
+
+/// Polls `descriptors` on behalf of `process`. Once any are ready, or `expired`
+/// is set, copies them back to the array at `address` and returns how many are
+/// ready. Returns `None` to keep waiting.
+fn poll_resources(process: &mut Process, address: u64, descriptors: &mut [PollDescriptor],
+                  expired: bool) -> OsResult<Option<usize>> {
+    let ready = process.poll(descriptors)?;
+    if ready == 0 && !expired {
+        return Ok(None);
+    }
+
+    let vmap = process.vmap.lock();
+    for (i, descriptor) in descriptors.iter().enumerate() {
+        poll_descriptor(address, i).write(&vmap, descriptor)?;
+    }
+    Ok(Some(ready))
+}
+
+/// The `i`th entry of the `PollDescriptor` array at `address`.
+fn poll_descriptor(address: u64, i: usize) -> UserPtr<PollDescriptor> {
+    UserPtr::new(address + (i * size_of::<PollDescriptor>()) as u64)
+}
+
+/// Reads entries from an open directory.
+///
+/// This system call takes three parameters: the resource id of the directory
+/// and the address and length of a buffer to encode entries into.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written, which is zero once every entry has
+/// been read.
+fn sys_readdir(tf: &mut TrapFrame) -> OsResult<()> {
+    let descriptor = ResourceId::from(tf.xs[0]);
+    let user_buffer = UserSlice::new(tf.xs[1], tf.xs[2] as usize);
+
+    let amount_read = SCHEDULER.on_process(tf, |process| -> OsResult<usize> {
+        user_buffer.validate_writable(&process.vmap.lock())?;
+        let mut buffer = vec![0u8; user_buffer.len()];
+        let amount_read = process.read_directory(descriptor, buffer.as_mut_slice())?;
+        user_buffer.copy_from(&process.vmap.lock(), &buffer[0..amount_read])?;
+        Ok(amount_read)
+    })??;
+
+    tf.xs[0] = amount_read as u64;
+
+    Ok(())
+}
+
+/// Moves the cursor of an open resource.
+///
+/// This system call takes three parameters: the resource id, where to seek
+/// from (0 for the start, 1 for the current position, 2 for the end), and the
+/// offset from that point.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new position from the start of the resource.
+fn sys_seek(tf: &mut TrapFrame) -> OsResult<()> {
+    let descriptor = ResourceId::from(tf.xs[0]);
+    let position = match tf.xs[1] {
+        0 => SeekFrom::Start(tf.xs[2]),
+        1 => SeekFrom::Current(tf.xs[2] as i64),
+        2 => SeekFrom::End(tf.xs[2] as i64),
+        _ => return Err(OsError::InvalidArgument),
+    };
+
+    tf.xs[0] = SCHEDULER.on_process(tf, |process| {
+        process.seek(descriptor, position)
+    })??;
+
+    Ok(())
 }
 
 fn sys_fork(tf: &mut TrapFrame) -> OsResult<()> {
@@ -189,82 +754,136 @@ fn sys_fork(tf: &mut TrapFrame) -> OsResult<()> {
 }
 
 fn sys_execute(tf: &mut TrapFrame) -> OsResult<()> {
-    let mut arguments = vec![0u8; tf.xs[1] as usize];
-    let mut environment = vec![0u8; tf.xs[3] as usize];
+    let arguments = UserSlice::new(tf.xs[0], tf.xs[1] as usize);
+    let environment = UserSlice::new(tf.xs[2], tf.xs[3] as usize);
 
-    copy_from_userspace(tf, tf.xs[0], arguments.as_mut_slice())?;
-    copy_from_userspace(tf, tf.xs[2], environment.as_mut_slice())?;
-
-    SCHEDULER.on_process(tf, |process|
-        process.execute(arguments.as_slice(), environment.as_slice()))??;
+    SCHEDULER.on_process(tf, |process| -> OsResult<()> {
+        let arguments = arguments.to_vec(&process.vmap.lock())?;
+        let environment = environment.to_vec(&process.vmap.lock())?;
+        process.execute(arguments.as_slice(), environment.as_slice())
+    })??;
     Ok(())
 }
 
+/// Waits for a child process to exit.
+///
+/// This system call takes three parameters: the id of the child to wait for,
+/// or `WAIT_ANY` for any child, the wait options, and the address of a `Usage`
+/// to fill in with what the child used, or 0. With `WAIT_NO_HANG` the call
+/// returns immediately if no matching child has exited yet. Fails with
+/// `NoEntry` if the caller has no matching child. A child stays a zombie until
+/// it is waited for, and the children of a process that exits are reparented
+/// to init.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters: the id of the child, its exit status, and `1` if a child was
+/// collected or `0` if none had exited.
 fn sys_wait(tf: &mut TrapFrame) -> OsResult<()> {
-    SCHEDULER.switch(State::Waiting(Box::new(|process| {
-        if let Some(id) = process.dead_children.pop() {
-            process.context.xs[0] = id;
-            true
-        } else {
-            false
-        }
-    })), tf);
+    let pid = tf.xs[0];
+    let options = tf.xs[1];
+    let usage = tf.xs[2];
+
+    if SCHEDULER.wait(tf, pid, options & WAIT_NO_HANG != 0, usage)? {
+        return blocked_status(tf);
+    }
     Ok(())
 }
 
-//TODO: make the functions work across page boundaries
-fn copy_from_userspace(_: &TrapFrame, ptr: u64, buf: &mut [u8]) -> OsResult<()> {
-    let virtual_address = VirtualAddr::from(ptr);
-
-    if virtual_address.offset() as usize + buf.len() > PAGE_SIZE {
-        Err(BadAddress)
-    } else {
-        buf.copy_from_slice(unsafe { core::slice::from_raw_parts(ptr as *const u8, buf.len()) });
-        Ok(())
-    }
+/// Carries out the `TraceRequest` in `x0` on the process `x1`, with an address
+/// in `x2` and a word of data in `x3` for the requests that take them. The word
+/// read by `TraceRequest::Read` is returned in `x0`.
+fn sys_trace(tf: &mut TrapFrame) -> OsResult<()> {
+    let request = TraceRequest::from_u64(tf.xs[0]).ok_or(OsError::InvalidArgument)?;
+    tf.xs[0] = SCHEDULER.trace(tf, request, tf.xs[1], tf.xs[2], tf.xs[3])?;
+    Ok(())
 }
 
-fn copy_into_userspace(_: &TrapFrame, ptr: u64, buf: &[u8]) -> OsResult<()> {
-    let virtual_address = VirtualAddr::from(ptr);
-    if virtual_address.offset() as usize + buf.len() > PAGE_SIZE {
-        return Err(BadAddress);
+/// Blocks until the traced process `x0`, or any traced process if it is
+/// `WAIT_ANY`, stops or exits. Returns its id in `x0` and the `TraceEvent`
+/// encoded with `TraceEvent::to_raw` in `x1` and `x2`.
+fn sys_trace_wait(tf: &mut TrapFrame) -> OsResult<()> {
+    let pid = tf.xs[0];
+
+    match SCHEDULER.on_process(tf, |process| process.tracing.take(pid))?? {
+        Some((id, event)) => {
+            let (kind, detail) = event.to_raw();
+            tf.xs[0] = id;
+            tf.xs[1] = kind;
+            tf.xs[2] = detail;
+        }
+        None => {
+            SCHEDULER.block(&[], None, Box::new(move |process| {
+                let result = match process.tracing.take(pid) {
+                    Ok(None) => return false,
+                    Ok(Some((id, event))) => {
+                        let (kind, detail) = event.to_raw();
+                        process.context.xs[0] = id;
+                        process.context.xs[1] = kind;
+                        process.context.xs[2] = detail;
+                        OsError::Ok
+                    }
+                    Err(err) => err,
+                };
+                process.context.xs[7] = result as u64;
+                true
+            }), tf);
+            return blocked_status(tf);
+        }
     }
 
-    if virtual_address.offset() as usize + buf.len() > PAGE_SIZE {
-        Err(BadAddress)
-    } else {
-        let user_slice = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, buf.len()) };
-        user_slice.copy_from_slice(buf);
-        Ok(())
-    }
+    Ok(())
+}
+
+macro_rules! define_dispatch {
+    ($($variant:ident = $number:literal: fn $stub:ident($($argument:ident: $type:ty),*) -> $returned:ty
+        => $handler:ident;)*) => {
+        fn syscall_to_function(call: Syscall) -> fn(tf: &mut TrapFrame) -> OsResult<()> {
+            match call {
+                $(Syscall::$variant => $handler,)*
+                Syscall::Unknown => |_| Err(OsError::Unknown)
+            }
+        }
+    };
 }
 
-fn syscall_to_function(call: Syscall) -> fn(tf: &mut TrapFrame) -> OsResult<()> {
-    match call {
-        Syscall::Open => sys_open,
-        Syscall::Close => sys_close,
-        Syscall::Read => sys_read,
-        Syscall::Write => sys_write,
-        Syscall::Pipe => sys_pipe,
-        Syscall::Duplicate => sys_duplicate,
-        Syscall::Seek => sys_seek,
-        Syscall::Fork => sys_fork,
-        Syscall::Execute => sys_execute,
-        Syscall::Exit => sys_exit,
-        Syscall::Wait => sys_wait,
-        Syscall::GetPid => sys_getpid,
-        Syscall::Sbrk => sys_sbrk,
-        Syscall::Sleep => sys_sleep,
-        Syscall::Time => sys_time,
-        Syscall::Unknown => |_| Err(OsError::Unknown)
+syscall_table!(define_dispatch);
+
+/// Returns the status a blocking system call completed with, which is stored
+/// in `x7` by whatever woke the process. Once `tf` has been switched back to
+/// the caller, this keeps `handle_syscall` from overwriting that status.
+fn blocked_status(tf: &TrapFrame) -> OsResult<()> {
+    match OsError::from(tf.xs[7]) {
+        OsError::Ok => Ok(()),
+        err => Err(err),
     }
 }
 
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
+    // A traced process may have to report the call to its tracer first. It
+    // then makes the call when it is resumed.
+    if SCHEDULER.on_process(tf, |process| process.enter_syscall(num)).unwrap_or(false) {
+        return;
+    }
+
     let call = Syscall::from(num);
-    let result = syscall_to_function(call)(tf);
-    tf.xs[7] = match result {
-        Ok(_) => 1,
-        Err(err) => err as u64
+    let caller = tf.tpidr;
+    tracepoint::record(Tracepoint::SyscallEntry { pid: caller, number: num });
+    let status = match syscall_to_function(call)(tf) {
+        Ok(()) => OsError::Ok,
+        Err(err) => err,
+    };
+    tracepoint::record(Tracepoint::SyscallExit { pid: caller, number: num, status: status as u64 });
+
+    // A call that blocked or exited has switched `tf` to another process.
+    // Blocking calls store their own status when they complete.
+    if tf.tpidr != caller {
+        return;
     }
+
+    // A successful sigreturn has restored every register of the caller.
+    if call == Syscall::SignalReturn && status == OsError::Ok {
+        return;
+    }
+
+    tf.xs[7] = status as u64;
 }
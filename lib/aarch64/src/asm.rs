@@ -30,6 +30,14 @@ pub fn isb() {
     unsafe { asm!("isb") };
 }
 
+/// Makes an instruction written through the data side at `address` visible
+/// to instruction fetch: cleans its data cache line to the point of
+/// unification and invalidates the instruction caches of every core.
+#[inline(always)]
+pub unsafe fn sync_instruction(address: u64) {
+    asm!("dc cvau, {a}", "dsb ish", "ic ialluis", "dsb ish", "isb", a = in(reg) address);
+}
+
 /// Set Event
 #[inline(always)]
 pub fn sev() {
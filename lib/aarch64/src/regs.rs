@@ -177,6 +177,19 @@ defreg!(
     ]
 );
 
+// (ref: D7.3.5 Monitor Debug System Control Register)
+defreg!(
+    MDSCR_EL1,
+    [
+        MDE[15 - 15], // Monitor debug events
+        KDE[13 - 13], // Local (kernel) debug enable
+        SS[00 - 00],  // Software step control
+    ]
+);
+
+// (ref: D7.3.18 OS Lock Access Register)
+defreg!(OSLAR_EL1, [OSLK[00 - 00],]);
+
 // (ref: D7.2.29-31 Fault Address Register)
 defreg!(FAR_EL1);
 defreg!(FAR_EL2);
@@ -61,6 +61,8 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -1,6 +1,7 @@
 extern crate rand;
 
-use alloc::string::ToString;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
 
 use mbr::{CHS, MasterBootRecord, PartitionEntry};
 use std::fmt::{self, Debug};
@@ -9,11 +10,16 @@ use std::io::Cursor;
 use std::io::prelude::*;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
+use vfat::{BiosParameterBlock, HandleReference, VFat, VFatHandle};
+
+use filesystem::fs2::{DirEntry2, Directory2, EntryKind, FileSystem2};
+use filesystem::path::Path as VfsPath;
 
 use crate::mbr;
-use crate::traits::*;
+use filesystem::{BlockDevice, Dir, Entry, File, FileSystem, Metadata, Timestamp};
 use crate::vfat;
+use crate::vfat::{Cluster, Status};
+use crate::vfat::vfat::Chain;
 
 #[derive(Clone)]
 struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
@@ -53,8 +59,8 @@ match $e {
 
 macro resource($name:expr) {{
     let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../ext/fat32-imgs/", $name);
-    match ::std::fs::File::open(path) {
-        Ok(file) => file,
+    match ::std::fs::read(path) {
+        Ok(data) => Cursor::new(data),
         Err(e) => {
             eprintln!(
                 "\nfailed to find assignment 2 resource '{}': {}\n\
@@ -182,6 +188,13 @@ fn test_vfat_init() {
     vfat_from_resource!("mock4.fat32.img");
 }
 
+/// Opens the directory at `path` of the file system `vfat`.
+fn open_dir<P: AsRef<Path>>(vfat: &StdVFatHandle, path: P) -> io::Result<vfat::Dir<StdVFatHandle>> {
+    let path = filesystem::path::Path::new(path.as_ref().to_str().expect("UTF-8 path"))?;
+    FileSystem::open(&mut HandleReference(vfat), &path)?
+        .into_dir().ok_or(io::Error::from(io::ErrorKind::NotADirectory))
+}
+
 fn hash_entry<T: Entry>(hash: &mut String, entry: &T) -> ::std::fmt::Result {
     use std::fmt::Write;
 
@@ -221,7 +234,7 @@ fn hash_entry<T: Entry>(hash: &mut String, entry: &T) -> ::std::fmt::Result {
     Ok(())
 }
 
-fn hash_dir<T: Dir>(hash: &mut String, dir: T) -> Result<Vec<T::Entry>, ::std::fmt::Error> {
+fn hash_dir<T: Dir>(hash: &mut String, mut dir: T) -> Result<Vec<T::Entry>, ::std::fmt::Error> {
     let mut entries: Vec<_> = dir.entries().expect("entries interator").collect();
 
     entries.sort_by(|a, b| a.name().cmp(b.name()));
@@ -237,7 +250,7 @@ fn hash_dir<T: Dir>(hash: &mut String, dir: T) -> Result<Vec<T::Entry>, ::std::f
 
 fn hash_dir_from<P: AsRef<Path>>(vfat: StdVFatHandle, path: P) -> String {
     let mut hash = String::new();
-    hash_dir(&mut hash, vfat.open_dir(path).expect("directory exists")).unwrap();
+    hash_dir(&mut hash, open_dir(&vfat, path).expect("directory exists")).unwrap();
     hash
 }
 
@@ -264,7 +277,7 @@ fn hash_dir_recursive<P: AsRef<Path>>(
     use std::fmt::Write;
 
     let path = path.as_ref();
-    let dir = vfat.open_dir(path).expect("directory");
+    let dir = open_dir(&vfat, path).expect("directory");
 
     write!(hash, "{}\n", path.display())?;
     let entries = hash_dir(hash, dir)?;
@@ -304,13 +317,13 @@ fn test_all_dir_entries() {
 }
 
 fn hash_file<T: File>(hash: &mut String, mut file: T) -> ::std::fmt::Result {
-    use crate::tests::rand::distributions::{Range, Sample};
+    use rand::distributions::{Distribution, Uniform};
     use std::collections::hash_map::DefaultHasher;
     use std::fmt::Write;
     use std::hash::Hasher;
 
     let mut rng = rand::thread_rng();
-    let mut range = Range::new(128, 8192);
+    let range = Uniform::new(128, 8192);
     let mut hasher = DefaultHasher::new();
 
     let mut bytes_read = 0;
@@ -343,8 +356,7 @@ fn hash_files_recursive<P: AsRef<Path>>(
     path: P,
 ) -> ::std::fmt::Result {
     let path = path.as_ref();
-    let mut entries = vfat
-        .open_dir(path)
+    let mut entries = open_dir(&vfat, path)
         .expect("directory")
         .entries()
         .expect("entries interator")
@@ -465,6 +477,10 @@ impl<T: BlockDevice> BlockDevice for Shuffle<T> {
         };
         self.device.write_sector(n, buf)
     }
+
+    fn flush_sector(&mut self, n: u64) -> io::Result<()> {
+        self.device.flush_sector(n)
+    }
 }
 
 #[test]
@@ -475,3 +491,296 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+/// Returns an empty FAT32 image of 512-byte sectors and clusters: a master
+/// boot record, then a partition with one FAT and an empty root directory.
+fn blank_image() -> Cursor<Vec<u8>> {
+    const SECTORS: u32 = 1024;
+    let mut data = vec![0u8; SECTORS as usize * 512];
+    let mut write = |offset: usize, bytes: &[u8]| {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+
+    // The partition table entry: a FAT32 (LBA) partition from sector 1.
+    write(446 + 4, &[0x0C]);
+    write(446 + 8, &1u32.to_le_bytes());
+    write(446 + 12, &(SECTORS - 1).to_le_bytes());
+    write(510, &[0x55, 0xAA]);
+
+    // The EBPB: 2 reserved sectors, one FAT of 8 sectors, root at cluster 2.
+    write(512 + 11, &512u16.to_le_bytes());
+    write(512 + 13, &[1]);
+    write(512 + 14, &2u16.to_le_bytes());
+    write(512 + 16, &[1]);
+    write(512 + 36, &8u32.to_le_bytes());
+    write(512 + 44, &2u32.to_le_bytes());
+    write(512 + 510, &[0x55, 0xAA]);
+
+    // The FAT: the two reserved entries, then the root directory's cluster.
+    write(3 * 512, &0x0FFF_FFF8u32.to_le_bytes());
+    write(3 * 512 + 4, &0x0FFF_FFFFu32.to_le_bytes());
+    write(3 * 512 + 8, &0x0FFF_FFF8u32.to_le_bytes());
+
+    Cursor::new(data)
+}
+
+/// Runs `test` against a blank image and each of the mock images.
+fn for_each_image(test: impl Fn(StdVFatHandle)) {
+    test(VFat::<StdVFatHandle>::from(blank_image()).expect("failed to initialize VFAT from blank image"));
+    test(vfat_from_resource!("mock1.fat32.img"));
+    test(vfat_from_resource!("mock2.fat32.img"));
+    test(vfat_from_resource!("mock3.fat32.img"));
+    test(vfat_from_resource!("mock4.fat32.img"));
+}
+
+fn vfs_path(path: &str) -> VfsPath {
+    VfsPath::new(path).expect("valid path")
+}
+
+fn root_of(vfat: &StdVFatHandle) -> Box<dyn Directory2> {
+    HandleReference(vfat).root().expect("root directory")
+}
+
+fn directory(vfat: &StdVFatHandle, path: &str) -> Box<dyn Directory2> {
+    FileSystem2::open(&mut HandleReference(vfat), &vfs_path(path))
+        .expect("entry exists")
+        .into_directory()
+        .expect("entry is a directory")
+}
+
+fn names(directory: &mut dyn Directory2) -> Vec<String> {
+    let mut names: Vec<String> = directory.list().expect("listing")
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    names
+}
+
+fn cluster_of(vfat: &StdVFatHandle, path: &str) -> Cluster {
+    match FileSystem::open(&mut HandleReference(vfat), &vfs_path(path)).expect("entry exists") {
+        vfat::Entry::File(file) => file.chain.first_cluster(),
+        vfat::Entry::Dir(dir) => dir.chain.first_cluster(),
+    }
+}
+
+/// Returns the clusters the `.` and `..` entries of the directory at `path`
+/// refer to.
+fn dot_clusters(vfat: &StdVFatHandle, path: &str) -> (Cluster, Cluster) {
+    let mut dir = open_dir(vfat, path).expect("directory exists");
+    let mut cluster = |name| match dir.find(name).expect("dot entry") {
+        vfat::Entry::Dir(dot) => dot.chain.first_cluster(),
+        vfat::Entry::File(_) => panic!("{} is not a directory", name),
+    };
+    (cluster("."), cluster(".."))
+}
+
+/// Returns the raw records of the directory at `path`.
+fn records(vfat: &StdVFatHandle, path: &str) -> Vec<u8> {
+    let mut dir = open_dir(vfat, path).expect("directory exists");
+    let mut bytes = Vec::new();
+    dir.chain.seek(io::SeekFrom::Start(0)).expect("seek");
+    dir.chain.read_to_end(&mut bytes).expect("records");
+    bytes
+}
+
+fn is_free(vfat: &StdVFatHandle, cluster: Cluster) -> bool {
+    vfat.lock(|vfat| vfat.fat_entry(cluster).expect("FAT entry").is_free())
+}
+
+fn kind<T>(result: io::Result<T>) -> io::ErrorKind {
+    match result {
+        Ok(_) => panic!("expected an error"),
+        Err(e) => e.kind(),
+    }
+}
+
+#[test]
+fn test_create_and_list() {
+    for_each_image(|vfat| {
+        let mut root = root_of(&vfat);
+        root.create_file("round trip.txt").unwrap();
+        root.create_directory("trip").unwrap();
+
+        let listed = root.list().unwrap();
+        assert!(listed.contains(&DirEntry2::new("round trip.txt", EntryKind::File)));
+        assert!(listed.contains(&DirEntry2::new("trip", EntryKind::Directory)));
+        assert_eq!(kind(root.create_file("TRIP")), io::ErrorKind::AlreadyExists);
+        assert_eq!(kind(root.create_directory("Round Trip.txt")), io::ErrorKind::AlreadyExists);
+
+        let mut trip = directory(&vfat, "/trip");
+        assert_eq!(names(&mut *trip), vec![".", ".."]);
+        trip.create_directory("inner").unwrap();
+        trip.create_file("leaf").unwrap();
+        assert_eq!(names(&mut *trip), vec![".", "..", "inner", "leaf"]);
+
+        // `..` of a directory in the root refers to cluster 0.
+        let trip_cluster = cluster_of(&vfat, "/trip");
+        assert_eq!(dot_clusters(&vfat, "/trip"), (trip_cluster, Cluster::from(0)));
+        let inner_cluster = cluster_of(&vfat, "/trip/inner");
+        assert_eq!(dot_clusters(&vfat, "/trip/inner"), (inner_cluster, trip_cluster));
+    });
+}
+
+#[test]
+fn test_rename() {
+    for_each_image(|vfat| {
+        let mut fs = HandleReference(&vfat);
+        let mut root = root_of(&vfat);
+        root.create_file("before").unwrap();
+        root.create_directory("from").unwrap();
+        root.create_directory("to").unwrap();
+        directory(&vfat, "/from").create_file("carried").unwrap();
+
+        // Within a directory, the entry keeps its clusters.
+        let cluster = cluster_of(&vfat, "/before");
+        fs.move_entry(&vfs_path("/before"), &vfs_path("/after")).unwrap();
+        let listed = names(&mut *root);
+        assert!(!listed.contains(&"before".to_string()));
+        assert!(listed.contains(&"after".to_string()));
+        assert_eq!(cluster_of(&vfat, "/after"), cluster);
+
+        fs.move_entry(&vfs_path("/after"), &vfs_path("/AFTER")).unwrap();
+        let listed = names(&mut *root);
+        assert!(listed.contains(&"AFTER".to_string()));
+        assert!(!listed.contains(&"after".to_string()));
+
+        // Across directories, a moved directory's `..` follows it.
+        fs.move_entry(&vfs_path("/from"), &vfs_path("/to/moved")).unwrap();
+        assert!(!names(&mut *root).contains(&"from".to_string()));
+        assert_eq!(names(&mut *directory(&vfat, "/to")), vec![".", "..", "moved"]);
+        assert_eq!(names(&mut *directory(&vfat, "/to/moved")), vec![".", "..", "carried"]);
+        let moved_cluster = cluster_of(&vfat, "/to/moved");
+        assert_eq!(dot_clusters(&vfat, "/to/moved"), (moved_cluster, cluster_of(&vfat, "/to")));
+
+        fs.move_entry(&vfs_path("/to/moved/carried"), &vfs_path("/carried")).unwrap();
+        assert!(names(&mut *root).contains(&"carried".to_string()));
+        assert_eq!(names(&mut *directory(&vfat, "/to/moved")), vec![".", ".."]);
+
+        assert_eq!(kind(fs.move_entry(&vfs_path("/carried"), &vfs_path("/AFTER"))),
+                   io::ErrorKind::AlreadyExists);
+        assert_eq!(kind(fs.move_entry(&vfs_path("/to"), &vfs_path("/to/moved/to"))),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(kind(fs.move_entry(&vfs_path("/missing"), &vfs_path("/found"))),
+                   io::ErrorKind::NotFound);
+    });
+}
+
+#[test]
+fn test_remove_directory() {
+    for_each_image(|vfat| {
+        let mut root = root_of(&vfat);
+        root.create_directory("full").unwrap();
+        let mut full = directory(&vfat, "/full");
+        full.create_file("file").unwrap();
+        let cluster = cluster_of(&vfat, "/full");
+
+        assert_eq!(kind(root.remove("full")), io::ErrorKind::DirectoryNotEmpty);
+        assert_eq!(kind(full.remove(".")), io::ErrorKind::InvalidFilename);
+        assert_eq!(kind(full.remove("..")), io::ErrorKind::InvalidFilename);
+        full.remove("file").unwrap();
+        assert_eq!(names(&mut *full), vec![".", ".."]);
+
+        root.remove("full").unwrap();
+        assert!(!names(&mut *root).contains(&"full".to_string()));
+        assert_eq!(kind(root.open_entry("full")), io::ErrorKind::NotFound);
+        assert_eq!(kind(root.remove("full")), io::ErrorKind::NotFound);
+        assert!(is_free(&vfat, cluster));
+
+        // A new directory starts empty even if it reuses the cluster.
+        root.create_directory("fresh").unwrap();
+        assert_eq!(names(&mut *directory(&vfat, "/fresh")), vec![".", ".."]);
+    });
+}
+
+#[test]
+fn test_remove_marks_records_deleted() {
+    for_each_image(|vfat| {
+        let mut root = root_of(&vfat);
+        root.create_file("gone").unwrap();
+        let before = records(&vfat, "/");
+        let offset = before.chunks(32)
+            .rposition(|record| &record[..11] == b"GONE       ")
+            .expect("short name record") * 32;
+
+        root.remove("gone").unwrap();
+        let after = records(&vfat, "/");
+        // The record and its long file name record are marked deleted in place.
+        assert_eq!(after[offset], 0xE5);
+        assert_eq!(after[offset - 32], 0xE5);
+        assert_eq!(&after[offset + 1..offset + 32], &before[offset + 1..offset + 32]);
+        assert!(!names(&mut *root).contains(&"gone".to_string()));
+
+        root.create_file("gone").unwrap();
+        assert!(names(&mut *root).contains(&"gone".to_string()));
+        root.remove("gone").unwrap();
+        assert!(!names(&mut *root).contains(&"gone".to_string()));
+    });
+}
+
+#[test]
+fn test_remove_open_file() {
+    for_each_image(|vfat| {
+        let mut root = root_of(&vfat);
+        root.create_file("open").unwrap();
+        let cluster = cluster_of(&vfat, "/open");
+
+        // Closing a descriptor on a file that was not removed frees nothing.
+        drop(root.open_entry("open").unwrap());
+        assert!(!is_free(&vfat, cluster));
+
+        let mut file = root.open_entry("open").unwrap().into_file().unwrap();
+        file.write_all(b"still here").unwrap();
+        let mut duplicate = file.duplicate().unwrap();
+
+        root.remove("open").unwrap();
+        assert!(!names(&mut *root).contains(&"open".to_string()));
+        assert!(!is_free(&vfat, cluster));
+        root.create_file("other").unwrap();
+        assert_ne!(cluster_of(&vfat, "/other"), cluster);
+
+        drop(file);
+        assert!(!is_free(&vfat, cluster));
+        let mut contents = [0u8; 10];
+        duplicate.seek(io::SeekFrom::Start(0)).unwrap();
+        duplicate.read_exact(&mut contents).unwrap();
+        assert_eq!(&contents, b"still here");
+
+        drop(duplicate);
+        assert!(is_free(&vfat, cluster));
+    });
+}
+
+#[test]
+fn test_chain_write_across_clusters() {
+    let vfat = VFat::<StdVFatHandle>::from(blank_image()).expect("failed to initialize VFAT from blank image");
+    let (first, second) = vfat.lock(|vfat| {
+        let first = vfat.next_free_cluster().unwrap();
+        vfat.update_fat_entry(first, Status::new_eoc()).unwrap();
+        let second = vfat.next_free_cluster().unwrap();
+        vfat.update_fat_entry(second, Status::new_eoc()).unwrap();
+        vfat.update_fat_entry(first, Status::Data(second)).unwrap();
+        (first, second)
+    });
+    let bytes_per_cluster = vfat.lock(|vfat| vfat.bytes_per_cluster());
+    let mut chain = Chain::new_from_cluster(vfat.clone(), first).unwrap();
+
+    let data: Vec<u8> = (1..=40).collect();
+    chain.seek(io::SeekFrom::Start(bytes_per_cluster as u64 - 10)).unwrap();
+    chain.write_all(&data).unwrap();
+    assert_eq!(chain.position(), bytes_per_cluster as u64 + 30);
+
+    let mut tail = [0u8; 30];
+    vfat.lock(|vfat| vfat.read_cluster(second, 0, &mut tail)).unwrap();
+    assert_eq!(&tail[..], &data[10..]);
+
+    let mut bytes = Vec::new();
+    chain.seek(io::SeekFrom::Start(0)).unwrap();
+    chain.read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 2 * bytes_per_cluster);
+    assert_eq!(&bytes[bytes_per_cluster - 10..bytes_per_cluster + 30], &data[..]);
+
+    // A write stops at the end of the chain.
+    chain.seek(io::SeekFrom::Start(0)).unwrap();
+    assert_eq!(chain.write(&vec![0xAA; 3 * bytes_per_cluster]).unwrap(), 2 * bytes_per_cluster);
+}
@@ -1,11 +1,14 @@
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::Range;
 
 use log::info;
 
 use filesystem;
 
-use shim::const_assert_size;
+use shim::{const_assert_size, ioerr};
 use shim::io;
 use shim::io::{Read, Seek, SeekFrom, Write};
 
@@ -50,6 +53,31 @@ pub struct VFatRegularDirEntry {
 
 const_assert_size!(VFatRegularDirEntry, 32);
 
+impl VFatRegularDirEntry {
+    fn new(name: [u8; 8], attributes: u8, cluster: Cluster, file_size: u32) -> Self {
+        let cluster: u32 = cluster.into();
+        VFatRegularDirEntry {
+            name,
+            extension: [b' '; 3],
+            attributes,
+            __nt_reserved: 0,
+            created_time_tenth: 0,
+            created_time: Default::default(),
+            last_access: Default::default(),
+            first_cluster_high: (cluster >> 16) as u16,
+            last_modification: Default::default(),
+            first_cluster_low: (cluster & 0xFFFF) as u16,
+            file_size,
+        }
+    }
+
+    fn set_cluster(&mut self, cluster: Cluster) {
+        let cluster: u32 = cluster.into();
+        self.first_cluster_high = (cluster >> 16) as u16;
+        self.first_cluster_low = (cluster & 0xFFFF) as u16;
+    }
+}
+
 //FIXME: use u16?
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
@@ -65,7 +93,9 @@ pub struct VFatLfnDirEntry {
 }
 
 impl VFatLfnDirEntry {
-    fn new(order: u8, name: &[u8]) -> Self {
+    /// Creates the long file name record `order` holding `name`, at most 13
+    /// UTF-16 code units.
+    fn new(order: u8, name: &[u16]) -> Self {
         // TODO: fill in these values
         let mut result = VFatLfnDirEntry {
             order,
@@ -78,10 +108,9 @@ impl VFatLfnDirEntry {
             name_three: [0u8; 4],
         };
 
-        let mut buffer = [0u8; 26];
-        (&mut buffer[..name.len()]).copy_from_slice(name);
-        let vec_u16: Vec<u16> = String::from_utf8_lossy(&buffer).encode_utf16().collect();
-        let vec_u8: Vec<u8> = vec_u16.iter()
+        let mut buffer = [0u16; 13];
+        buffer[..name.len()].copy_from_slice(name);
+        let vec_u8: Vec<u8> = buffer.iter()
             .flat_map(|d| [(d & 0xFF) as u8, (d >> 8 & 0xFF) as u8])
             .collect();
         let slice = vec_u8.as_slice();
@@ -135,6 +164,90 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
 
         Err(io::Error::from(io::ErrorKind::NotFound))
     }
+
+    /// Writes the `.` and `..` entries of a newly created directory `self`,
+    /// whose parent is `parent`, and clears the rest of its first cluster.
+    pub(crate) fn initialize(&mut self, parent: &Dir<HANDLE>) -> io::Result<()> {
+        let bytes_per_cluster = self.vfat.lock(|vfat| vfat.bytes_per_cluster());
+        let records = vec![
+            VFatDirEntry {
+                regular: VFatRegularDirEntry::new(*b".       ",
+                                                  DirectoryAttribute::Directory as u8,
+                                                  self.chain.first_cluster(), 0)
+            },
+            VFatDirEntry {
+                regular: VFatRegularDirEntry::new(*b"..      ",
+                                                  DirectoryAttribute::Directory as u8,
+                                                  parent.dot_dot_cluster(), 0)
+            },
+        ];
+
+        let mut bytes: Vec<u8> = unsafe { records.cast() };
+        bytes.resize(bytes_per_cluster, 0);
+        self.chain.seek(SeekFrom::Start(0))?;
+        self.chain.write_all(bytes.as_slice())
+    }
+
+    /// Points the `..` entry of `self` at `parent`. Used when a directory is
+    /// moved to a new parent.
+    pub(crate) fn set_parent(&mut self, parent: &Dir<HANDLE>) -> io::Result<()> {
+        let (mut records, range) = self.records("..")?;
+        let cluster = parent.dot_dot_cluster();
+        unsafe { records[range.end - 1].regular.set_cluster(cluster) };
+        self.write_records(records, range)
+    }
+
+    /// Records `size` as the size of the entry named `name`.
+    pub(crate) fn set_size(&mut self, name: &str, size: u32) -> io::Result<()> {
+        let (mut records, range) = self.records(name)?;
+        records[range.end - 1].regular.file_size = size;
+        self.write_records(records, range)
+    }
+
+    /// Returns whether `self` contains any entries besides `.` and `..`.
+    pub(crate) fn is_empty(&mut self) -> io::Result<bool> {
+        use filesystem::{Dir, Entry};
+
+        Ok(self.entries()?.all(|entry| entry.name() == "." || entry.name() == ".."))
+    }
+
+    /// The cluster a child's `..` entry refers to when `self` is its parent.
+    /// By convention the root directory is recorded as cluster 0.
+    fn dot_dot_cluster(&self) -> Cluster {
+        let cluster = self.chain.first_cluster();
+        if cluster == self.vfat.lock(|vfat| vfat.root_cluster()) {
+            Cluster::from(0)
+        } else {
+            cluster
+        }
+    }
+
+    /// Reads every record of `self` and returns them together with the range
+    /// of records that make up the entry named `name`, including its long file
+    /// name records.
+    fn records(&mut self, name: &str) -> io::Result<(Vec<VFatDirEntry>, Range<usize>)> {
+        use filesystem::{Dir, Entry};
+
+        let mut entries = self.entries()?;
+        loop {
+            match entries.next() {
+                Some(entry) if str::eq_ignore_ascii_case(entry.name(), name) => {
+                    let range = entries.start..entries.i;
+                    return Ok((entries.data, range));
+                }
+                Some(_) => {}
+                None => return ioerr!(NotFound),
+            }
+        }
+    }
+
+    /// Writes the records in `range` back to their place on disk.
+    fn write_records(&mut self, records: Vec<VFatDirEntry>, range: Range<usize>) -> io::Result<()> {
+        let size = size_of::<VFatDirEntry>();
+        let bytes: Vec<u8> = unsafe { records.cast() };
+        self.chain.seek(SeekFrom::Start((range.start * size) as u64))?;
+        self.chain.write_all(&bytes[range.start * size..range.end * size])
+    }
 }
 
 impl<HANDLE: VFatHandle> filesystem::Dir for Dir<HANDLE> {
@@ -143,12 +256,15 @@ impl<HANDLE: VFatHandle> filesystem::Dir for Dir<HANDLE> {
 
     fn entries(&mut self) -> io::Result<Self::Iter> {
         let mut data: Vec<u8> = Vec::new();
+        self.chain.seek(SeekFrom::Start(0))?;
         let _read = self.chain.read_to_end(&mut data)?;
 
         Ok(DirIter {
             vfat: self.vfat.clone(),
+            dir: self.clone(),
             data: unsafe { data.cast() },
             i: 0,
+            start: 0,
             done: false,
         })
     }
@@ -176,19 +292,16 @@ impl<HANDLE: VFatHandle> filesystem::Dir for Dir<HANDLE> {
             entries.push(VFatDirEntry { long_filename: lfn });
         }
 
-        let mut regular_dir_entry = VFatRegularDirEntry {
-            name: [0; 8],
-            extension: [0; 3],
-            attributes: 0,
-            __nt_reserved: 0,
-            created_time_tenth: 0,
-            created_time: Default::default(),
-            last_access: Default::default(),
-            first_cluster_high: 0,
-            last_modification: Default::default(),
-            first_cluster_low: 0,
-            file_size: 0,
+        let (attributes, cluster, file_size) = match entry {
+            crate::vfat::Entry::File(ref file) => {
+                (file.metadata.attributes, file.chain.first_cluster(), file.file_size)
+            }
+            crate::vfat::Entry::Dir(ref dir) => {
+                (dir.metadata.attributes | DirectoryAttribute::Directory as u8,
+                 dir.chain.first_cluster(), 0)
+            }
         };
+        let mut regular_dir_entry = VFatRegularDirEntry::new([b' '; 8], attributes, cluster, file_size);
 
         for (i, c) in entry.name().as_bytes().iter().take(8).enumerate() {
             regular_dir_entry.name[i] = c.to_ascii_uppercase();
@@ -205,15 +318,27 @@ impl<HANDLE: VFatHandle> filesystem::Dir for Dir<HANDLE> {
         Ok(())
     }
 
-    fn remove(&mut self, _entry: Self::Entry) -> io::Result<()> {
-        unimplemented!("not implemented")
+    /// Removes the records of `entry` from `self`. The clusters of `entry`
+    /// are left allocated.
+    fn remove(&mut self, entry: Self::Entry) -> io::Result<()> {
+        use filesystem::Entry;
+
+        let (mut records, range) = self.records(entry.name())?;
+        for record in &mut records[range.clone()] {
+            record.unknown.named = 0xe5;
+        }
+        self.write_records(records, range)
     }
 }
 
 pub struct DirIter<HANDLE: VFatHandle> {
     vfat: HANDLE,
+    /// The directory being listed, the parent of the files returned.
+    dir: Dir<HANDLE>,
     data: Vec<VFatDirEntry>,
     i: usize,
+    /// The index of the first record of the entry last returned.
+    start: usize,
     done: bool,
 }
 
@@ -240,6 +365,9 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
                 continue;
             }
 
+            if long_file_names.is_empty() {
+                self.start = self.i;
+            }
             self.i += 1;
 
             let (regular, long_file_name) = unsafe {
@@ -293,6 +421,7 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
 
             let metadata = Metadata {
                 attributes: regular_dir.attributes,
+                size: regular_dir.file_size,
                 created: regular_dir.created_time,
                 last_access: Timestamp { date: regular_dir.last_access, time: Default::default() },
                 last_modification: regular_dir.last_modification,
@@ -321,6 +450,8 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
                     metadata,
                     file_size: regular_dir.file_size,
                     chain,
+                    parent: Some(self.dir.clone()),
+                    open: false,
                 })
             };
 
@@ -352,7 +483,8 @@ fn parse_name(long_file_names: &mut Vec<VFatLfnDirEntry>) -> String {
 }
 
 fn serialize_lfns(name: &str) -> Vec<VFatLfnDirEntry> {
-    name.as_bytes().chunks(26).enumerate().map(|(i, chunk)| {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    units.chunks(13).enumerate().map(|(i, chunk)| {
         VFatLfnDirEntry::new(i as u8 + 1, chunk)
     }).collect()
 }
\ No newline at end of file
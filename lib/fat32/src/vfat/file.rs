@@ -1,17 +1,37 @@
 use alloc::string::String;
 
 use filesystem;
+use shim::{ioerr, newioerr};
 use shim::io::{self, SeekFrom};
 
-use crate::vfat::{Cluster, Metadata, Status, VFatHandle};
+use crate::vfat::{Cluster, Dir, Metadata, Status, VFatHandle};
 use crate::vfat::vfat::Chain;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
     pub name: String,
     pub metadata: Metadata,
     pub file_size: u32,
     pub(crate) chain: Chain<HANDLE>,
+    /// The directory that holds the entry of the file, if it is known.
+    pub(crate) parent: Option<Dir<HANDLE>>,
+    /// Whether `self` is an open descriptor, counted by the file system until
+    /// it is dropped.
+    pub(crate) open: bool,
+}
+
+/// A clone is a copy of the entry, not another descriptor.
+impl<HANDLE: VFatHandle> Clone for File<HANDLE> {
+    fn clone(&self) -> Self {
+        File {
+            name: self.name.clone(),
+            metadata: self.metadata.clone(),
+            file_size: self.file_size,
+            chain: self.chain.clone(),
+            parent: self.parent.clone(),
+            open: false,
+        }
+    }
 }
 
 impl<HANDLE: VFatHandle> File<HANDLE> {
@@ -27,8 +47,23 @@ impl<HANDLE: VFatHandle> File<HANDLE> {
             metadata: Default::default(),
             file_size: 0,
             chain: Chain::new_from_cluster(vfat.clone(), cluster)?,
+            parent: None,
+            open: false,
         })
     }
+
+    /// Empties the file in place: frees its clusters after the first and
+    /// records a size of zero in its directory entry, keeping the attributes
+    /// and times there.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.chain.truncate()?;
+        self.file_size = 0;
+        self.metadata.size = 0;
+        match self.parent {
+            Some(ref mut parent) => parent.set_size(&self.name, 0),
+            None => Ok(()),
+        }
+    }
 }
 
 impl<HANDLE: VFatHandle> filesystem::File for File<HANDLE> {
@@ -72,7 +107,17 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     ///
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        unimplemented!("File::seek()")
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n as i64),
+            SeekFrom::End(n) => (self.file_size as i64).checked_add(n),
+            SeekFrom::Current(n) => (self.chain.position() as i64).checked_add(n),
+        }.ok_or(newioerr!(InvalidInput))?;
+
+        if position < 0 || position > self.file_size as i64 {
+            ioerr!(InvalidInput)
+        } else {
+            self.chain.seek(SeekFrom::Start(position as u64))
+        }
     }
 }
@@ -2,6 +2,7 @@ use core::fmt;
 use core::fmt::Formatter;
 
 use filesystem;
+use filesystem::fs2::{EntryKind, Timestamp2};
 
 /// A date as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
@@ -102,10 +103,25 @@ impl fmt::Display for Timestamp {
     }
 }
 
+impl From<Timestamp> for Timestamp2 {
+    fn from(timestamp: Timestamp) -> Self {
+        use filesystem::Timestamp;
+        Timestamp2 {
+            year: timestamp.year(),
+            month: timestamp.month(),
+            day: timestamp.day(),
+            hour: timestamp.hour(),
+            minute: timestamp.minute(),
+            second: timestamp.second(),
+        }
+    }
+}
+
 /// Metadata for a directory entry.
 #[derive(Default, Debug, Clone)]
 pub struct Metadata {
     pub attributes: u8,
+    pub size: u32,
     pub created: Timestamp,
     pub last_access: Timestamp,
     pub last_modification: Timestamp,
@@ -135,6 +151,40 @@ impl filesystem::Metadata for Metadata {
     }
 }
 
+impl filesystem::fs2::Metadata2 for Metadata {
+    fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    fn kind(&self) -> EntryKind {
+        if self.attributes & 0x10 > 0 {
+            EntryKind::Directory
+        } else {
+            EntryKind::File
+        }
+    }
+
+    fn read_only(&self) -> bool {
+        filesystem::Metadata::read_only(self)
+    }
+
+    fn hidden(&self) -> bool {
+        filesystem::Metadata::hidden(self)
+    }
+
+    fn created(&self) -> Timestamp2 {
+        self.created.into()
+    }
+
+    fn accessed(&self) -> Timestamp2 {
+        self.last_access.into()
+    }
+
+    fn modified(&self) -> Timestamp2 {
+        self.last_modification.into()
+    }
+}
+
 impl fmt::Display for Metadata {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         use filesystem::Metadata;
@@ -1,4 +1,5 @@
 use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::{fmt, mem};
@@ -11,7 +12,7 @@ use log::info;
 
 use filesystem::{BlockDevice, FileSystem};
 use filesystem::Dir as DirTrait;
-use filesystem::fs2::{Directory2, Entry2, File2, FileSystem2, Metadata2};
+use filesystem::fs2::{DirEntry2, Directory2, Entry2, EntryKind, File2, FileSystem2, Metadata2, Readiness};
 use filesystem::path::{Component, Path};
 use shim::{io, ioerr, newioerr};
 use shim::io::SeekFrom;
@@ -38,6 +39,11 @@ pub struct VFat<HANDLE: VFatHandle> {
     data_start_sector: u64,
     root_cluster: Cluster,
     empty_fat_pointer: u32,
+    /// The number of descriptors open on each file, by its first cluster.
+    open_files: BTreeMap<Cluster, usize>,
+    /// The first clusters of files removed while descriptors were open on
+    /// them. Their chains are freed when the last descriptor is closed.
+    unlinked: BTreeSet<Cluster>,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
@@ -87,6 +93,8 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
             data_start_sector: data_start_sector as u64,
             root_cluster: Cluster::from(bios_parameter_block.root_cluster),
             empty_fat_pointer: 0,
+            open_files: BTreeMap::new(),
+            unlinked: BTreeSet::new(),
         }))
     }
 
@@ -144,7 +152,7 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         current_sector += offset / sector_size;
 
         if offset % self.device.sector_size() != 0 {
-            let amount_to_write = (sector_size - (offset % sector_size)) as usize;
+            let amount_to_write = min((sector_size - (offset % sector_size)) as usize, buf.len());
             let buffer = &buf[..amount_to_write];
             self.update_sector(current_sector, (offset % sector_size) as usize, buffer)?;
             current_sector += 1;
@@ -221,6 +229,57 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         Ok(())
     }
 
+    /// Marks every cluster of the chain starting at `cluster` as free.
+    pub(crate) fn free_chain(&mut self, cluster: Cluster) -> io::Result<()> {
+        let mut current = cluster;
+        loop {
+            let next = self.next_cluster(current)?;
+            self.update_fat_entry(current, Status::Free)?;
+            match next {
+                Some(cluster) => current = cluster,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Records that a descriptor was opened on the file starting at `cluster`.
+    pub(crate) fn open_file(&mut self, cluster: Cluster) {
+        *self.open_files.entry(cluster).or_insert(0) += 1;
+    }
+
+    /// Records that a descriptor on the file starting at `cluster` was
+    /// closed. If it was the last one and the file was removed, its chain is
+    /// freed.
+    pub(crate) fn close_file(&mut self, cluster: Cluster) -> io::Result<()> {
+        match self.open_files.get_mut(&cluster) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                return Ok(());
+            }
+            Some(_) => {
+                self.open_files.remove(&cluster);
+            }
+            None => return Ok(()),
+        }
+
+        if self.unlinked.remove(&cluster) {
+            self.free_chain(cluster)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Frees the chain starting at `cluster` of a removed entry, or, if
+    /// descriptors are still open on it, once the last of them is closed.
+    pub(crate) fn release_chain(&mut self, cluster: Cluster) -> io::Result<()> {
+        if self.open_files.contains_key(&cluster) {
+            self.unlinked.insert(cluster);
+            Ok(())
+        } else {
+            self.free_chain(cluster)
+        }
+    }
+
     pub(crate) fn bytes_per_cluster(&self) -> usize {
         (self.bytes_per_sector * self.sectors_per_cluster as u16) as usize
     }
@@ -239,7 +298,7 @@ pub(crate) struct Chain<HANDLE: VFatHandle> {
 
 impl<HANDLE: VFatHandle> Chain<HANDLE> {
     pub(crate) fn new(vfat: HANDLE) -> io::Result<Self> {
-        let cluster = vfat.lock(|vfat| {
+        let cluster = vfat.lock(|vfat| -> io::Result<Cluster> {
             let cluster = vfat.next_free_cluster()?;
             vfat.update_fat_entry(cluster, Status::new_eoc())?;
             Ok(cluster)
@@ -266,6 +325,31 @@ impl<HANDLE: VFatHandle> Chain<HANDLE> {
     pub(crate) fn position(&self) -> u64 {
         self.position
     }
+
+    pub(crate) fn first_cluster(&self) -> Cluster {
+        self.first_cluster
+    }
+
+    /// Frees every cluster of the chain after the first and moves back to its
+    /// start. A chain with no clusters is left as it is.
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        let first = self.first_cluster;
+        let number: u32 = first.into();
+        if number >= 2 {
+            self.vfat.lock(|vfat| -> io::Result<()> {
+                if let Some(next) = vfat.next_cluster(first)? {
+                    vfat.update_fat_entry(first, Status::new_eoc())?;
+                    vfat.free_chain(next)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        self.position = 0;
+        self.current_cluster = first;
+        self.exhausted = false;
+        Ok(())
+    }
 }
 
 /// Read for ChainOffset
@@ -322,12 +406,12 @@ impl<HANDLE: VFatHandle> io::Write for Chain<HANDLE> {
             let mut amount_written = 0;
 
             while !exhausted && amount_written < buf.len() {
-                let end_of_buffer = min(buf.len(), bytes_per_cluster - cluster_offset);
+                let end_of_buffer = min(buf.len(), bytes_per_cluster - cluster_offset + amount_written);
                 let buffer = &buf[amount_written..end_of_buffer];
                 let read = vfat.write_cluster(current_cluster, cluster_offset as u64, buffer)?;
                 amount_written += read;
                 cluster_offset += read;
-                if read == bytes_per_cluster {
+                if cluster_offset == bytes_per_cluster {
                     cluster_offset = 0;
                     match vfat.next_cluster(current_cluster)? {
                         Some(next_cluster) => {
@@ -390,22 +474,29 @@ impl<HANDLE: VFatHandle> io::Seek for Chain<HANDLE> {
             SeekFrom::Current(n) => {
                 if n < 0 {
                     let cluster_offset = self.position % bytes_per_cluster;
-                    let target_position = self.position - ((-n) as u64);
-                    if (target_position - self.position) < cluster_offset {
-                        self.position = target_position;
+                    let distance = n.unsigned_abs();
+                    if self.position < distance {
+                        ioerr!(InvalidInput)
+                    } else if !self.exhausted && distance <= cluster_offset {
+                        self.position -= distance;
                         Ok(self.position)
                     } else {
-                        self.seek(SeekFrom::Start(target_position))
+                        self.seek(SeekFrom::Start(self.position - distance))
                     }
                 } else if n == 0 {
                     Ok(self.position)
                 } else {
+                    let mut cluster_offset = self.position % bytes_per_cluster;
                     let mut offset = n as u64;
-                    while bytes_per_cluster < offset {
+                    while !self.exhausted && bytes_per_cluster <= cluster_offset + offset {
                         let next_cluster = self.vfat.lock(|vfat|
                             vfat.next_cluster(self.current_cluster)
                         )?;
+                        let step = bytes_per_cluster - cluster_offset;
                         match next_cluster {
+                            None if offset == step => {
+                                self.exhausted = true;
+                            }
                             None => {
                                 return ioerr!(UnexpectedEof);
                             }
@@ -413,8 +504,13 @@ impl<HANDLE: VFatHandle> io::Seek for Chain<HANDLE> {
                                 self.current_cluster = cluster;
                             }
                         }
-                        offset -= bytes_per_cluster;
-                        self.position += bytes_per_cluster;
+                        offset -= step;
+                        self.position += step;
+                        cluster_offset = 0;
+                    }
+
+                    if self.exhausted && offset > 0 {
+                        return ioerr!(UnexpectedEof);
                     }
 
                     self.position += offset;
@@ -509,61 +605,123 @@ impl<'a, HANDLE: VFatHandle> FileSystem for HandleReference<'a, HANDLE> {
 
 impl<HANDLE: VFatHandle + 'static> File2 for File<HANDLE> {
     fn duplicate(&mut self) -> io::Result<Box<dyn File2>> {
-        Ok(Box::new(File::<HANDLE> {
-            name: self.name.clone(),
-            metadata: self.metadata.clone(),
-            file_size: self.file_size,
-            chain: self.chain.clone(),
-        }))
+        let cluster = self.chain.first_cluster();
+        self.chain.vfat.lock(|vfat| vfat.open_file(cluster));
+        let mut file = self.clone();
+        file.open = true;
+        Ok(Box::new(file))
+    }
+
+    fn metadata(&mut self) -> io::Result<Box<dyn Metadata2>> {
+        Ok(Box::new(self.metadata.clone()))
+    }
+
+    fn readiness(&mut self) -> io::Result<Readiness> {
+        Ok(Readiness::ALWAYS)
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+        File::truncate(self)
     }
 }
 
 impl<HANDLE: VFatHandle> Drop for File<HANDLE> {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        if self.open {
+            let cluster = self.chain.first_cluster();
+            // Drop cannot report the error; the clusters then stay allocated.
+            let _ = self.chain.vfat.lock(|vfat| vfat.close_file(cluster));
+        }
+    }
 }
 
 impl<HANDLE: VFatHandle> Directory2 for Dir<HANDLE> where HANDLE: 'static {
     fn open_entry(&mut self, name: &str) -> io::Result<Entry2> {
         Ok(match self.find(name)? {
-            Entry::File(file) => Entry2::File(Box::new(file)),
+            Entry::File(mut file) => {
+                let cluster = file.chain.first_cluster();
+                self.vfat.lock(|vfat| vfat.open_file(cluster));
+                file.open = true;
+                Entry2::File(Box::new(file))
+            }
             Entry::Dir(dir) => Entry2::Directory(Box::new(dir)),
         })
     }
 
     fn create_file(&mut self, name: &str) -> io::Result<()> {
+        if self.exists(name)? {
+            return ioerr!(AlreadyExists);
+        }
+
         let file = File::<HANDLE> {
             name: name.to_string(),
             metadata: Default::default(),
             file_size: 0,
             chain: Chain::new(self.vfat.clone())?,
+            parent: Some(self.clone()),
+            open: false,
         };
 
         DirTrait::append(self, Entry::File(file))
     }
 
     fn create_directory(&mut self, name: &str) -> io::Result<()> {
-        let dir = Dir::<HANDLE> {
+        if self.exists(name)? {
+            return ioerr!(AlreadyExists);
+        }
+
+        let mut dir = Dir::<HANDLE> {
             vfat: self.vfat.clone(),
             name: name.to_string(),
             metadata: Default::default(),
             chain: Chain::new(self.vfat.clone())?,
         };
+        dir.initialize(self)?;
 
         DirTrait::append(self, Entry::Dir(dir))
     }
 
-    fn remove(&mut self, _name: &str) -> io::Result<()> {
-        todo!()
+    fn remove(&mut self, name: &str) -> io::Result<()> {
+        if name == "." || name == ".." {
+            return ioerr!(InvalidFilename);
+        }
+
+        let entry = self.find(name)?;
+        let cluster = match entry {
+            Entry::File(ref file) => file.chain.first_cluster(),
+            Entry::Dir(ref dir) => {
+                if !dir.clone().is_empty()? {
+                    return ioerr!(DirectoryNotEmpty);
+                }
+                dir.chain.first_cluster()
+            }
+        };
+
+        DirTrait::remove(self, entry)?;
+        // Files that never had any data written have no clusters. Those of a
+        // file that is still open are kept until its last descriptor closes.
+        let number: u32 = cluster.into();
+        if number >= 2 {
+            self.vfat.lock(|vfat| vfat.release_chain(cluster))?;
+        }
+        Ok(())
     }
 
-    fn list(&mut self) -> io::Result<Vec<String>> {
+    fn list(&mut self) -> io::Result<Vec<DirEntry2>> {
         Ok(DirTrait::entries(self)?
-            .map(|entry| filesystem::Entry::name(&entry).to_string())
+            .map(|entry| {
+                let kind = match entry {
+                    Entry::File(_) => EntryKind::File,
+                    Entry::Dir(_) => EntryKind::Directory,
+                };
+                DirEntry2::new(filesystem::Entry::name(&entry), kind)
+            })
             .collect())
     }
 
-    fn metadata(&mut self, _name: &str) -> io::Result<Box<dyn Metadata2>> {
-        todo!()
+    fn metadata(&mut self, name: &str) -> io::Result<Box<dyn Metadata2>> {
+        use filesystem::Entry;
+        Ok(Box::new(self.find(name)?.metadata().clone()))
     }
 }
 
@@ -583,4 +741,58 @@ impl<'a, HANDLE: VFatHandle> FileSystem2 for HandleReference<'a, HANDLE> where H
     fn copy_entry(&mut self, _source: &Path, _destination: &Path) -> io::Result<()> {
         todo!()
     }
+
+    fn move_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        use filesystem::Entry as EntryTrait;
+
+        let source = source.simplify()?;
+        let destination = destination.simplify()?;
+        if source == destination {
+            return Ok(());
+        }
+        if destination.starts_with(&source) {
+            return ioerr!(InvalidInput);
+        }
+
+        let (source_parent, source_name) = split_parent(&source)?;
+        let (destination_parent, destination_name) = split_parent(&destination)?;
+        let mut source_dir = FileSystem::open(self, &source_parent)?
+            .into_dir().ok_or(newioerr!(NotADirectory))?;
+        let mut destination_dir = FileSystem::open(self, &destination_parent)?
+            .into_dir().ok_or(newioerr!(NotADirectory))?;
+
+        let renaming_in_place = source_parent == destination_parent &&
+            source_name.eq_ignore_ascii_case(destination_name.as_str());
+        if !renaming_in_place && destination_dir.exists(destination_name.as_str())? {
+            return ioerr!(AlreadyExists);
+        }
+
+        let entry = source_dir.find(source_name.as_str())?;
+        let mut moved = entry.clone();
+        match moved {
+            Entry::File(ref mut file) => file.name = destination_name,
+            Entry::Dir(ref mut dir) => {
+                dir.name = destination_name;
+                if source_parent != destination_parent {
+                    dir.set_parent(&destination_dir)?;
+                }
+            }
+        }
+
+        // The new records are written before the old ones are cleared, so a
+        // failure part way through leaves the entry reachable.
+        DirTrait::append(&mut destination_dir, moved)?;
+        DirTrait::remove(&mut source_dir, entry)
+    }
+}
+
+/// Splits `path`, which must be simplified, into its parent and final name.
+fn split_parent(path: &Path) -> io::Result<(Path, String)> {
+    match path.components().last() {
+        Some(Component::Child(name)) => {
+            let parent = path.prefix(path.len() - 1).ok_or(newioerr!(InvalidFilename))?;
+            Ok((parent, name.clone()))
+        }
+        _ => ioerr!(InvalidFilename),
+    }
 }
\ No newline at end of file
@@ -13,7 +13,7 @@ use shim::{io, ioerr};
 use sync::Mutex;
 
 use crate::{File, FileSystem};
-use crate::fs2::{Directory2, Entry2, File2, FileSystem2, Metadata2};
+use crate::fs2::{DirEntry2, Directory2, Entry2, EntryKind, File2, FileSystem2, Metadata2, Readiness, VirtualMetadata};
 use crate::path::Path;
 
 /// Trait implemented by devices that can be read/written in sector
@@ -124,7 +124,18 @@ impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
 #[cfg(test)]
 impl_for_read_write_seek!(::std::fs::File);
 
-pub trait CharDevice: Send + Sync + io::Read + io::Write + Clone {}
+pub trait CharDevice: Send + Sync + io::Read + io::Write + Clone {
+    /// Reports whether the device has input waiting or room for output.
+    fn readiness(&mut self) -> Readiness {
+        Readiness::ALWAYS
+    }
+
+    /// Returns the wait channel woken when input arrives, for a device whose
+    /// reads fail with `WouldBlock` while there is none.
+    fn wait_channel(&self) -> Option<usize> {
+        None
+    }
+}
 
 pub struct CharDeviceFileSystem<T: CharDevice + 'static>(String, T);
 
@@ -169,12 +180,16 @@ impl<T: CharDevice + 'static> Directory2 for CharDeviceRootDirectory<T> {
         ioerr!(Unsupported)
     }
 
-    fn list(&mut self) -> io::Result<Vec<String>> {
-        Ok(vec![self.0.clone()])
+    fn list(&mut self) -> io::Result<Vec<DirEntry2>> {
+        Ok(vec![DirEntry2::new(self.0.clone(), EntryKind::CharDevice)])
     }
 
-    fn metadata(&mut self, _: &str) -> io::Result<Box<dyn Metadata2>> {
-        ioerr!(Unsupported)
+    fn metadata(&mut self, name: &str) -> io::Result<Box<dyn Metadata2>> {
+        if self.0.eq(name) {
+            Ok(Box::new(VirtualMetadata(EntryKind::CharDevice)))
+        } else {
+            ioerr!(NotFound)
+        }
     }
 }
 
@@ -204,6 +219,18 @@ impl<T: CharDevice + 'static> File2 for CharDeviceFile<T> {
     fn duplicate(&mut self) -> io::Result<Box<dyn File2>> {
         Ok(Box::new(CharDeviceFile(self.0.clone())))
     }
+
+    fn metadata(&mut self) -> io::Result<Box<dyn Metadata2>> {
+        Ok(Box::new(VirtualMetadata(EntryKind::CharDevice)))
+    }
+
+    fn readiness(&mut self) -> io::Result<Readiness> {
+        Ok(self.0.readiness())
+    }
+
+    fn wait_channel(&self) -> Option<usize> {
+        self.0.wait_channel()
+    }
 }
 
 impl<T: CharDevice + 'static> Drop for CharDeviceFile<T> {
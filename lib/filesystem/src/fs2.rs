@@ -11,11 +11,257 @@ use shim::io::ErrorKind::NotFound;
 use crate::Metadata;
 use crate::path::{Component, Path};
 
-pub trait Metadata2 {}
+/// The kind of entry described by a `Metadata2`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    CharDevice,
+}
+
+/// A single entry of a directory listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry2 {
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+impl DirEntry2 {
+    pub fn new<T: ToString>(name: T, kind: EntryKind) -> Self {
+        DirEntry2 {
+            name: name.to_string(),
+            kind,
+        }
+    }
+}
+
+/// A calendar date and time as recorded by a file system. The fields have the
+/// same ranges as the corresponding methods of `Timestamp`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timestamp2 {
+    pub year: usize,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+pub trait Metadata2 {
+    /// The size of the entry in bytes.
+    fn size(&self) -> u64;
+
+    /// The kind of the entry.
+    fn kind(&self) -> EntryKind;
+
+    /// Whether the entry is read only.
+    fn read_only(&self) -> bool;
+
+    /// Whether the entry should be hidden from directory listings.
+    fn hidden(&self) -> bool;
+
+    /// The time the entry was created.
+    fn created(&self) -> Timestamp2;
+
+    /// The time the entry was last accessed.
+    fn accessed(&self) -> Timestamp2;
+
+    /// The time the entry was last modified.
+    fn modified(&self) -> Timestamp2;
+}
+
+/// Metadata for entries that exist only in memory, such as devices and the
+/// directories leading up to a mount point. They have no size, flags or times.
+#[derive(Copy, Clone, Debug)]
+pub struct VirtualMetadata(pub EntryKind);
+
+impl Metadata2 for VirtualMetadata {
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn kind(&self) -> EntryKind {
+        self.0
+    }
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    fn hidden(&self) -> bool {
+        false
+    }
+
+    fn created(&self) -> Timestamp2 {
+        Default::default()
+    }
+
+    fn accessed(&self) -> Timestamp2 {
+        Default::default()
+    }
+
+    fn modified(&self) -> Timestamp2 {
+        Default::default()
+    }
+}
+
+/// Which operations on a file would complete without blocking, as reported by
+/// `File2::readiness`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// A read would return data, or zero bytes at the end of the file.
+    pub readable: bool,
+    /// A write would accept at least one byte.
+    pub writable: bool,
+    /// The other end has gone away, so no more data will arrive or be
+    /// consumed.
+    pub hangup: bool,
+}
+
+impl Readiness {
+    /// The readiness of a file that never blocks, such as a file on disk.
+    pub const ALWAYS: Readiness = Readiness { readable: true, writable: true, hangup: false };
+}
 
 // For char devices, their seek just gives a NotSeekable error
 pub trait File2: io::Seek + io::Read + io::Write + Drop + Send + Sync {
     fn duplicate(&mut self) -> io::Result<Box<dyn File2>>;
+
+    fn metadata(&mut self) -> io::Result<Box<dyn Metadata2>> {
+        ioerr!(Unsupported)
+    }
+
+    /// Reports which operations would currently complete without blocking.
+    fn readiness(&mut self) -> io::Result<Readiness> {
+        Ok(Readiness::ALWAYS)
+    }
+
+    /// Returns the wait channel the kernel wakes when the readiness of the
+    /// file changes, for a file whose reads can fail with `WouldBlock`. Files
+    /// that never block have none.
+    fn wait_channel(&self) -> Option<usize> {
+        None
+    }
+
+    /// Empties the file, keeping its attributes and times.
+    /// Files that cannot be emptied return `Unsupported`.
+    fn truncate(&mut self) -> io::Result<()> {
+        ioerr!(Unsupported)
+    }
+}
+
+/// How `Directory2::open_file` opens a file, after `std::fs::OpenOptions`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenOptions2 {
+    pub read: bool,
+    pub write: bool,
+    /// Every write goes to the end of the file. Implies `write`.
+    pub append: bool,
+    /// An existing file is emptied when it is opened.
+    pub truncate: bool,
+    /// The file is created if it does not exist.
+    pub create: bool,
+    /// The file is created, and must not already exist.
+    pub create_new: bool,
+}
+
+impl OpenOptions2 {
+    pub fn writes(&self) -> bool {
+        self.write || self.append
+    }
+
+    /// Checks that the options ask for some access, and that files are only
+    /// created or truncated when opened for writing.
+    pub fn validate(&self) -> io::Result<()> {
+        if !self.read && !self.writes() {
+            return ioerr!(InvalidInput);
+        }
+        if (self.truncate || self.create || self.create_new) && !self.writes() {
+            return ioerr!(InvalidInput);
+        }
+        Ok(())
+    }
+}
+
+/// A file opened through `Directory2::open_file`. Reads and writes the
+/// options did not ask for fail with `PermissionDenied`, and in append mode
+/// every write first moves to the end of the file, unless the file cannot
+/// seek.
+pub struct OpenedFile {
+    file: Box<dyn File2>,
+    options: OpenOptions2,
+}
+
+impl OpenedFile {
+    pub fn new(file: Box<dyn File2>, options: OpenOptions2) -> Self {
+        OpenedFile { file, options }
+    }
+}
+
+impl io::Read for OpenedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.options.read {
+            return ioerr!(PermissionDenied);
+        }
+        self.file.read(buf)
+    }
+}
+
+impl io::Write for OpenedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.options.writes() {
+            return ioerr!(PermissionDenied);
+        }
+        if self.options.append {
+            // Char devices and pipes have no end to move to.
+            match self.file.seek(io::SeekFrom::End(0)) {
+                Err(e) if e.kind() == io::ErrorKind::NotSeekable => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl io::Seek for OpenedFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for OpenedFile {
+    fn drop(&mut self) {}
+}
+
+impl File2 for OpenedFile {
+    fn duplicate(&mut self) -> io::Result<Box<dyn File2>> {
+        Ok(Box::new(OpenedFile::new(self.file.duplicate()?, self.options)))
+    }
+
+    fn metadata(&mut self) -> io::Result<Box<dyn Metadata2>> {
+        self.file.metadata()
+    }
+
+    fn readiness(&mut self) -> io::Result<Readiness> {
+        self.file.readiness()
+    }
+
+    fn wait_channel(&self) -> Option<usize> {
+        self.file.wait_channel()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+        if !self.options.writes() {
+            return ioerr!(PermissionDenied);
+        }
+        self.file.truncate()
+    }
 }
 
 pub trait Directory2 {
@@ -24,17 +270,40 @@ pub trait Directory2 {
     fn create_directory(&mut self, name: &str) -> io::Result<()>;
     fn remove(&mut self, name: &str) -> io::Result<()>;
 
-    fn list(&mut self) -> io::Result<Vec<String>>;
+    fn list(&mut self) -> io::Result<Vec<DirEntry2>>;
     fn metadata(&mut self, name: &str) -> io::Result<Box<dyn Metadata2>>;
 
     fn exists(&mut self, name: &str) -> io::Result<bool> {
-        let result = self.open_entry(name);
-        Ok(match result {
-            Ok(_) => true,
-            Err(ref e) => {
-                e.kind() == io::ErrorKind::NotFound
+        match self.open_entry(name) {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens the file `name` as `options` describe, creating or truncating it
+    /// first if asked to. Opening a directory fails with `IsADirectory`.
+    ///
+    /// Truncation empties the file in place, so descriptors already open on
+    /// it stay valid. It only applies to regular files, so devices can be
+    /// opened for truncation like any other output.
+    fn open_file(&mut self, name: &str, options: &OpenOptions2) -> io::Result<Box<dyn File2>> {
+        options.validate()?;
+
+        if !self.exists(name)? {
+            if !options.create && !options.create_new {
+                return ioerr!(NotFound);
             }
-        })
+            self.create_file(name)?;
+        } else if options.create_new {
+            return ioerr!(AlreadyExists);
+        }
+
+        let mut file = self.open_entry(name)?.into_file().ok_or(newioerr!(IsADirectory))?;
+        if options.truncate && self.metadata(name)?.kind() == EntryKind::File {
+            file.truncate()?;
+        }
+        Ok(Box::new(OpenedFile::new(file, *options)))
     }
 }
 
@@ -74,20 +343,44 @@ pub trait FileSystem2 {
             })
     }
 
+    fn metadata(&mut self, path: &Path) -> io::Result<Box<dyn Metadata2>> {
+        let path = path.simplify()?;
+        match path.components().last() {
+            Some(Component::Root) => Ok(Box::new(VirtualMetadata(EntryKind::Directory))),
+            Some(Component::Child(name)) => {
+                let parent = path.prefix(path.len() - 1)
+                    .ok_or(newioerr!(InvalidFilename))?;
+                self.open(&parent)?
+                    .into_directory().ok_or(newioerr!(NotADirectory))?
+                    .metadata(name.as_str())
+            }
+            _ => ioerr!(InvalidFilename),
+        }
+    }
+
+    /// Opens the file at `path` as `options` describe. See
+    /// `Directory2::open_file`.
+    fn open_file(&mut self, path: &Path, options: &OpenOptions2) -> io::Result<Box<dyn File2>> {
+        let path = path.simplify()?;
+        match path.components().last() {
+            Some(Component::Root) => ioerr!(IsADirectory),
+            Some(Component::Child(name)) => {
+                let parent = path.prefix(path.len() - 1)
+                    .ok_or(newioerr!(InvalidFilename))?;
+                self.open(&parent)?
+                    .into_directory().ok_or(newioerr!(NotADirectory))?
+                    .open_file(name.as_str(), options)
+            }
+            _ => ioerr!(InvalidFilename),
+        }
+    }
+
     fn copy_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()>;
 
-    fn move_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
-        //self.copy_entry(source, destination)?;
-        //match self.open(source.parent().ok_or(newioerr!(InvalidFilename))?)?.borrow_mut() {
-        //    Entry2::File(_) => ioerr!(InvalidFilename),
-        //    Entry2::Directory(directory) => {
-        //        let file_name = source.file_name()
-        //            .ok_or(newioerr!(InvalidFilename))?.to_str()
-        //            .ok_or(newioerr!(InvalidFilename))?;
-        //        directory.remove(file_name)
-        //    }
-        //}
-        todo!()
+    /// Moves the entry at `source` to `destination`, which must not exist.
+    /// File systems that cannot move entries return `Unsupported`.
+    fn move_entry(&mut self, _source: &Path, _destination: &Path) -> io::Result<()> {
+        ioerr!(Unsupported)
     }
 }
 
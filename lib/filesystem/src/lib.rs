@@ -3,6 +3,8 @@
 #![feature(decl_macro)]
 
 extern crate alloc;
+#[cfg(test)]
+extern crate std;
 
 pub use self::devices::{BlockDevice, CharDevice};
 pub use self::metadata::{Metadata, Timestamp};
@@ -1,16 +1,329 @@
-#![test]
-
+use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use shim::{io, ioerr};
+use shim::io::{Read, Seek, SeekFrom, Write};
 
+use crate::devices::{CharDevice, CharDeviceFileSystem};
+use crate::fs2::{DirEntry2, Directory2, Entry2, EntryKind, File2, FileSystem2, Metadata2, OpenOptions2, VirtualMetadata};
 use crate::path::Path;
 
+#[test]
 fn test_path() {
     let mut root = Path::root();
     assert_eq!(root.to_string(), String::from("/"));
 
-    let sub_path = Path::from("a/b/c");
+    let sub_path = Path::new("a/b/c").unwrap();
     assert_eq!(sub_path.to_string(), String::from("a/b/c"));
 
     root.append(&sub_path);
     assert_eq!(root.to_string(), String::from("/a/b/c"));
-}
\ No newline at end of file
+}
+
+/// A file kept in memory. Descriptors on the same file share its data but
+/// each has its own position.
+struct MemoryFile {
+    data: Arc<Mutex<Vec<u8>>>,
+    position: usize,
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = self.position.min(data.len());
+        let read = buf.len().min(data.len() - start);
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.lock().unwrap();
+        if data.len() < self.position + buf.len() {
+            data.resize(self.position + buf.len(), 0);
+        }
+        data[self.position..self.position + buf.len()].copy_from_slice(buf);
+        self.position += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let length = self.data.lock().unwrap().len() as i64;
+        let position = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => length + n,
+            SeekFrom::Current(n) => self.position as i64 + n,
+        };
+        if position < 0 || position > length {
+            return ioerr!(InvalidInput);
+        }
+        self.position = position as usize;
+        Ok(self.position as u64)
+    }
+}
+
+impl Drop for MemoryFile {
+    fn drop(&mut self) {}
+}
+
+impl File2 for MemoryFile {
+    fn duplicate(&mut self) -> io::Result<Box<dyn File2>> {
+        Ok(Box::new(MemoryFile { data: self.data.clone(), position: self.position }))
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+        self.data.lock().unwrap().clear();
+        self.position = 0;
+        Ok(())
+    }
+}
+
+/// A directory of `MemoryFile`s.
+#[derive(Default)]
+struct MemoryDirectory {
+    files: BTreeMap<String, Arc<Mutex<Vec<u8>>>>,
+}
+
+impl MemoryDirectory {
+    fn with_file(name: &str, contents: &[u8]) -> Self {
+        let mut directory = MemoryDirectory::default();
+        directory.files.insert(name.to_string(), Arc::new(Mutex::new(contents.to_vec())));
+        directory
+    }
+
+    fn contents(&self, name: &str) -> Vec<u8> {
+        self.files[name].lock().unwrap().clone()
+    }
+}
+
+impl Directory2 for MemoryDirectory {
+    fn open_entry(&mut self, name: &str) -> io::Result<Entry2> {
+        match self.files.get(name) {
+            Some(data) => Ok(Entry2::File(Box::new(MemoryFile { data: data.clone(), position: 0 }))),
+            None => ioerr!(NotFound),
+        }
+    }
+
+    fn create_file(&mut self, name: &str) -> io::Result<()> {
+        if self.files.contains_key(name) {
+            return ioerr!(AlreadyExists);
+        }
+        self.files.insert(name.to_string(), Arc::new(Mutex::new(Vec::new())));
+        Ok(())
+    }
+
+    fn create_directory(&mut self, _name: &str) -> io::Result<()> {
+        ioerr!(Unsupported)
+    }
+
+    fn remove(&mut self, name: &str) -> io::Result<()> {
+        self.files.remove(name).map(|_| ()).ok_or(io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn list(&mut self) -> io::Result<Vec<DirEntry2>> {
+        Ok(self.files.keys().map(|name| DirEntry2::new(name, EntryKind::File)).collect())
+    }
+
+    fn metadata(&mut self, name: &str) -> io::Result<Box<dyn Metadata2>> {
+        if !self.files.contains_key(name) {
+            return ioerr!(NotFound);
+        }
+        Ok(Box::new(VirtualMetadata(EntryKind::File)))
+    }
+}
+
+/// A char device that keeps what is written to it.
+#[derive(Clone, Default)]
+struct MemoryDevice {
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for MemoryDevice {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for MemoryDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CharDevice for MemoryDevice {}
+
+/// Returns the options whose fields are set from the low six bits of `bits`.
+fn options(bits: u8) -> OpenOptions2 {
+    OpenOptions2 {
+        read: bits & 1 != 0,
+        write: bits & 2 != 0,
+        append: bits & 4 != 0,
+        truncate: bits & 8 != 0,
+        create: bits & 16 != 0,
+        create_new: bits & 32 != 0,
+    }
+}
+
+fn read_to_end(file: &mut Box<dyn File2>) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[test]
+fn test_validate_every_combination() {
+    for bits in 0..64 {
+        let options = options(bits);
+        let writes = options.write || options.append;
+        let valid = (options.read || writes) &&
+            (writes || !(options.truncate || options.create || options.create_new));
+
+        let result = options.validate();
+        assert_eq!(result.is_ok(), valid, "{:?}", options);
+        if let Err(e) = result {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{:?}", options);
+        }
+        assert_eq!(options.writes(), writes, "{:?}", options);
+    }
+}
+
+#[test]
+fn test_open_every_combination() {
+    for bits in 0..64 {
+        let options = options(bits);
+        let creates = options.create || options.create_new;
+
+        let mut missing = MemoryDirectory::default();
+        let mut existing = MemoryDirectory::with_file("file", b"data");
+        let opened_missing = missing.open_file("file", &options);
+        let opened_existing = existing.open_file("file", &options);
+
+        if options.validate().is_err() {
+            assert_eq!(opened_missing.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(opened_existing.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(existing.contents("file"), b"data");
+            continue;
+        }
+
+        match opened_missing {
+            Ok(_) => {
+                assert!(creates, "{:?}", options);
+                assert_eq!(missing.contents("file"), b"");
+            }
+            Err(e) => {
+                assert!(!creates, "{:?}", options);
+                assert_eq!(e.kind(), io::ErrorKind::NotFound);
+                assert!(missing.files.is_empty());
+            }
+        }
+
+        match opened_existing {
+            Ok(_) => {
+                assert!(!options.create_new, "{:?}", options);
+                let expected: &[u8] = if options.truncate { b"" } else { b"data" };
+                assert_eq!(existing.contents("file"), expected, "{:?}", options);
+            }
+            Err(e) => {
+                assert!(options.create_new, "{:?}", options);
+                assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+                assert_eq!(existing.contents("file"), b"data");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_access_modes() {
+    let read_only = OpenOptions2 { read: true, ..Default::default() };
+    let write_only = OpenOptions2 { write: true, ..Default::default() };
+    let read_write = OpenOptions2 { read: true, write: true, ..Default::default() };
+    let mut directory = MemoryDirectory::with_file("file", b"data");
+
+    let mut file = directory.open_file("file", &read_only).unwrap();
+    assert_eq!(read_to_end(&mut file).unwrap(), b"data");
+    assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(file.truncate().unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    let mut duplicate = file.duplicate().unwrap();
+    assert_eq!(duplicate.write(b"x").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(directory.contents("file"), b"data");
+
+    let mut file = directory.open_file("file", &write_only).unwrap();
+    assert_eq!(read_to_end(&mut file).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    file.write_all(b"DA").unwrap();
+    assert_eq!(directory.contents("file"), b"DAta");
+
+    let mut file = directory.open_file("file", &read_write).unwrap();
+    file.seek(SeekFrom::Start(2)).unwrap();
+    file.write_all(b"TA").unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    assert_eq!(read_to_end(&mut file).unwrap(), b"DATA");
+}
+
+#[test]
+fn test_append_writes_at_end() {
+    let append = OpenOptions2 { read: true, append: true, ..Default::default() };
+    let write = OpenOptions2 { write: true, ..Default::default() };
+    let mut directory = MemoryDirectory::with_file("file", b"one");
+
+    let mut appender = directory.open_file("file", &append).unwrap();
+    let mut writer = directory.open_file("file", &write).unwrap();
+    appender.seek(SeekFrom::Start(0)).unwrap();
+    appender.write_all(b" two").unwrap();
+    assert_eq!(appender.seek(SeekFrom::Current(0)).unwrap(), 7);
+
+    // Another descriptor extends the file; the next append follows it.
+    writer.seek(SeekFrom::End(0)).unwrap();
+    writer.write_all(b" three").unwrap();
+    appender.write_all(b" four").unwrap();
+    assert_eq!(directory.contents("file"), b"one two three four");
+
+    // Reads still start where the descriptor was moved to.
+    appender.seek(SeekFrom::Start(4)).unwrap();
+    let mut word = [0u8; 3];
+    appender.read_exact(&mut word).unwrap();
+    assert_eq!(&word, b"two");
+}
+
+#[test]
+fn test_truncate_keeps_open_descriptors() {
+    let read = OpenOptions2 { read: true, ..Default::default() };
+    let truncate = OpenOptions2 { write: true, truncate: true, ..Default::default() };
+    let mut directory = MemoryDirectory::with_file("file", b"old contents");
+
+    let mut reader = directory.open_file("file", &read).unwrap();
+    let mut writer = directory.open_file("file", &truncate).unwrap();
+    writer.write_all(b"new").unwrap();
+
+    assert_eq!(read_to_end(&mut reader).unwrap(), b"new");
+    assert_eq!(directory.list().unwrap(), vec![DirEntry2::new("file", EntryKind::File)]);
+}
+
+#[test]
+fn test_append_to_char_device() {
+    let append = OpenOptions2 { append: true, ..Default::default() };
+    let device = MemoryDevice::default();
+    let mut filesystem = CharDeviceFileSystem::new("console".to_string(), device.clone());
+
+    let mut file = filesystem.root().unwrap().open_file("console", &append).unwrap();
+    file.write_all(b"one").unwrap();
+    file.write_all(b" two").unwrap();
+    assert_eq!(*device.written.lock().unwrap(), b"one two");
+    assert_eq!(file.seek(SeekFrom::End(0)).unwrap_err().kind(), io::ErrorKind::NotSeekable);
+}
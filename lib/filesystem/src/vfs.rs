@@ -14,7 +14,7 @@ use log::info;
 use shim::{io, ioerr, newioerr};
 
 use crate::fs2;
-use crate::fs2::{Directory2, Entry2, FileSystem2, Metadata2};
+use crate::fs2::{DirEntry2, Directory2, Entry2, EntryKind, FileSystem2, Metadata2, VirtualMetadata};
 use crate::path::{Component, Path};
 
 struct Mount {
@@ -56,6 +56,37 @@ impl FileSystem2 for VirtualFileSystem {
     fn copy_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
         todo!()
     }
+
+    fn move_entry(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        let source = source.simplify()?;
+        let destination = destination.simplify()?;
+
+        let mut mounts = self.mounts.0.as_ref().borrow_mut();
+        let source_mount = Mounts::containing(&mounts, &source).ok_or(newioerr!(NotFound))?;
+        let destination_mount = Mounts::containing(&mounts, &destination)
+            .ok_or(newioerr!(NotFound))?;
+        if source_mount != destination_mount {
+            return ioerr!(CrossesDevices);
+        }
+
+        let mount = &mut mounts[source_mount];
+        let mut source_in_mount = Path::root();
+        source_in_mount.append(&source.split_from_start(&mount.mount_point).unwrap());
+        let mut destination_in_mount = Path::root();
+        destination_in_mount.append(&destination.split_from_start(&mount.mount_point).unwrap());
+        mount.filesystem.move_entry(&source_in_mount, &destination_in_mount)
+    }
+}
+
+impl Mounts {
+    /// Returns the index of the mount with the longest mount point that
+    /// `path` lies under.
+    fn containing(mounts: &Vec<Mount>, path: &Path) -> Option<usize> {
+        mounts.iter().enumerate()
+            .filter(|(_, mount)| path.starts_with(&mount.mount_point))
+            .max_by_key(|(_, mount)| mount.mount_point.len())
+            .map(|(i, _)| i)
+    }
 }
 
 struct VFSDirectory {
@@ -95,29 +126,37 @@ impl Directory2 for VFSDirectory {
     }
 
     fn create_directory(&mut self, name: &str) -> io::Result<()> {
-        ioerr!(Unsupported)
+        self.mounts.0.as_ref().borrow_mut().iter_mut()
+            .filter(|mount| mount.mount_point == self.path)
+            .next().map(|mount| mount.filesystem.root()?.create_directory(name))
+            .unwrap_or(ioerr!(Unsupported))
     }
 
-    fn remove(&mut self, _: &str) -> io::Result<()> {
-        ioerr!(Unsupported)
+    fn remove(&mut self, name: &str) -> io::Result<()> {
+        self.mounts.0.as_ref().borrow_mut().iter_mut()
+            .filter(|mount| mount.mount_point == self.path)
+            .next().map(|mount| mount.filesystem.root()?.remove(name))
+            .unwrap_or(ioerr!(Unsupported))
     }
 
-    fn list(&mut self) -> io::Result<Vec<String>> {
+    fn list(&mut self) -> io::Result<Vec<DirEntry2>> {
         self.mounts.0.as_ref().borrow_mut().iter_mut()
             .filter(|mount| mount.mount_point.starts_with(&self.path))
             .fold(Ok(Vec::new()), |wrapped_result, mount| {
-                let mut result = wrapped_result?;
+                let mut result: Vec<DirEntry2> = wrapped_result?;
 
-                if let Some(component) = mount.mount_point.at(self.path.len() + 1) {
+                if let Some(component) = mount.mount_point.at(self.path.len()) {
                     match component {
                         Component::Child(child) => {
-                            result.push(child)
+                            if !result.iter().any(|entry| entry.name == child) {
+                                result.push(DirEntry2::new(child, EntryKind::Directory))
+                            }
                         }
                         _ => {}
                     }
                 } else {
                     if let Ok(mut fs) = mount.filesystem.root() {
-                        result.extend(fs.list()?.iter().map(|s| s.clone()))
+                        result.extend(fs.list()?)
                     }
                 }
 
@@ -125,8 +164,21 @@ impl Directory2 for VFSDirectory {
             })
     }
 
-    fn metadata(&mut self, _: &str) -> io::Result<Box<dyn Metadata2>> {
-        ioerr!(Unsupported)
+    fn metadata(&mut self, name: &str) -> io::Result<Box<dyn Metadata2>> {
+        let mut new_path = self.path.clone();
+        new_path.append_child(name.to_string());
+
+        self.mounts.0.as_ref().borrow_mut().iter_mut()
+            .filter(|mount| mount.mount_point.starts_with(&self.path))
+            .find_map(|mount| -> Option<Box<dyn Metadata2>> {
+                if mount.mount_point == self.path {
+                    mount.filesystem.root().ok()?.metadata(name).ok()
+                } else if mount.mount_point.starts_with(&new_path) {
+                    Some(Box::new(VirtualMetadata(EntryKind::Directory)))
+                } else {
+                    None
+                }
+            }).ok_or(newioerr!(NotFound))
     }
 }
 
@@ -0,0 +1,58 @@
+//! The environment variables a process was started with.
+//!
+//! `execute` copies the arguments and the environment of a new program to the
+//! top of its stack. The highest 8 bytes hold the length of the arguments and
+//! the 8 bytes below them the length of the environment, both little endian.
+//! Below those come the arguments followed by the environment, each stored as
+//! NUL separated strings in reverse order.
+
+use core::mem::size_of;
+use core::slice;
+use core::str;
+
+/// The address of the last byte of the user stack.
+const STACK_TOP: usize = usize::MAX;
+
+/// Returns an iterator over the environment variables of the current process
+/// as `(name, value)` pairs. Entries without an `=` are skipped.
+pub fn vars() -> Vars {
+    let length_size = size_of::<u64>();
+    let argument_length = unsafe { read_length(STACK_TOP - length_size + 1) };
+    let environment_length = unsafe { read_length(STACK_TOP - 2 * length_size + 1) };
+
+    // Both blocks are stored with one more byte than their length.
+    let end = STACK_TOP - 2 * length_size - argument_length;
+    let start = end - environment_length - 1;
+    let block = unsafe { slice::from_raw_parts(start as *const u8, end - start) };
+    Vars(block.rsplit(is_nul))
+}
+
+/// Returns the value of the environment variable `name`, if it is set.
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(key, _)| *key == name).map(|(_, value)| value)
+}
+
+fn is_nul(byte: &u8) -> bool {
+    *byte == 0
+}
+
+unsafe fn read_length(address: usize) -> usize {
+    u64::from_le_bytes(*(address as *const [u8; 8])) as usize
+}
+
+/// An iterator over the environment variables of the current process, created
+/// by `vars`.
+pub struct Vars(slice::RSplit<'static, u8, fn(&u8) -> bool>);
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = str::from_utf8(self.0.next()?).unwrap_or("");
+            if let Some(pair) = entry.split_once('=') {
+                return Some(pair);
+            }
+        }
+    }
+}
@@ -2,15 +2,38 @@
 
 #![feature(asm_const)]
 
+use core::time::Duration;
+
 use shim::io;
 
+#[cfg(feature = "user-space")]
+pub mod env;
 #[cfg(feature = "user-space")]
 pub mod syscall;
 
 pub type OsResult<T> = Result<T, OsError>;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum OsError {
+/// Defines `OsError` along with its conversion from the value the kernel
+/// passes back in `x7`, so that every error survives the round trip.
+macro_rules! os_errors {
+    ($($name:ident = $value:literal,)*) => {
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum OsError {
+            $($name = $value,)*
+        }
+
+        impl From<u64> for OsError {
+            fn from(e: u64) -> Self {
+                match e {
+                    $($value => OsError::$name,)*
+                    _ => OsError::Unknown,
+                }
+            }
+        }
+    };
+}
+
+os_errors! {
     Unknown = 0,
     Ok = 1,
 
@@ -21,45 +44,21 @@ pub enum OsError {
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
-
     UnknownResourceId = 80,
+    Interrupted = 90,
+    WouldBlock = 91,
 
     IoError = 101,
     IoErrorEof = 102,
     IoErrorInvalidData = 103,
     IoErrorInvalidInput = 104,
     IoErrorTimedOut = 105,
+    IoErrorNotSeekable = 106,
 
     InvalidSocket = 200,
     IllegalSocketOperation = 201,
 }
 
-impl From<u64> for OsError {
-    fn from(e: u64) -> Self {
-        match e {
-            1 => OsError::Ok,
-
-            10 => OsError::NoEntry,
-            20 => OsError::NoMemory,
-            30 => OsError::NoVmSpace,
-            40 => OsError::NoAccess,
-            50 => OsError::BadAddress,
-            60 => OsError::FileExists,
-            70 => OsError::InvalidArgument,
-
-            101 => OsError::IoError,
-            102 => OsError::IoErrorEof,
-            103 => OsError::IoErrorInvalidData,
-            104 => OsError::IoErrorInvalidInput,
-
-            200 => OsError::InvalidSocket,
-            201 => OsError::IllegalSocketOperation,
-
-            _ => OsError::Unknown,
-        }
-    }
-}
-
 impl From<io::Error> for OsError {
     fn from(e: io::Error) -> Self {
         match e.kind() {
@@ -67,59 +66,589 @@ impl From<io::Error> for OsError {
             io::ErrorKind::InvalidData => OsError::IoErrorInvalidData,
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
+            io::ErrorKind::NotSeekable => OsError::IoErrorNotSeekable,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::Interrupted => OsError::Interrupted,
+            io::ErrorKind::WouldBlock => OsError::WouldBlock,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
+            io::ErrorKind::NotADirectory |
+            io::ErrorKind::IsADirectory |
+            io::ErrorKind::DirectoryNotEmpty |
+            io::ErrorKind::InvalidFilename |
+            io::ErrorKind::CrossesDevices => OsError::InvalidArgument,
             _ => OsError::IoError,
         }
     }
 }
 
-#[derive(Debug)]
-pub enum Syscall {
-    Open = 0,
-    Close = 1,
-    Read = 2,
-    Write = 3,
-    Pipe = 4,
-    Duplicate = 5,
-    Seek = 6,
+impl From<OsError> for io::ErrorKind {
+    fn from(e: OsError) -> Self {
+        match e {
+            OsError::NoEntry => io::ErrorKind::NotFound,
+            OsError::NoMemory | OsError::NoVmSpace => io::ErrorKind::OutOfMemory,
+            OsError::NoAccess => io::ErrorKind::PermissionDenied,
+            OsError::FileExists => io::ErrorKind::AlreadyExists,
+            OsError::BadAddress |
+            OsError::InvalidArgument |
+            OsError::UnknownResourceId |
+            OsError::IoErrorInvalidInput => io::ErrorKind::InvalidInput,
+            OsError::Interrupted => io::ErrorKind::Interrupted,
+            OsError::WouldBlock => io::ErrorKind::WouldBlock,
+            OsError::IoErrorEof => io::ErrorKind::UnexpectedEof,
+            OsError::IoErrorInvalidData => io::ErrorKind::InvalidData,
+            OsError::IoErrorTimedOut => io::ErrorKind::TimedOut,
+            OsError::IoErrorNotSeekable => io::ErrorKind::NotSeekable,
+            _ => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl From<OsError> for io::Error {
+    fn from(e: OsError) -> Self {
+        io::Error::from(io::ErrorKind::from(e))
+    }
+}
+
+/// Passed to `wait` in place of a process id to wait for any child.
+pub const WAIT_ANY: u64 = u64::MAX;
+
+/// A `wait` option: return immediately if no matching child has exited.
+pub const WAIT_NO_HANG: u64 = 1 << 0;
+
+/// The lowest nice value, which gives a process the largest share of the CPU.
+pub const NICE_MIN: i64 = -20;
+
+/// The highest nice value, which gives a process the smallest share of the
+/// CPU.
+pub const NICE_MAX: i64 = 19;
+
+/// Passed to `getrusage` for what the calling process used.
+pub const USAGE_SELF: u64 = 0;
+
+/// Passed to `getrusage` for what the children the calling process reaped
+/// with `wait` used, along with the children they reaped.
+pub const USAGE_CHILDREN: u64 = 1;
+
+/// Passed to `getrusage` with a process id for what the process with the
+/// lowest id from it on used. The id of that process is returned, so every
+/// process can be listed by starting from `0` and going on from one past it.
+pub const USAGE_NEXT: u64 = 2;
+
+/// A signal that can be sent to a process with `kill`. The numbering follows
+/// POSIX.
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Hangup = 1,
+    Interrupt = 2,
+    Quit = 3,
+    IllegalInstruction = 4,
+    Trap = 5,
+    Abort = 6,
+    BusError = 7,
+    FloatingPointError = 8,
+    Kill = 9,
+    User1 = 10,
+    SegmentationFault = 11,
+    User2 = 12,
+    BrokenPipe = 13,
+    Alarm = 14,
+    Terminate = 15,
+    Child = 17,
+    Continue = 18,
+    Stop = 19,
+    TerminalStop = 20,
+}
+
+impl Signal {
+    pub fn from_u64(value: u64) -> Option<Signal> {
+        Some(match value {
+            1 => Signal::Hangup,
+            2 => Signal::Interrupt,
+            3 => Signal::Quit,
+            4 => Signal::IllegalInstruction,
+            5 => Signal::Trap,
+            6 => Signal::Abort,
+            7 => Signal::BusError,
+            8 => Signal::FloatingPointError,
+            9 => Signal::Kill,
+            10 => Signal::User1,
+            11 => Signal::SegmentationFault,
+            12 => Signal::User2,
+            13 => Signal::BrokenPipe,
+            14 => Signal::Alarm,
+            15 => Signal::Terminate,
+            17 => Signal::Child,
+            18 => Signal::Continue,
+            19 => Signal::Stop,
+            20 => Signal::TerminalStop,
+            _ => return None,
+        })
+    }
+
+    /// The bit that represents this signal in a signal mask.
+    pub fn mask(self) -> u64 {
+        1 << self as u64
+    }
+
+    /// Whether a process may handle, ignore or block this signal. `Kill` and
+    /// `Stop` always take their default action.
+    pub fn can_catch(self) -> bool {
+        self != Signal::Kill && self != Signal::Stop
+    }
+}
+
+/// Handler values for `SignalAction` with a special meaning.
+pub const SIGNAL_DEFAULT: u64 = 0;
+pub const SIGNAL_IGNORE: u64 = 1;
+
+/// How `SignalMask` combines the given mask with the blocked signals.
+pub const SIGNAL_BLOCK: u64 = 0;
+pub const SIGNAL_UNBLOCK: u64 = 1;
+pub const SIGNAL_SET_MASK: u64 = 2;
+
+/// Passed to `FutexWait` and `Poll` in place of a timeout to block without a
+/// time limit.
+pub const NO_TIMEOUT: u64 = u64::MAX;
+
+/// Readiness bits for `PollDescriptor`. `POLL_HANGUP` and `POLL_INVALID` are
+/// reported whether or not they were asked for.
+pub const POLL_READ: u32 = 1 << 0;
+pub const POLL_WRITE: u32 = 1 << 1;
+pub const POLL_HANGUP: u32 = 1 << 2;
+pub const POLL_INVALID: u32 = 1 << 3;
+
+/// A descriptor to watch with `poll`, along with the events to watch it for
+/// and, on return, the events it is ready for.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollDescriptor {
+    pub descriptor: u64,
+    pub events: u32,
+    pub ready: u32,
+}
+
+impl PollDescriptor {
+    pub fn new(descriptor: u64, events: u32) -> Self {
+        PollDescriptor {
+            descriptor,
+            events,
+            ready: 0,
+        }
+    }
+}
+
+/// Flags for `Open`. At least one of `OPEN_READ` and `OPEN_WRITE` must be
+/// given, and files can only be created or truncated when opened for writing.
+pub const OPEN_READ: u64 = 1 << 0;
+pub const OPEN_WRITE: u64 = 1 << 1;
+/// Every write goes to the end of the file. Implies `OPEN_WRITE`.
+pub const OPEN_APPEND: u64 = 1 << 2;
+/// An existing file is emptied.
+pub const OPEN_TRUNCATE: u64 = 1 << 3;
+/// The file is created if it does not exist.
+pub const OPEN_CREATE: u64 = 1 << 4;
+/// With `OPEN_CREATE`, fail with `FileExists` if the file already exists.
+pub const OPEN_EXCLUSIVE: u64 = 1 << 5;
+/// The descriptor is closed when the process calls `Execute`.
+pub const OPEN_CLOSE_ON_EXEC: u64 = 1 << 6;
+
+/// The protection bits of memory mapped with `Map`. Memory without
+/// `MEMORY_READ` cannot be mapped.
+pub const MEMORY_READ: u64 = 1 << 0;
+pub const MEMORY_WRITE: u64 = 1 << 1;
+pub const MEMORY_EXECUTE: u64 = 1 << 2;
+
+/// A request a tracer makes of a traced process with `Trace`.
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceRequest {
+    /// Asks to be traced by the parent. The caller stops after its next
+    /// successful `Execute`.
+    Me = 0,
+    /// Starts tracing a child, which stops the next time it returns to user
+    /// space.
+    Attach = 1,
+    /// Stops tracing a process and lets it run freely.
+    Detach = 2,
+    /// Resumes a stopped process until its next breakpoint.
+    Continue = 3,
+    /// Resumes a stopped process until it enters or leaves a system call.
+    Syscall = 4,
+    /// Resumes a stopped process for a single instruction.
+    Step = 5,
+    /// Copies the registers of a stopped process to a `Registers` at the
+    /// address given.
+    GetRegisters = 6,
+    /// Replaces the registers of a stopped process with the `Registers` at the
+    /// address given. Only the condition flags of `pstate` may be changed.
+    SetRegisters = 7,
+    /// Reads the `u64` at an address of a stopped process.
+    Read = 8,
+    /// Writes a `u64` to an address of a stopped process, even one it may only
+    /// read or execute.
+    Write = 9,
+}
+
+impl TraceRequest {
+    pub fn from_u64(value: u64) -> Option<TraceRequest> {
+        Some(match value {
+            0 => TraceRequest::Me,
+            1 => TraceRequest::Attach,
+            2 => TraceRequest::Detach,
+            3 => TraceRequest::Continue,
+            4 => TraceRequest::Syscall,
+            5 => TraceRequest::Step,
+            6 => TraceRequest::GetRegisters,
+            7 => TraceRequest::SetRegisters,
+            8 => TraceRequest::Read,
+            9 => TraceRequest::Write,
+            _ => return None,
+        })
+    }
+}
+
+/// Why a traced process stopped, as reported to its tracer by `TraceWait`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The tracer attached to it.
+    Attached,
+    /// It executed a `brk` instruction. The program counter is left on it.
+    Breakpoint,
+    /// It executed the single instruction it was resumed for.
+    Step,
+    /// It is about to make the system call with this number. Its arguments
+    /// can still be changed.
+    SyscallEntry(u16),
+    /// It made the system call with this number. The results are in place.
+    SyscallExit(u16),
+    /// It replaced its program with `Execute`.
+    Exec,
+    /// It exited with this status. It is no longer traced.
+    Exited(u64),
+}
+
+impl TraceEvent {
+    /// Encodes the event as the kind and detail `TraceWait` returns.
+    pub fn to_raw(self) -> (u64, u64) {
+        match self {
+            TraceEvent::Attached => (0, 0),
+            TraceEvent::Breakpoint => (1, 0),
+            TraceEvent::Step => (2, 0),
+            TraceEvent::SyscallEntry(number) => (3, number as u64),
+            TraceEvent::SyscallExit(number) => (4, number as u64),
+            TraceEvent::Exec => (5, 0),
+            TraceEvent::Exited(status) => (6, status),
+        }
+    }
+
+    /// Decodes an event encoded with `to_raw`.
+    pub fn from_raw(kind: u64, detail: u64) -> Option<TraceEvent> {
+        Some(match kind {
+            0 => TraceEvent::Attached,
+            1 => TraceEvent::Breakpoint,
+            2 => TraceEvent::Step,
+            3 => TraceEvent::SyscallEntry(detail as u16),
+            4 => TraceEvent::SyscallExit(detail as u16),
+            5 => TraceEvent::Exec,
+            6 => TraceEvent::Exited(detail),
+            _ => return None,
+        })
+    }
+}
+
+/// The user visible registers of a traced process.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub xs: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u64,
+    pub tls: u64,
+}
+
+impl Registers {
+    /// The condition flags, the only bits of `pstate` a tracer may change.
+    pub const FLAGS: u64 = 0b1111 << 28;
+}
+
+/// What a process used, as returned by `getrusage` and `wait`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Microseconds spent running in user space
+    pub user_time: u64,
+    /// Microseconds spent running in the kernel
+    pub system_time: u64,
+    /// Pages mapped in the address space
+    pub pages: u64,
+    /// The most pages that were mapped at once
+    pub max_pages: u64,
+    /// Times the process was switched to
+    pub switches: u64,
+    /// System calls made
+    pub syscalls: u64,
+}
+
+impl Usage {
+    /// Returns the time spent running in user space.
+    pub fn user(&self) -> Duration {
+        Duration::from_micros(self.user_time)
+    }
+
+    /// Returns the time spent running in the kernel.
+    pub fn system(&self) -> Duration {
+        Duration::from_micros(self.system_time)
+    }
+
+    /// Adds what `other`, a process that exited, used, as for the children
+    /// of a process. The most pages mapped at once is the larger of the two,
+    /// and `pages` is left alone since an exited process has none mapped.
+    pub fn add(&mut self, other: &Usage) {
+        self.user_time += other.user_time;
+        self.system_time += other.system_time;
+        self.max_pages = self.max_pages.max(other.max_pages);
+        self.switches += other.switches;
+        self.syscalls += other.syscalls;
+    }
+}
+
+/// The kind of entry described by a `Stat`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StatKind {
+    #[default]
+    File = 0,
+    Directory = 1,
+    CharDevice = 2,
+}
+
+/// A calendar date and time, as recorded by the file system.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    __reserved: u8,
+}
+
+impl DateTime {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            __reserved: 0,
+        }
+    }
+}
 
-    Fork = 10,
-    Execute = 11,
-    Exit = 12,
-    Wait = 13,
-    GetPid = 14,
+/// Information about a file system entry, as returned by `stat` and `fstat`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub size: u64,
+    pub kind: StatKind,
+    pub flags: u32,
+    pub created: DateTime,
+    pub accessed: DateTime,
+    pub modified: DateTime,
+}
 
-    Sbrk = 20,
+impl Stat {
+    pub const READ_ONLY: u32 = 1 << 0;
+    pub const HIDDEN: u32 = 1 << 1;
 
-    Sleep = 30,
-    Time = 31,
+    pub fn read_only(&self) -> bool {
+        self.flags & Stat::READ_ONLY != 0
+    }
 
-    Unknown = 256,
+    pub fn hidden(&self) -> bool {
+        self.flags & Stat::HIDDEN != 0
+    }
 }
 
-impl From<u16> for Syscall {
-    fn from(value: u16) -> Self {
+impl StatKind {
+    fn from_u8(value: u8) -> Option<StatKind> {
         match value {
-            0 => Syscall::Open,
-            1 => Syscall::Close,
-            2 => Syscall::Read,
-            3 => Syscall::Write,
-            4 => Syscall::Pipe,
-            5 => Syscall::Duplicate,
-            6 => Syscall::Seek,
-
-            10 => Syscall::Fork,
-            11 => Syscall::Execute,
-            12 => Syscall::Exit,
-            13 => Syscall::Wait,
-            14 => Syscall::GetPid,
-
-            20 => Syscall::Sbrk,
-
-            30 => Syscall::Sleep,
-            31 => Syscall::Time,
-
-            _ => Syscall::Unknown,
+            0 => Some(StatKind::File),
+            1 => Some(StatKind::Directory),
+            2 => Some(StatKind::CharDevice),
+            _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// A directory entry as streamed by the `readdir` system call.
+///
+/// On the wire, each entry is one byte holding its `StatKind`, the length of
+/// its name as a little endian `u16`, and then the UTF-8 name itself. Entries
+/// are packed back to back and never split across calls.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry<'a> {
+    pub kind: StatKind,
+    pub name: &'a str,
+}
+
+impl<'a> DirEntry<'a> {
+    const HEADER_SIZE: usize = 3;
+
+    /// The number of bytes the entry takes up once encoded.
+    pub fn encoded_len(&self) -> usize {
+        DirEntry::HEADER_SIZE + self.name.len()
+    }
+
+    /// Encodes the entry at the start of `buf`, returning the number of bytes
+    /// written, or `None` if `buf` is too small or the name too long.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let name_len = u16::try_from(self.name.len()).ok()?;
+        let buf = buf.get_mut(..self.encoded_len())?;
+
+        buf[0] = self.kind as u8;
+        buf[1..DirEntry::HEADER_SIZE].copy_from_slice(&name_len.to_le_bytes());
+        buf[DirEntry::HEADER_SIZE..].copy_from_slice(self.name.as_bytes());
+        Some(buf.len())
+    }
+
+    /// Decodes the entry at the start of `buf`, returning it along with the
+    /// number of bytes it took up.
+    pub fn decode(buf: &'a [u8]) -> Option<(DirEntry<'a>, usize)> {
+        let header = buf.get(..DirEntry::HEADER_SIZE)?;
+        let kind = StatKind::from_u8(header[0])?;
+        let name_len = u16::from_le_bytes([header[1], header[2]]) as usize;
+        let end = DirEntry::HEADER_SIZE + name_len;
+        let name = core::str::from_utf8(buf.get(DirEntry::HEADER_SIZE..end)?).ok()?;
+
+        Some((DirEntry { kind, name }, end))
+    }
+}
+
+/// An iterator over the entries packed into a buffer filled by `readdir`.
+pub struct DirEntries<'a>(&'a [u8]);
+
+impl<'a> DirEntries<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        DirEntries(buf)
+    }
+}
+
+impl<'a> Iterator for DirEntries<'a> {
+    type Item = DirEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (entry, len) = DirEntry::decode(self.0)?;
+        self.0 = &self.0[len..];
+        Some(entry)
+    }
+}
+
+/// The system call table: the single source of the `Syscall` numbers, the
+/// user-space stubs in `syscall::raw` and the kernel's dispatch.
+///
+/// Each entry reads `Variant = number: fn stub(x0: T0, x1: T1, ..) ->
+/// Returned => handler;`. The stub passes its arguments in the registers from
+/// `x0` on, so a wrapper that passes the wrong number of them, or a pointer
+/// where a length goes, does not compile. `Returned` is what the stub makes
+/// of `x0` to `x2` on success: `()`, one value, or a tuple of two or three.
+/// `handler` is the kernel function that serves the call.
+///
+/// `syscall_table!(callback)` expands to `callback! { entries.. }`, so every
+/// consumer generates its code from the same list.
+#[macro_export]
+macro_rules! syscall_table {
+    ($callback:ident) => {
+        $callback! {
+            Open = 0: fn open(path: *const u8, length: usize, flags: u64) -> u64 => sys_open;
+            Close = 1: fn close(descriptor: u64) -> () => sys_close;
+            Read = 2: fn read(descriptor: u64, buffer: *mut u8, length: usize) -> usize => sys_read;
+            Write = 3: fn write(descriptor: u64, buffer: *const u8, length: usize) -> usize => sys_write;
+            Pipe = 4: fn pipe() -> (u64, u64) => sys_pipe;
+            Duplicate = 5: fn duplicate(descriptor: u64, new: u64) -> u64 => sys_duplicate;
+            Seek = 6: fn seek(descriptor: u64, whence: u64, offset: u64) -> u64 => sys_seek;
+            Stat = 7: fn stat(path: *const u8, length: usize, stat: *mut Stat) -> () => sys_stat;
+            FileStat = 8: fn fstat(descriptor: u64, stat: *mut Stat) -> () => sys_fstat;
+            ReadDirectory = 9: fn readdir(descriptor: u64, buffer: *mut u8, length: usize) -> usize
+                => sys_readdir;
+
+            Fork = 10: fn fork() -> (u64, u64) => sys_fork;
+            Execute = 11: fn execute(arguments: *const u8, arguments_length: usize,
+                                     environment: *const u8, environment_length: usize) -> u64
+                => sys_execute;
+            Exit = 12: fn exit(status: u64) -> () => sys_exit;
+            Wait = 13: fn wait(pid: u64, options: u64, usage: *mut Usage) -> (u64, u64, u64) => sys_wait;
+            GetPid = 14: fn getpid() -> u64 => sys_getpid;
+            ThreadSpawn = 15: fn thread_spawn(entry: usize, argument: u64, tls: u64) -> u64
+                => sys_thread_spawn;
+            ThreadExit = 16: fn thread_exit(value: u64) -> () => sys_thread_exit;
+            ThreadJoin = 17: fn thread_join(id: u64) -> u64 => sys_thread_join;
+            Yield = 18: fn sched_yield() -> () => sys_yield;
+            SetPriority = 19: fn setpriority(pid: u64, nice: i64) -> () => sys_setpriority;
+
+            Sbrk = 20: fn sbrk() -> (usize, usize) => sys_sbrk;
+            Map = 21: fn mmap(address: usize, length: usize, protection: u64) -> usize => sys_mmap;
+            Unmap = 22: fn munmap(address: usize, length: usize) -> () => sys_munmap;
+
+            Sleep = 30: fn sleep(milliseconds: u64) -> u64 => sys_sleep;
+            Time = 31: fn time() -> (u64, u64) => sys_time;
+            ResourceUsage = 32: fn getrusage(who: u64, pid: u64, usage: *mut Usage) -> u64 => sys_getrusage;
+
+            CreateFile = 40: fn create(path: *const u8, length: usize) -> () => sys_create;
+            CreateDirectory = 41: fn mkdir(path: *const u8, length: usize) -> () => sys_mkdir;
+            RemoveFile = 42: fn unlink(path: *const u8, length: usize) -> () => sys_unlink;
+            RemoveDirectory = 43: fn rmdir(path: *const u8, length: usize) -> () => sys_rmdir;
+            Rename = 44: fn rename(from: *const u8, from_length: usize, to: *const u8, to_length: usize) -> ()
+                => sys_rename;
+            ChangeDirectory = 45: fn chdir(path: *const u8, length: usize) -> () => sys_chdir;
+            GetWorkingDirectory = 46: fn getcwd(buffer: *mut u8, length: usize) -> usize => sys_getcwd;
+
+            Kill = 50: fn kill(pid: u64, signal: u64) -> () => sys_kill;
+            SignalAction = 51: fn sigaction(signal: u64, handler: u64, restorer: usize) -> () => sys_sigaction;
+            SignalMask = 52: fn sigprocmask(how: u64, mask: u64) -> u64 => sys_sigprocmask;
+            SignalReturn = 53: fn sigreturn() -> () => sys_sigreturn;
+
+            FutexWait = 60: fn futex_wait(address: *const u32, expected: u32, timeout: u64) -> ()
+                => sys_futex_wait;
+            FutexWake = 61: fn futex_wake(address: *const u32, count: u64) -> u64 => sys_futex_wake;
+
+            Poll = 70: fn poll(descriptors: *mut PollDescriptor, count: usize, timeout: u64) -> usize
+                => sys_poll;
+
+            Trace = 80: fn trace(request: u64, pid: u64, address: u64, data: u64) -> u64 => sys_trace;
+            TraceWait = 81: fn trace_wait(pid: u64) -> (u64, u64, u64) => sys_trace_wait;
+
+            SetAffinity = 90: fn setaffinity(pid: u64, mask: u64) -> () => sys_setaffinity;
+            GetAffinity = 91: fn getaffinity(pid: u64) -> u64 => sys_getaffinity;
+        }
+    };
+}
+
+macro_rules! define_syscalls {
+    ($($variant:ident = $number:literal: fn $stub:ident($($argument:ident: $type:ty),*) -> $returned:ty
+        => $handler:ident;)*) => {
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum Syscall {
+            $($variant = $number,)*
+            Unknown = 256,
+        }
+
+        impl From<u16> for Syscall {
+            fn from(value: u16) -> Self {
+                match value {
+                    $($number => Syscall::$variant,)*
+                    _ => Syscall::Unknown,
+                }
+            }
+        }
+    };
+}
+
+syscall_table!(define_syscalls);
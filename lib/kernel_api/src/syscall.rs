@@ -1,168 +1,403 @@
 use core::arch::asm;
+use core::cmp::min;
 use core::fmt;
 use core::fmt::Write;
+use core::ptr;
+use core::sync::atomic::AtomicU32;
 use core::time::Duration;
 
 use shim::ioerr;
 
 use crate::*;
 
-macro_rules! err_or {
-    ($ecode:expr, $rtn:expr) => {{
-        let e = OsError::from($ecode);
-        if let OsError::Ok = e {
-            Ok($rtn)
-        } else {
-            Err(e)
-        }
-    }};
-}
-
-macro_rules! syscall_args {
-    ($a:expr) => (
-        asm!("mov x0, {}", in(reg) $a);
-    );
-    ($a:expr, $b:expr) => (
-        syscall_args!($a);
-        asm!("mov x1, {}", in(reg) $b);
-    );
-    ($a:expr, $b:expr, $c:expr) => (
-        syscall_args!($a, $b);
-        asm!("mov x2, {}", in(reg) $c);
-    );
-    ($a:expr, $b:expr, $c:expr, $d:expr) => (
-        syscall_args!($a, $b, $c);
-        asm!("mov x3, {}", in(reg) $d);
+/// Makes system call `N` with `arguments` in `x0` onwards. Returns `x0` to
+/// `x2` if the status the kernel leaves in `x7` is `Ok`.
+///
+/// The kernel preserves every register other than these, so the single `svc`
+/// declares exactly the registers the call reads and writes.
+#[inline(always)]
+unsafe fn svc<const N: u16>(arguments: &[u64]) -> OsResult<[u64; 3]> {
+    let mut registers = [0u64; 4];
+    registers[..arguments.len()].copy_from_slice(arguments);
+
+    let (x0, x1, x2, status): (u64, u64, u64, u64);
+    asm!(
+        "svc {number}",
+        number = const N,
+        inlateout("x0") registers[0] => x0,
+        inlateout("x1") registers[1] => x1,
+        inlateout("x2") registers[2] => x2,
+        in("x3") registers[3],
+        lateout("x7") status,
+        options(nostack),
     );
+
+    match OsError::from(status) {
+        OsError::Ok => Ok([x0, x1, x2]),
+        e => Err(e),
+    }
 }
 
-macro_rules! syscall {
-    ($syscall_number:expr) => (
-        asm!("svc {}", const { $syscall_number as u16 })
-    );
+/// A value that travels in one register.
+trait Register {
+    fn into_register(self) -> u64;
+    fn from_register(register: u64) -> Self;
 }
 
-macro_rules! syscall_receive0 {
-    () => {
-        syscall_receive3!().map(|(_, _, _)| ())
+macro_rules! integer_registers {
+    ($($type:ty),*) => {
+        $(
+            impl Register for $type {
+                fn into_register(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_register(register: u64) -> Self {
+                    register as $type
+                }
+            }
+        )*
     };
 }
 
-macro_rules! syscall_receive1 {
-    () => {
-        syscall_receive3!().map(|(x, _, _)| x)
-    };
+integer_registers!(u32, u64, i64, usize);
+
+impl<T> Register for *const T {
+    fn into_register(self) -> u64 {
+        self as usize as u64
+    }
+
+    fn from_register(register: u64) -> Self {
+        register as usize as *const T
+    }
 }
 
-macro_rules! syscall_receive2 {
-    () => {
-        syscall_receive3!().map(|(x, y, _)| (x, y))
-    };
+impl<T> Register for *mut T {
+    fn into_register(self) -> u64 {
+        self as usize as u64
+    }
+
+    fn from_register(register: u64) -> Self {
+        register as usize as *mut T
+    }
+}
+
+/// The results a stub picks out of `x0` to `x2`.
+trait Returned {
+    fn from_registers(registers: [u64; 3]) -> Self;
+}
+
+impl Returned for () {
+    fn from_registers(_: [u64; 3]) -> Self {}
+}
+
+impl<A: Register> Returned for A {
+    fn from_registers(registers: [u64; 3]) -> Self {
+        A::from_register(registers[0])
+    }
 }
 
-macro_rules! syscall_receive3 {
-    () => {{
-        let x: u64;
-        let y: u64;
-        let z: u64;
-        let e: u64;
-        asm!(
-            "mov {}, x0",
-            "mov {}, x1",
-            "mov {}, x2",
-            "mov {}, x7",
-            out(reg) x,
-            out(reg) y,
-            out(reg) z,
-            out(reg) e
-        );
-        err_or!(e, (x, y, z))
-    }};
+impl<A: Register, B: Register> Returned for (A, B) {
+    fn from_registers(registers: [u64; 3]) -> Self {
+        (A::from_register(registers[0]), B::from_register(registers[1]))
+    }
+}
+
+impl<A: Register, B: Register, C: Register> Returned for (A, B, C) {
+    fn from_registers(registers: [u64; 3]) -> Self {
+        (A::from_register(registers[0]), B::from_register(registers[1]), C::from_register(registers[2]))
+    }
 }
 
+macro_rules! define_stubs {
+    ($($variant:ident = $number:literal: fn $stub:ident($($argument:ident: $type:ty),*) -> $returned:ty
+        => $handler:ident;)*) => {
+        /// One stub per entry of `syscall_table!`, taking and returning the
+        /// types the entry lists. The wrappers in this module build on these.
+        pub mod raw {
+            use super::*;
+
+            $(
+                #[inline(always)]
+                pub unsafe fn $stub($($argument: $type),*) -> OsResult<$returned> {
+                    svc::<{ Syscall::$variant as u16 }>(&[$($argument.into_register()),*])
+                        .map(Returned::from_registers)
+                }
+            )*
+        }
+    };
+}
+
+crate::syscall_table!(define_stubs);
+
 pub fn sleep(span: Duration) -> OsResult<Duration> {
     if span.as_millis() > u64::MAX as u128 {
         panic!("too big!");
     }
 
-    let elapsed_ms = unsafe {
-        syscall_args!(span.as_millis() as u64);
-        syscall!(Syscall::Sleep);
-        syscall_receive1!()?
-    };
+    let elapsed_ms = unsafe { raw::sleep(span.as_millis() as u64)? };
 
     Ok(Duration::from_millis(elapsed_ms))
 }
 
 pub fn time() -> OsResult<Duration> {
-    let returned = unsafe {
-        syscall!(Syscall::Time);
-        syscall_receive2!()?
-    };
+    let returned = unsafe { raw::time()? };
 
     Ok(Duration::from_secs(returned.0) + Duration::from_nanos(returned.1))
 }
 
-pub fn exit() -> OsResult<()> {
+/// Returns what the calling process used with `USAGE_SELF`, or what the
+/// children it reaped used with `USAGE_CHILDREN`.
+pub fn getrusage(who: u64) -> OsResult<Usage> {
+    let mut usage = Usage::default();
     unsafe {
-        syscall!(Syscall::Exit);
-        syscall_receive0!()
+        raw::getrusage(who, 0, &mut usage)?;
     }
+    Ok(usage)
+}
+
+/// Returns the id of the process with the lowest id from `pid` on, and what
+/// it used. Fails with `NoEntry` if there is none.
+pub fn next_usage(pid: u64) -> OsResult<(u64, Usage)> {
+    let mut usage = Usage::default();
+    let id = unsafe { raw::getrusage(USAGE_NEXT, pid, &mut usage)? };
+    Ok((id, usage))
 }
 
-pub fn open(file: &str) -> OsResult<u64> {
+/// Ends the current process with exit status `status`, which its parent can
+/// collect with `wait`.
+pub fn exit(status: u64) -> OsResult<()> {
+    unsafe { raw::exit(status) }
+}
+
+/// Opens `file` with `flags`, a combination of the `OPEN_*` constants, and
+/// returns its descriptor.
+pub fn open(file: &str, flags: u64) -> OsResult<u64> {
+    let slice = file.as_bytes();
+    unsafe { raw::open(slice.as_ptr(), slice.len(), flags) }
+}
+
+/// Reads from `file` into `bytes` and returns how many bytes were read, which
+/// is zero at the end of the file.
+pub fn read(file: u64, bytes: &mut [u8]) -> OsResult<usize> {
+    unsafe { raw::read(file, bytes.as_mut_ptr(), bytes.len()) }
+}
+
+/// Writes `bytes` to `file` and returns how many were written, which may be
+/// fewer than `bytes.len()`.
+pub fn write(file: u64, bytes: &[u8]) -> OsResult<usize> {
+    unsafe { raw::write(file, bytes.as_ptr(), bytes.len()) }
+}
+
+pub fn stat(path: &str) -> OsResult<Stat> {
+    let mut stat = Stat::default();
+    let slice = path.as_bytes();
     unsafe {
-        let slice = file.as_bytes();
-        syscall_args!((slice.as_ptr()) as u64, slice.len() as u64);
-        syscall!(Syscall::Open);
-        syscall_receive1!()
+        raw::stat(slice.as_ptr(), slice.len(), &mut stat)?;
     }
+    Ok(stat)
 }
 
-//TODO: make the semantics match io::Read
-pub fn read(file: u64, bytes: &mut [u8]) -> OsResult<()> {
+pub fn fstat(file: u64) -> OsResult<Stat> {
+    let mut stat = Stat::default();
     unsafe {
-        syscall_args!(file, (bytes.as_ptr()) as u64, bytes.len() as u64);
-        syscall!(Syscall::Read);
-        syscall_receive0!()
+        raw::fstat(file, &mut stat)?;
     }
+    Ok(stat)
+}
+
+/// Reads the next entries of the open directory `directory` into `buf`.
+///
+/// Returns the number of bytes written, which is `0` once every entry has been
+/// read. Use `DirEntries` to walk the entries in `buf`.
+pub fn readdir(directory: u64, buf: &mut [u8]) -> OsResult<usize> {
+    unsafe { raw::readdir(directory, buf.as_mut_ptr(), buf.len()) }
 }
 
-pub fn write(file: u64, bytes: &[u8]) -> OsResult<()> {
+/// Creates an empty file at `path`. Fails with `FileExists` if an entry is
+/// already there.
+pub fn create(path: &str) -> OsResult<()> {
+    let slice = path.as_bytes();
+    unsafe { raw::create(slice.as_ptr(), slice.len()) }
+}
+
+/// Creates an empty directory at `path`. Fails with `FileExists` if an entry
+/// is already there.
+pub fn mkdir(path: &str) -> OsResult<()> {
+    let slice = path.as_bytes();
+    unsafe { raw::mkdir(slice.as_ptr(), slice.len()) }
+}
+
+/// Removes the file at `path`. Fails with `InvalidArgument` if `path` is a
+/// directory.
+pub fn unlink(path: &str) -> OsResult<()> {
+    let slice = path.as_bytes();
+    unsafe { raw::unlink(slice.as_ptr(), slice.len()) }
+}
+
+/// Removes the empty directory at `path`. Fails with `InvalidArgument` if
+/// `path` is not a directory or is not empty.
+pub fn rmdir(path: &str) -> OsResult<()> {
+    let slice = path.as_bytes();
+    unsafe { raw::rmdir(slice.as_ptr(), slice.len()) }
+}
+
+/// Moves the entry at `from` to `to`. Both paths must be on the same mount and
+/// `to` must not exist.
+pub fn rename(from: &str, to: &str) -> OsResult<()> {
+    let from = from.as_bytes();
+    let to = to.as_bytes();
     unsafe {
-        syscall_args!(file, (bytes.as_ptr()) as u64, bytes.len() as u64);
-        syscall!(Syscall::Write);
-        syscall_receive0!()
+        raw::rename(from.as_ptr(), from.len(), to.as_ptr(), to.len())
     }
 }
 
+/// Makes `path` the current directory. Relative paths passed to other system
+/// calls are resolved against it.
+pub fn chdir(path: &str) -> OsResult<()> {
+    let slice = path.as_bytes();
+    unsafe { raw::chdir(slice.as_ptr(), slice.len()) }
+}
+
+/// Writes the absolute path of the current directory into `buf` and returns
+/// it. Fails with `InvalidArgument` if `buf` is too small.
+pub fn getcwd(buf: &mut [u8]) -> OsResult<&str> {
+    let len = unsafe { raw::getcwd(buf.as_mut_ptr(), buf.len())? };
+    core::str::from_utf8(&buf[..len]).map_err(|_| OsError::InvalidArgument)
+}
+
+pub fn seek(file: u64, position: io::SeekFrom) -> OsResult<u64> {
+    let (whence, offset) = match position {
+        io::SeekFrom::Start(n) => (0u64, n),
+        io::SeekFrom::Current(n) => (1u64, n as u64),
+        io::SeekFrom::End(n) => (2u64, n as u64),
+    };
+
+    unsafe { raw::seek(file, whence, offset) }
+}
+
 pub fn pipe() -> OsResult<(u64, u64)> {
-    unsafe {
-        syscall!(Syscall::Pipe);
-        syscall_receive2!()
+    unsafe { raw::pipe() }
+}
+
+/// Starts a thread in the current process that runs `entry` with `argument`
+/// on a stack the kernel maps for it. `tls` is placed in its `TPIDR_EL0`.
+/// Returns the ID of the thread.
+///
+/// `entry` must not return; it ends with `thread_exit`.
+pub fn thread_spawn(entry: extern "C" fn(u64) -> !, argument: u64, tls: u64) -> OsResult<u64> {
+    unsafe { raw::thread_spawn(entry as usize, argument, tls) }
+}
+
+/// Ends the calling thread, handing `value` to `thread_join`. Ending the
+/// first thread of a process exits the process with `value` as its status.
+pub fn thread_exit(value: u64) -> ! {
+    loop {
+        let _ = unsafe { raw::thread_exit(value) };
     }
 }
 
-pub fn getpid() -> OsResult<u64> {
+/// Waits for the thread `id` of the current process to end and returns the
+/// value it passed to `thread_exit`.
+pub fn thread_join(id: u64) -> OsResult<u64> {
+    unsafe { raw::thread_join(id) }
+}
+
+/// Returns the ID of the calling thread, which the kernel keeps in
+/// `TPIDRRO_EL0`.
+pub fn gettid() -> u64 {
+    let id: u64;
     unsafe {
-        syscall!(Syscall::GetPid);
-        syscall_receive1!()
+        asm!("mrs {}, TPIDRRO_EL0", out(reg) id);
     }
+    id
+}
+
+pub fn getpid() -> OsResult<u64> {
+    unsafe { raw::getpid() }
+}
+
+/// Gives up the CPU to the processes waiting to run. The caller runs again
+/// once they had their turn.
+pub fn sched_yield() -> OsResult<()> {
+    unsafe { raw::sched_yield() }
+}
+
+/// Sets the nice value of the process or thread `pid` to `nice`, from
+/// `NICE_MIN` to `NICE_MAX`. Lower values get a larger share of the CPU.
+pub fn setpriority(pid: u64, nice: i64) -> OsResult<()> {
+    unsafe { raw::setpriority(pid, nice) }
+}
+
+/// Lets the process or thread `pid` run only on the cores in `mask`, which
+/// has bit `n` set for core `n`.
+pub fn setaffinity(pid: u64, mask: u64) -> OsResult<()> {
+    unsafe { raw::setaffinity(pid, mask) }
+}
+
+/// Returns the cores the process or thread `pid` may run on, with bit `n` set
+/// for core `n`.
+pub fn getaffinity(pid: u64) -> OsResult<u64> {
+    unsafe { raw::getaffinity(pid) }
 }
 
 pub fn sbrk() -> OsResult<(usize, usize)> {
+    unsafe { raw::sbrk() }
+}
+
+/// Maps `length` bytes of zeroed memory with the `MEMORY_*` protection bits in
+/// `protection` and returns its address. The memory is placed at `address` if
+/// it is given, which must be page aligned, and wherever the kernel finds room
+/// otherwise.
+pub fn mmap(address: Option<usize>, length: usize, protection: u64) -> OsResult<usize> {
+    if address == Some(0) {
+        return Err(OsError::InvalidArgument);
+    }
+
+    unsafe { raw::mmap(address.unwrap_or(0), length, protection) }
+}
+
+/// Unmaps the `length` bytes of memory at the page aligned `address`.
+pub fn munmap(address: usize, length: usize) -> OsResult<()> {
+    unsafe { raw::munmap(address, length) }
+}
+
+/// Blocks while `word` holds `expected` until `futex_wake` is called on it,
+/// or until `timeout` passes, in which case it fails with `IoErrorTimedOut`.
+///
+/// Returns `WouldBlock` straight away if `word` does not hold `expected`.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> OsResult<()> {
+    unsafe { raw::futex_wait(word.as_ptr(), expected, timeout_millis(timeout)) }
+}
+
+/// Wakes up to `count` processes blocked in `futex_wait` on `word` and returns
+/// how many were woken.
+pub fn futex_wake(word: &AtomicU32, count: u64) -> OsResult<u64> {
+    unsafe { raw::futex_wake(word.as_ptr(), count) }
+}
+
+/// Blocks until at least one of `descriptors` is ready for one of the events
+/// it asks for, or until `timeout` passes. A timeout of zero checks without
+/// blocking.
+///
+/// Fills in `ready` for every descriptor and returns how many have a nonzero
+/// `ready`, which is zero if the timeout passed.
+pub fn poll(descriptors: &mut [PollDescriptor], timeout: Option<Duration>) -> OsResult<usize> {
     unsafe {
-        syscall!(Syscall::Sbrk);
-        let result = syscall_receive2!()?;
-        Ok((result.0 as usize, result.1 as usize))
+        raw::poll(descriptors.as_mut_ptr(), descriptors.len(), timeout_millis(timeout))
+    }
+}
+
+/// Converts a timeout to milliseconds, saturating short of `NO_TIMEOUT`.
+fn timeout_millis(timeout: Option<Duration>) -> u64 {
+    match timeout {
+        Some(timeout) => min(timeout.as_millis(), (NO_TIMEOUT - 1) as u128) as u64,
+        None => NO_TIMEOUT,
     }
 }
 
 pub fn fork() -> OsResult<Option<u64>> {
-    let (child_id, is_child) = unsafe {
-        syscall!(Syscall::Fork);
-        syscall_receive2!()?
-    };
+    let (child_id, is_child) = unsafe { raw::fork()? };
 
     match is_child {
         0 => Ok(Some(child_id)),
@@ -171,36 +406,168 @@ pub fn fork() -> OsResult<Option<u64>> {
 }
 
 pub fn duplicate(file: u64, new: u64) -> OsResult<u64> {
-    unsafe {
-        syscall_args!(file, new);
-        syscall!(Syscall::Duplicate);
-        syscall_receive1!()
-    }
+    unsafe { raw::duplicate(file, new) }
 }
 
 //TODO: this should not return on success; codify that
 pub fn execute(arguments: &[u8], environment: &[u8]) -> OsResult<u64> {
     unsafe {
-        syscall_args!(arguments.as_ptr() as u64, arguments.len() as u64,
-            environment.as_ptr() as u64, environment.len() as u64);
-        syscall!(Syscall::Execute);
-        syscall_receive1!()
+        raw::execute(arguments.as_ptr(), arguments.len(), environment.as_ptr(), environment.len())
     }
 }
 
-pub fn wait(process: u64) -> OsResult<u64> {
-    unsafe {
-        syscall_args!(process);
-        syscall!(Syscall::Wait);
-        syscall_receive1!()
+/// Blocks until the child `pid` exits, or any child if `pid` is `WAIT_ANY`,
+/// and returns the child's id and exit status.
+pub fn wait(pid: u64) -> OsResult<(u64, u64)> {
+    waitpid(pid, 0).map(|exited| exited.expect("blocking wait returned no child"))
+}
+
+/// Like `wait`, but takes `options`. With `WAIT_NO_HANG`, returns `None`
+/// instead of blocking when no matching child has exited yet.
+pub fn waitpid(pid: u64, options: u64) -> OsResult<Option<(u64, u64)>> {
+    let (id, status, exited) = unsafe { raw::wait(pid, options, ptr::null_mut())? };
+
+    match exited {
+        0 => Ok(None),
+        _ => Ok(Some((id, status))),
+    }
+}
+
+/// Like `waitpid`, but also returns what the child used, along with the
+/// children it reaped.
+pub fn waitpid_usage(pid: u64, options: u64) -> OsResult<Option<(u64, u64, Usage)>> {
+    let mut usage = Usage::default();
+    let (id, status, exited) = unsafe { raw::wait(pid, options, &mut usage)? };
+
+    match exited {
+        0 => Ok(None),
+        _ => Ok(Some((id, status, usage))),
     }
 }
 
+/// Sends `signal` to the process `pid`.
+pub fn kill(pid: u64, signal: Signal) -> OsResult<()> {
+    unsafe { raw::kill(pid, signal as u64) }
+}
+
+/// What to do when a signal arrives.
+#[derive(Copy, Clone)]
+pub enum SignalHandler {
+    /// Take the default action of the signal.
+    Default,
+    /// Discard the signal.
+    Ignore,
+    /// Call the function with the signal number. The signal is blocked until
+    /// the function returns.
+    Handle(extern "C" fn(u64)),
+}
+
+/// Sets how the current process handles `signal`. `Kill` and `Stop` cannot
+/// be handled.
+pub fn signal(signal: Signal, handler: SignalHandler) -> OsResult<()> {
+    let handler = match handler {
+        SignalHandler::Default => SIGNAL_DEFAULT,
+        SignalHandler::Ignore => SIGNAL_IGNORE,
+        SignalHandler::Handle(function) => function as usize as u64,
+    };
+    let restorer: extern "C" fn() -> ! = signal_return;
+
+    unsafe { raw::sigaction(signal as u64, handler, restorer as usize) }
+}
+
+/// Changes the set of blocked signals and returns the previous set. `how` is
+/// one of `SIGNAL_BLOCK`, `SIGNAL_UNBLOCK` or `SIGNAL_SET_MASK`, and `mask`
+/// is built from `Signal::mask`.
+pub fn sigprocmask(how: u64, mask: u64) -> OsResult<u64> {
+    unsafe { raw::sigprocmask(how, mask) }
+}
+
+/// Where signal handlers return to. Restores the context the handler
+/// interrupted and so never returns itself.
+extern "C" fn signal_return() -> ! {
+    let _ = unsafe { raw::sigreturn() };
+    unreachable!("sigreturn returned")
+}
+
+/// Asks to be traced by the parent. The current process stops for the parent
+/// after its next successful `execute`, before the new program runs.
+pub fn trace_me() -> OsResult<()> {
+    trace(TraceRequest::Me, 0, 0, 0).map(|_| ())
+}
+
+/// Starts tracing the child `pid`. It stops, reporting `TraceEvent::Attached`,
+/// the next time it returns to user space.
+pub fn trace_attach(pid: u64) -> OsResult<()> {
+    trace(TraceRequest::Attach, pid, 0, 0).map(|_| ())
+}
+
+/// Stops tracing `pid` and resumes it if it is stopped.
+pub fn trace_detach(pid: u64) -> OsResult<()> {
+    trace(TraceRequest::Detach, pid, 0, 0).map(|_| ())
+}
+
+/// Resumes the stopped process `pid` until its next breakpoint.
+pub fn trace_continue(pid: u64) -> OsResult<()> {
+    trace(TraceRequest::Continue, pid, 0, 0).map(|_| ())
+}
+
+/// Resumes the stopped process `pid` until it enters or leaves a system call.
+pub fn trace_syscall(pid: u64) -> OsResult<()> {
+    trace(TraceRequest::Syscall, pid, 0, 0).map(|_| ())
+}
+
+/// Resumes the stopped process `pid` for a single instruction.
+pub fn trace_step(pid: u64) -> OsResult<()> {
+    trace(TraceRequest::Step, pid, 0, 0).map(|_| ())
+}
+
+/// Returns the registers of the stopped process `pid`.
+pub fn trace_get_registers(pid: u64) -> OsResult<Registers> {
+    let mut registers = Registers::default();
+    trace(TraceRequest::GetRegisters, pid, &mut registers as *mut Registers as u64, 0)?;
+    Ok(registers)
+}
+
+/// Replaces the registers of the stopped process `pid`. Only the condition
+/// flags of `pstate` are taken.
+pub fn trace_set_registers(pid: u64, registers: &Registers) -> OsResult<()> {
+    trace(TraceRequest::SetRegisters, pid, registers as *const Registers as u64, 0).map(|_| ())
+}
+
+/// Reads the `u64` at `address` in the stopped process `pid`.
+pub fn trace_read(pid: u64, address: u64) -> OsResult<u64> {
+    trace(TraceRequest::Read, pid, address, 0)
+}
+
+/// Writes `value` to `address` in the stopped process `pid`, even if the
+/// process may only read or execute the memory there.
+pub fn trace_write(pid: u64, address: u64, value: u64) -> OsResult<()> {
+    trace(TraceRequest::Write, pid, address, value).map(|_| ())
+}
+
+fn trace(request: TraceRequest, pid: u64, address: u64, data: u64) -> OsResult<u64> {
+    unsafe { raw::trace(request as u64, pid, address, data) }
+}
+
+/// Blocks until the traced process `pid`, or any traced process if `pid` is
+/// `WAIT_ANY`, stops or exits, and returns its id and why.
+pub fn trace_wait(pid: u64) -> OsResult<(u64, TraceEvent)> {
+    let (id, kind, detail) = unsafe { raw::trace_wait(pid)? };
+    let event = TraceEvent::from_raw(kind, detail).ok_or(OsError::Unknown)?;
+    Ok((id, event))
+}
+
 struct Console;
 
 impl Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        write(1, s.as_bytes()).expect("unable to write data");
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            match write(1, bytes) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(written) => bytes = &bytes[written..],
+            }
+        }
         Ok(())
     }
 }
@@ -238,21 +605,24 @@ impl File {
 impl io::Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match read(self.0, buf) {
-            Ok(_) => Ok(buf.len()),
-            Err(_) => ioerr!(Interrupted),
+            Err(OsError::IoErrorEof) => Ok(0),
+            result => Ok(result?),
         }
     }
 }
 
 impl io::Write for File {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match write(self.0, buf) {
-            Ok(_) => Ok(buf.len()),
-            Err(_) => ioerr!(Interrupted),
-        }
+        Ok(write(self.0, buf)?)
     }
 
     fn flush(&mut self) -> io::Result<()> {
         ioerr!(Unsupported)
     }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(seek(self.0, pos)?)
+    }
 }
\ No newline at end of file
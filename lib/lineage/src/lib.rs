@@ -0,0 +1,193 @@
+#![cfg_attr(not(test), no_std)]
+
+//! The family tree of processes: who created whom, and what the processes that
+//! exited left for their parents to collect.
+//!
+//! The kernel keeps this apart from the processes themselves so a process can
+//! be dropped, and everything it holds released, as soon as it exits, while
+//! its parent may still wait for it.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+
+#[cfg(test)]
+mod tests;
+
+/// Identifies a process.
+pub type Pid = u64;
+
+/// Returned by `Lineage::reap` when the parent has no child it could wait for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoChild;
+
+/// What the caller of `Lineage::exit` has to act on.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Exit {
+    /// PIDs nobody will reap, free for reuse: the exiting process if it has
+    /// no parent, and the zombies it leaves when there is no init to take them
+    pub released: Vec<Pid>,
+    /// Processes that have a new zombie child to reap
+    pub reapers: Vec<Pid>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    parent: Option<Pid>,
+    children: Vec<Pid>,
+    /// What the process left when it exited; `Some` for a zombie
+    exited: Option<T>,
+}
+
+/// The parent and children of every process, and the zombies.
+///
+/// A process that exits becomes a zombie holding what it left, such as its
+/// exit status, until its parent reaps it. Its PID stays taken until then, so
+/// the parent cannot mistake a new process for it. The children of a process
+/// that exits are reparented to init, which reaps them in turn. A process with
+/// no parent is forgotten as soon as it exits.
+#[derive(Debug)]
+pub struct Lineage<T> {
+    init: Pid,
+    nodes: BTreeMap<Pid, Node<T>>,
+}
+
+impl<T> Lineage<T> {
+    /// Creates an empty lineage where orphans are reparented to `init`.
+    pub fn new(init: Pid) -> Self {
+        Lineage { init, nodes: BTreeMap::new() }
+    }
+
+    /// Records that `pid` was created by `parent`, or has no parent if it is
+    /// `None` or not a live process.
+    pub fn add(&mut self, pid: Pid, parent: Option<Pid>) {
+        let parent = parent.filter(|parent| match self.nodes.get_mut(parent) {
+            Some(node) if node.exited.is_none() => {
+                node.children.push(pid);
+                true
+            }
+            _ => false,
+        });
+
+        self.nodes.insert(pid, Node { parent, children: Vec::new(), exited: None });
+    }
+
+    /// Returns the parent of `pid`.
+    pub fn parent(&self, pid: Pid) -> Option<Pid> {
+        self.nodes.get(&pid)?.parent
+    }
+
+    /// Returns the children of `pid`, live and zombie, in the order they came.
+    pub fn children(&self, pid: Pid) -> &[Pid] {
+        self.nodes.get(&pid).map_or(&[], |node| &node.children)
+    }
+
+    /// Returns whether `pid` exited and waits to be reaped.
+    pub fn is_zombie(&self, pid: Pid) -> bool {
+        self.nodes.get(&pid).is_some_and(|node| node.exited.is_some())
+    }
+
+    /// Records that `pid` exited, leaving `exited` for its parent to reap, and
+    /// reparents its children to init.
+    ///
+    /// A PID that was never added, such as a thread's, is released right
+    /// away, and `exited` is dropped.
+    pub fn exit(&mut self, pid: Pid, exited: T) -> Exit {
+        let mut exit = Exit::default();
+        let node = match self.nodes.get_mut(&pid) {
+            Some(node) if node.exited.is_none() => node,
+            Some(_) => return exit,
+            None => {
+                exit.released.push(pid);
+                return exit;
+            }
+        };
+
+        let children = mem::take(&mut node.children);
+        match node.parent {
+            Some(parent) => {
+                node.exited = Some(exited);
+                exit.reapers.push(parent);
+            }
+            None => {
+                self.nodes.remove(&pid);
+                exit.released.push(pid);
+            }
+        }
+
+        let init = self.nodes.get(&self.init)
+            .filter(|node| self.init != pid && node.exited.is_none())
+            .map(|_| self.init);
+        for child in children {
+            let zombie = self.is_zombie(child);
+            match init {
+                Some(init) => {
+                    self.reparent(child, Some(init));
+                    if zombie && !exit.reapers.contains(&init) {
+                        exit.reapers.push(init);
+                    }
+                }
+                None if zombie => {
+                    self.nodes.remove(&child);
+                    exit.released.push(child);
+                }
+                None => self.reparent(child, None),
+            }
+        }
+
+        exit
+    }
+
+    /// Reaps a zombie child of `parent`: `child`, or any child if it is
+    /// `None`. Forgets the zombie and returns its PID, now free for reuse,
+    /// with what it left.
+    ///
+    /// Returns `None` if a matching child exists but has not exited yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoChild` if no child of `parent` matches.
+    pub fn reap(&mut self, parent: Pid, child: Option<Pid>) -> Result<Option<(Pid, T)>, NoChild> {
+        let children = &self.nodes.get(&parent).ok_or(NoChild)?.children;
+        let matching: Vec<Pid> = children.iter().copied()
+            .filter(|&id| child.is_none_or(|child| child == id))
+            .collect();
+        if matching.is_empty() {
+            return Err(NoChild);
+        }
+
+        let zombie = matching.into_iter().find(|id| self.is_zombie(*id));
+        let zombie = match zombie {
+            Some(zombie) => zombie,
+            None => return Ok(None),
+        };
+
+        if let Some(node) = self.nodes.get_mut(&parent) {
+            node.children.retain(|&id| id != zombie);
+        }
+        Ok(self.nodes.remove(&zombie)
+            .and_then(|node| node.exited)
+            .map(|exited| (zombie, exited)))
+    }
+
+    /// Returns the number of processes recorded, live and zombie.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns whether no process is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn reparent(&mut self, pid: Pid, parent: Option<Pid>) {
+        if let Some(node) = self.nodes.get_mut(&pid) {
+            node.parent = parent;
+        }
+        if let Some(node) = parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+            node.children.push(pid);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::{Exit, Lineage, NoChild, Pid};
+
+const INIT: Pid = 0;
+
+/// Counts how many times the values it is cloned into are dropped.
+#[derive(Debug, Clone)]
+struct Tracked(Rc<Cell<usize>>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+fn lineage() -> Lineage<u64> {
+    let mut lineage = Lineage::new(INIT);
+    lineage.add(INIT, None);
+    lineage
+}
+
+#[test]
+fn test_zombie_until_reaped() {
+    let mut lineage = lineage();
+    lineage.add(1, Some(INIT));
+    assert_eq!(lineage.reap(INIT, Some(1)), Ok(None));
+
+    let exit = lineage.exit(1, 42);
+    assert_eq!(exit, Exit { released: vec![], reapers: vec![INIT] });
+    assert!(lineage.is_zombie(1));
+    assert_eq!(lineage.children(INIT), &[1]);
+
+    assert_eq!(lineage.reap(INIT, Some(1)), Ok(Some((1, 42))));
+    assert!(!lineage.is_zombie(1));
+    assert_eq!(lineage.children(INIT), &[]);
+    assert_eq!(lineage.reap(INIT, Some(1)), Err(NoChild));
+    assert_eq!(lineage.len(), 1);
+}
+
+#[test]
+fn test_reap_selects_child() {
+    let mut lineage = lineage();
+    lineage.add(1, Some(INIT));
+    lineage.add(2, Some(INIT));
+    lineage.add(3, Some(1));
+
+    assert_eq!(lineage.reap(INIT, Some(3)), Err(NoChild));
+    assert_eq!(lineage.reap(2, None), Err(NoChild));
+    assert_eq!(lineage.reap(7, None), Err(NoChild));
+
+    lineage.exit(2, 2);
+    assert_eq!(lineage.reap(INIT, Some(1)), Ok(None));
+    assert_eq!(lineage.reap(INIT, None), Ok(Some((2, 2))));
+    assert_eq!(lineage.reap(INIT, None), Ok(None));
+}
+
+#[test]
+fn test_orphans_reparented_to_init() {
+    let mut lineage = lineage();
+    lineage.add(1, Some(INIT));
+    lineage.add(2, Some(1));
+    lineage.add(3, Some(1));
+    lineage.add(4, Some(3));
+    lineage.exit(2, 2);
+
+    let exit = lineage.exit(1, 1);
+    assert_eq!(exit, Exit { released: vec![], reapers: vec![INIT] });
+    assert_eq!(lineage.parent(2), Some(INIT));
+    assert_eq!(lineage.parent(3), Some(INIT));
+    assert_eq!(lineage.parent(4), Some(3));
+    assert_eq!(lineage.children(1), &[]);
+    assert_eq!(lineage.reap(1, None), Err(NoChild));
+
+    let mut reaped = vec![];
+    while let Ok(Some((pid, status))) = lineage.reap(INIT, None) {
+        assert_eq!(pid, status);
+        reaped.push(pid);
+    }
+    assert_eq!(reaped, vec![1, 2]);
+    assert_eq!(lineage.children(INIT), &[3]);
+
+    let exit = lineage.exit(3, 3);
+    assert_eq!(exit.reapers, vec![INIT]);
+    assert_eq!(lineage.parent(4), Some(INIT));
+}
+
+#[test]
+fn test_no_parent_released() {
+    let mut lineage = lineage();
+    lineage.add(1, Some(INIT));
+    lineage.add(2, Some(1));
+    lineage.add(3, Some(1));
+    lineage.exit(3, 3);
+
+    let exit = lineage.exit(INIT, 0);
+    assert_eq!(exit, Exit { released: vec![INIT], reapers: vec![] });
+    assert_eq!(lineage.parent(1), None);
+
+    let exit = lineage.exit(1, 1);
+    assert_eq!(exit, Exit { released: vec![1, 3], reapers: vec![] });
+    assert_eq!(lineage.parent(2), None);
+    assert_eq!(lineage.exit(2, 2), Exit { released: vec![2], reapers: vec![] });
+    assert!(lineage.is_empty());
+
+    assert_eq!(lineage.exit(9, 9), Exit { released: vec![9], reapers: vec![] });
+    assert!(lineage.is_empty());
+}
+
+#[test]
+fn test_exit_twice_ignored() {
+    let mut lineage = lineage();
+    lineage.add(1, Some(INIT));
+    lineage.exit(1, 1);
+    assert_eq!(lineage.exit(1, 7), Exit::default());
+    assert_eq!(lineage.reap(INIT, None), Ok(Some((1, 1))));
+}
+
+#[test]
+fn test_no_leaks() {
+    let drops = Rc::new(Cell::new(0));
+    let mut lineage = Lineage::new(INIT);
+    lineage.add(INIT, None);
+
+    // A deterministic mix of forks, exits and waits: every PID must come back
+    // for reuse exactly once, through a reap or a release, and every exit
+    // record must be dropped exactly once.
+    let mut live: Vec<Pid> = vec![INIT];
+    let mut next: Pid = 1;
+    let mut exited = 0;
+    let mut freed = BTreeSet::new();
+    let mut free = |pid: Pid| assert!(freed.insert(pid), "{} freed twice", pid);
+
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut random = |bound: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize % bound
+    };
+
+    for _ in 0..2000 {
+        let pid = live[random(live.len())];
+        match random(4) {
+            0 | 1 if live.len() < 64 => {
+                lineage.add(next, Some(pid));
+                live.push(next);
+                next += 1;
+            }
+            2 if pid != INIT => {
+                let exit = lineage.exit(pid, Tracked(drops.clone()));
+                exit.released.into_iter().for_each(&mut free);
+                live.retain(|&id| id != pid);
+                exited += 1;
+            }
+            _ => {
+                if let Ok(Some((child, record))) = lineage.reap(pid, None) {
+                    drop(record);
+                    free(child);
+                }
+            }
+        }
+    }
+
+    for pid in live.into_iter().rev() {
+        let exit = lineage.exit(pid, Tracked(drops.clone()));
+        exit.released.into_iter().for_each(&mut free);
+        exited += 1;
+        while let Ok(Some((child, record))) = lineage.reap(INIT, None) {
+            drop(record);
+            free(child);
+        }
+    }
+
+    assert!(lineage.is_empty());
+    assert_eq!(freed.len() as u64, next);
+    assert_eq!(drops.get(), exited);
+}
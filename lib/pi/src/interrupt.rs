@@ -10,6 +10,7 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
@@ -18,11 +19,11 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 9;
 
     pub fn iter() -> impl Iterator<Item=Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
+        [Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
             .iter()
             .map(|int| *int)
     }
@@ -40,6 +41,7 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
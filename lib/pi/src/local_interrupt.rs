@@ -114,8 +114,19 @@ impl LocalController {
             aarch64::CNTP_CTL_EL0.set(aarch64::CNTP_CTL_EL0.get() & (!aarch64::CNTP_CTL_EL0::IMASK));
         }
     }
+
+    /// Masks the timer interrupt until the next `tick_in`.
+    pub fn stop_tick(&mut self) {
+        unsafe {
+            aarch64::CNTP_CTL_EL0.set(aarch64::CNTP_CTL_EL0.get() | aarch64::CNTP_CTL_EL0::IMASK);
+        }
+    }
 }
 
 pub fn local_tick_in(core: usize, t: Duration) {
     LocalController::new(core).tick_in(t)
 }
+
+pub fn local_tick_stop(core: usize) {
+    LocalController::new(core).stop_tick()
+}
@@ -17,6 +17,11 @@ const MU_REG_BASE: usize = IO_BASE + 0x215040;
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
 const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
 
+/// The bits of the `AUX_MU_IER_REG` register that enable the receive
+/// interrupt. Bits 3:2 are documented as unused but are needed for the
+/// interrupt to be raised (BCM2835 errata).
+const IER_RECEIVE: u8 = 0b1101;
+
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
 enum LsrStatus {
@@ -91,6 +96,12 @@ impl MiniUart {
         self.timeout = Some(t);
     }
 
+    /// Switches the interrupt raised while there is a byte to read on or off.
+    /// It is shared with the other auxiliary peripherals as `Interrupt::Aux`.
+    pub fn set_receive_interrupt(&mut self, enabled: bool) {
+        self.registers.MU_IER.write(if enabled { IER_RECEIVE } else { 0 });
+    }
+
     pub fn can_write(&mut self) -> bool {
         (self.registers.MU_LSR.read() & (LsrStatus::TxAvailable as u8)) != 0
     }
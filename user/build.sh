@@ -4,7 +4,7 @@ IMG=fs.img
 MNT=mnt
 ROOT=$(git rev-parse --show-toplevel)
 
-PROGS=(cat echo fib heap init shell stack)
+PROGS=(cat echo env fib heap init kill mkdir mv nice rm rmdir shell stack strace threads time top touch)
 
 dd if=/dev/zero of=$IMG bs=1MB count=128
 echo -e "n\np\n1\n\n\nt\nc\nw\n" | fdisk $IMG
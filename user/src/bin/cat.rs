@@ -5,8 +5,8 @@
 
 extern crate alloc;
 
-use kernel_api::println;
-use kernel_api::syscall::{File, open, write};
+use kernel_api::{OPEN_READ, println};
+use kernel_api::syscall::{File, open};
 use shim::io::{Read, Write};
 
 use crate::user::get_arguments;
@@ -14,25 +14,28 @@ use crate::user::get_arguments;
 mod user;
 
 fn cat(mut file: File) {
+    let mut stdout = File::new(1);
     let mut data = [0u8; 128];
-    while {
+    loop {
         match file.read(&mut data) {
+            Ok(0) => break,
             Ok(n) => {
-                let _ = write(1, &data[..n]);
-                n == 128
+                if stdout.write_all(&data[..n]).is_err() {
+                    break;
+                }
             }
             Err(e) => {
                 println!("unable to read file {:?}", e);
-                false
+                break;
             }
         }
-    } {}
+    }
 }
 
 fn main() {
     match get_arguments().skip(1).next() {
         Some(file) => {
-            match open(file.trim_matches(0 as char)) {
+            match open(file.trim_matches(0 as char), OPEN_READ) {
                 Ok(id) => {
                     cat(File::new(id));
                 }
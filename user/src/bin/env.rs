@@ -0,0 +1,16 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use kernel_api::{env, println};
+
+mod user;
+
+fn main() {
+    for (name, value) in env::vars() {
+        println!("{}={}", name, value);
+    }
+}
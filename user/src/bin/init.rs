@@ -7,13 +7,13 @@ extern crate alloc;
 
 use alloc::string::ToString;
 
-use kernel_api::println;
+use kernel_api::{OPEN_READ, OPEN_WRITE, WAIT_ANY, println};
 use kernel_api::syscall::{duplicate, execute, fork, open, wait};
 
 mod user;
 
 fn main() {
-    let console = open("/console").expect("unable to open console");
+    let console = open("/console", OPEN_READ | OPEN_WRITE).expect("unable to open console");
     duplicate(console, 1).expect("unable to duplicate console");
     duplicate(console, 2).expect("unable to duplicate console");
 
@@ -24,15 +24,18 @@ fn main() {
 
         match shell_pid {
             None => {
-                execute("shell".as_bytes(), "".as_bytes())
+                execute("shell".as_bytes(), "PATH=/bin:/".as_bytes())
                     .expect("unable to execute shell");
             }
             Some(child_pid) => {
-                while {
-                    let wait_pid = wait(child_pid)
-                        .expect("unable to wait for process");
-                    wait_pid != child_pid
-                } {}
+                // Orphans are reparented to init, so reap them along the way.
+                loop {
+                    let (pid, status) = wait(WAIT_ANY).expect("unable to wait for process");
+                    if pid == child_pid {
+                        println!("init: shell exited with status {}", status);
+                        break;
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,36 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use kernel_api::{println, Signal};
+use kernel_api::syscall::kill;
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    let mut signal = Signal::Terminate;
+    for argument in get_arguments().skip(1) {
+        let argument = argument.trim_matches(0 as char);
+        if let Some(number) = argument.strip_prefix('-') {
+            match number.parse().ok().and_then(Signal::from_u64) {
+                Some(new_signal) => signal = new_signal,
+                None => println!("kill: unknown signal {}", number),
+            }
+            continue;
+        }
+
+        match argument.parse() {
+            Ok(pid) => {
+                if let Err(e) = kill(pid, signal) {
+                    println!("kill: unable to signal {}: {:?}", pid, e);
+                }
+            }
+            Err(_) => println!("kill: invalid process id {}", argument),
+        }
+    }
+}
@@ -0,0 +1,22 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use kernel_api::println;
+use kernel_api::syscall::mkdir;
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    for path in get_arguments().skip(1) {
+        let path = path.trim_matches(0 as char);
+        if let Err(e) = mkdir(path) {
+            println!("mkdir: unable to create directory {}: {:?}", path, e);
+        }
+    }
+}
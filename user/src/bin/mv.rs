@@ -0,0 +1,27 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use kernel_api::println;
+use kernel_api::syscall::rename;
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    let mut arguments = get_arguments().skip(1);
+    match (arguments.next(), arguments.next()) {
+        (Some(from), Some(to)) => {
+            let from = from.trim_matches(0 as char);
+            let to = to.trim_matches(0 as char);
+            if let Err(e) = rename(from, to) {
+                println!("mv: unable to move {} to {}: {:?}", from, to, e);
+            }
+        }
+        _ => println!("usage: mv <from> <to>"),
+    }
+}
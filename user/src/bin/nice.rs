@@ -0,0 +1,53 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use kernel_api::{env, println, NICE_MAX, NICE_MIN};
+use kernel_api::syscall::{execute, exit, getpid, setpriority};
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    let mut arguments: Vec<&str> = get_arguments().skip(1)
+        .map(|argument| argument.trim_matches(0 as char))
+        .collect();
+
+    let mut nice = 10;
+    if arguments.first() == Some(&"-n") && arguments.len() > 1 {
+        match arguments[1].parse() {
+            Ok(value) if (NICE_MIN..=NICE_MAX).contains(&value) => nice = value,
+            _ => {
+                println!("nice: invalid nice value {}", arguments[1]);
+                return;
+            }
+        }
+        arguments.drain(..2);
+    }
+
+    if arguments.is_empty() {
+        println!("usage: nice [-n value] <program> [arguments..]");
+        return;
+    }
+
+    let pid = getpid().expect("could not get pid");
+    if let Err(e) = setpriority(pid, nice) {
+        println!("nice: unable to set nice value {}: {:?}", nice, e);
+        return;
+    }
+
+    let environment: Vec<_> = env::vars()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if let Err(e) = execute(arguments.join("\0").as_bytes(), environment.join("\0").as_bytes()) {
+        println!("nice: unable to run {}: {:?}", arguments[0], e);
+        exit(127).expect("could not exit");
+    }
+}
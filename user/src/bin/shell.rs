@@ -6,11 +6,12 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-use kernel_api::{print, println};
-use kernel_api::syscall::{execute, exit, File, fork, wait};
+use kernel_api::{env, print, println};
+use kernel_api::syscall::{chdir, execute, exit, File, fork, getcwd, wait};
 use shim::io::{Read, Write};
 
 mod user;
@@ -19,6 +20,9 @@ fn main() {
     let mut stdin = File::new(0);
     let mut stdout = File::new(1);
     let mut commands: Vec<String> = Vec::new();
+    let mut environment: Vec<String> = env::vars()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
 
     loop {
         let mut byte = [0u8; 1];
@@ -28,12 +32,12 @@ fn main() {
         print!("shell > ");
 
         while {
-            stdin.read(&mut byte).expect("could not read stdio");
+            stdin.read_exact(&mut byte).expect("could not read stdio");
 
             // TODO: work for serial escape codes broader than arrow keys
             if byte[0] == 27 {
                 let mut escape_bytes = [0u8; 2];
-                stdin.read(&mut escape_bytes).expect("could not read stdio");
+                stdin.read_exact(&mut escape_bytes).expect("could not read stdio");
                 // up 91, 65
                 // right 91, 67
                 // down 91, 66
@@ -69,11 +73,11 @@ fn main() {
                 match command.pop() {
                     None => {}
                     Some(_) => {
-                        stdout.write("\x08 \x08".as_bytes()).expect("could not write to stdout");
+                        stdout.write_all("\x08 \x08".as_bytes()).expect("could not write to stdout");
                     }
                 }
             } else {
-                stdout.write(&byte).expect("could not write to stdout");
+                stdout.write_all(&byte).expect("could not write to stdout");
                 command.push(byte[0] as char);
             }
 
@@ -88,6 +92,42 @@ fn main() {
             break;
         }
 
+        let mut words = command.split(' ').filter(|word| !word.is_empty());
+        match words.next() {
+            Some("cd") => {
+                println!();
+                let directory = words.next().unwrap_or("/");
+                if let Err(e) = chdir(directory) {
+                    println!("cd: unable to change directory to {}: {:?}", directory, e);
+                }
+                continue;
+            }
+            Some("export") => {
+                println!();
+                for variable in words {
+                    match variable.split_once('=') {
+                        Some((name, _)) if !name.is_empty() => {
+                            let prefix = format!("{}=", name);
+                            environment.retain(|existing| !existing.starts_with(prefix.as_str()));
+                            environment.push(variable.to_string());
+                        }
+                        _ => println!("export: expected NAME=VALUE, got {}", variable),
+                    }
+                }
+                continue;
+            }
+            Some("pwd") => {
+                println!();
+                let mut buffer = [0u8; 256];
+                match getcwd(&mut buffer) {
+                    Ok(directory) => println!("{}", directory),
+                    Err(e) => println!("pwd: unable to get current directory: {:?}", e),
+                }
+                continue;
+            }
+            _ => {}
+        }
+
         //if let Some(command) = CommandParser::new(command).parse() {
         //    if command.run() {
         //        break;
@@ -106,12 +146,13 @@ fn main() {
                         _ => c as u8,
                     }
                 }).collect();
-                match execute(encoded.as_slice(), "".as_ref()) {
+                let environment = environment.join("\0");
+                match execute(encoded.as_slice(), environment.as_bytes()) {
                     Ok(_) => {}
                     Err(_) => {
                         println!("no such command {}",
                                  command.split(' ').next().unwrap_or(""));
-                        exit().expect("could not exit");
+                        exit(127).expect("could not exit");
                     }
                 }
             }
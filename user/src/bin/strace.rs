@@ -0,0 +1,78 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use kernel_api::{env, print, println, OsError, Syscall, TraceEvent};
+use kernel_api::syscall::{execute, exit, fork, trace_get_registers, trace_me, trace_syscall, trace_wait, wait};
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    let arguments: Vec<&str> = get_arguments().skip(1)
+        .map(|argument| argument.trim_matches(0 as char))
+        .collect();
+    if arguments.is_empty() {
+        println!("usage: strace <program> [arguments..]");
+        return;
+    }
+
+    match fork().expect("could not fork") {
+        None => {
+            let environment: Vec<_> = env::vars()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            trace_me().expect("could not ask to be traced");
+            if let Err(e) = execute(arguments.join("\0").as_bytes(), environment.join("\0").as_bytes()) {
+                println!("strace: unable to run {}: {:?}", arguments[0], e);
+                exit(127).expect("could not exit");
+            }
+        }
+        Some(child) => {
+            trace(child);
+            let _ = wait(child);
+        }
+    }
+}
+
+/// Prints every system call `child` makes, with its first four arguments and
+/// its result, until it exits.
+fn trace(child: u64) {
+    loop {
+        match trace_wait(child) {
+            Ok((_, TraceEvent::SyscallEntry(number))) => {
+                let xs = trace_get_registers(child).map(|registers| registers.xs).unwrap_or_default();
+                print!("{:?}({:#x}, {:#x}, {:#x}, {:#x})",
+                       Syscall::from(number), xs[0], xs[1], xs[2], xs[3]);
+            }
+            Ok((_, TraceEvent::SyscallExit(_))) => {
+                let xs = trace_get_registers(child).map(|registers| registers.xs).unwrap_or_default();
+                match OsError::from(xs[7]) {
+                    OsError::Ok => println!(" = {:#x}", xs[0]),
+                    e => println!(" = {:?}", e),
+                }
+            }
+            Ok((_, TraceEvent::Exited(status))) => {
+                println!("+++ exited with {} +++", status);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("strace: unable to wait for {}: {:?}", child, e);
+                return;
+            }
+        }
+
+        if let Err(e) = trace_syscall(child) {
+            println!("strace: unable to resume {}: {:?}", child, e);
+            return;
+        }
+    }
+}
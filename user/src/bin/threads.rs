@@ -0,0 +1,41 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+use kernel_api::println;
+use kernel_api::syscall::{gettid, thread_exit, thread_join, thread_spawn, time};
+
+mod user;
+
+const THREADS: u64 = 4;
+
+fn fib(n: u64) -> u64 {
+    match n {
+        0 => 1,
+        1 => 1,
+        n => fib(n - 1) + fib(n - 2),
+    }
+}
+
+extern "C" fn worker(n: u64) -> ! {
+    println!("[{}] fib({})", gettid(), n);
+    thread_exit(fib(n))
+}
+
+fn main() {
+    let beg = time().expect("unable to get time");
+
+    let mut threads = [0; THREADS as usize];
+    for (i, thread) in threads.iter_mut().enumerate() {
+        *thread = thread_spawn(worker, 30 + i as u64, 0).expect("unable to spawn thread");
+    }
+
+    for thread in threads.iter() {
+        let result = thread_join(*thread).expect("unable to join thread");
+        println!("[{}] Result: {}", thread, result);
+    }
+
+    let end = time().expect("unable to get time");
+    println!("Elapsed: {}", (end - beg).as_millis());
+}
@@ -0,0 +1,66 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::{env, println};
+use kernel_api::syscall::{execute, exit, fork, time, waitpid_usage};
+
+use crate::user::get_arguments;
+
+mod user;
+
+fn main() {
+    let arguments: Vec<&str> = get_arguments().skip(1)
+        .map(|argument| argument.trim_matches(0 as char))
+        .collect();
+    if arguments.is_empty() {
+        println!("usage: time <program> [arguments..]");
+        return;
+    }
+
+    let start = time().expect("could not read the time");
+    match fork().expect("could not fork") {
+        None => {
+            let environment: Vec<_> = env::vars()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            if let Err(e) = execute(arguments.join("\0").as_bytes(), environment.join("\0").as_bytes()) {
+                println!("time: unable to run {}: {:?}", arguments[0], e);
+                exit(127).expect("could not exit");
+            }
+        }
+        Some(child) => {
+            let (_, status, usage) = match waitpid_usage(child, 0) {
+                Ok(Some(exited)) => exited,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("time: unable to wait for {}: {:?}", child, e);
+                    return;
+                }
+            };
+            let real = time().expect("could not read the time").saturating_sub(start);
+
+            println!("real {}", seconds(real));
+            println!("user {}", seconds(usage.user()));
+            println!("sys  {}", seconds(usage.system()));
+            println!("{} switches, {} system calls, {} pages at most",
+                     usage.switches, usage.syscalls, usage.max_pages);
+            if status != 0 {
+                exit(status).expect("could not exit");
+            }
+        }
+    }
+}
+
+/// Formats `span` as seconds with three decimals.
+fn seconds(span: Duration) -> String {
+    format!("{}.{:03}s", span.as_secs(), span.subsec_millis())
+}
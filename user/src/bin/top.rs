@@ -0,0 +1,79 @@
+#![feature(alloc_error_handler)]
+#![feature(prelude_2024)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::{print, println, Usage};
+use kernel_api::syscall::{next_usage, sleep, time};
+
+use crate::user::get_arguments;
+
+mod user;
+
+/// How long `top` waits between two listings.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() {
+    let arguments: Vec<&str> = get_arguments().skip(1)
+        .map(|argument| argument.trim_matches(0 as char))
+        .collect();
+
+    let mut iterations = 5;
+    match arguments.as_slice() {
+        [] => {}
+        ["-n", count] => match count.parse() {
+            Ok(count) if count > 0 => iterations = count,
+            _ => {
+                println!("top: invalid count {}", count);
+                return;
+            }
+        },
+        _ => {
+            println!("usage: top [-n iterations]");
+            return;
+        }
+    }
+
+    let mut previous = BTreeMap::new();
+    let mut last = time().expect("could not read the time");
+    for iteration in 0..iterations {
+        if iteration > 0 {
+            sleep(INTERVAL).expect("could not sleep");
+        }
+        let now = time().expect("could not read the time");
+        let elapsed = now.saturating_sub(last).as_micros().max(1) as u64;
+        last = now;
+
+        println!("{:>5} {:>6} {:>10} {:>10} {:>6} {:>9} {:>9}",
+                 "PID", "%CPU", "USER", "SYS", "PAGES", "SWITCHES", "SYSCALLS");
+        let mut current = BTreeMap::new();
+        for (pid, usage) in processes() {
+            let ran = usage.user_time + usage.system_time;
+            let before = previous.get(&pid).copied().unwrap_or(0);
+            let cpu = ran.saturating_sub(before) * 1000 / elapsed;
+            println!("{:>5} {:>4}.{} {:>8}ms {:>8}ms {:>6} {:>9} {:>9}",
+                     pid, cpu / 10, cpu % 10, usage.user_time / 1000, usage.system_time / 1000,
+                     usage.pages, usage.switches, usage.syscalls);
+            current.insert(pid, ran);
+        }
+        println!();
+        previous = current;
+    }
+}
+
+/// Returns every process with what it used, by id.
+fn processes() -> Vec<(u64, Usage)> {
+    let mut processes = Vec::new();
+    let mut pid = 0;
+    while let Ok((id, usage)) = next_usage(pid) {
+        processes.push((id, usage));
+        pid = id + 1;
+    }
+    processes
+}
@@ -5,16 +5,18 @@ use core::mem;
 use core::mem::zeroed;
 use core::panic::PanicInfo;
 use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use kernel_api::println;
-use kernel_api::syscall::{exit, sbrk, write};
+use kernel_api::{MEMORY_READ, MEMORY_WRITE};
+use kernel_api::syscall::{exit, mmap, write};
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     let _ = write(1, "panicked\n".as_bytes());
     //TODO: remove
     println!("{:?}", info);
-    close();
+    close(101);
 }
 
 #[alloc_error_handler]
@@ -41,16 +43,20 @@ unsafe fn zeros_bss() {
 pub unsafe extern "C" fn _start() -> ! {
     zeros_bss();
     crate::main();
-    close();
+    close(0);
 }
 
-fn close() -> ! {
+fn close(status: u64) -> ! {
     loop {
-        let _ = exit();
+        let _ = exit(status);
     }
 }
 
-struct InnerAlloc(UnsafeCell<(usize, usize)>);
+/// The smallest amount of memory the allocator asks the kernel for at once.
+const ALLOCATION_CHUNK: usize = 64 * 1024;
+
+/// The bump region, guarded by a spin lock since threads share the allocator.
+struct InnerAlloc(UnsafeCell<(usize, usize)>, AtomicBool);
 
 unsafe impl Send for InnerAlloc {}
 
@@ -60,34 +66,50 @@ pub struct GlobalAllocator(InnerAlloc);
 
 impl GlobalAllocator {
     const fn new() -> Self {
-        GlobalAllocator(InnerAlloc(UnsafeCell::new((0, 0))))
+        GlobalAllocator(InnerAlloc(UnsafeCell::new((0, 0)), AtomicBool::new(false)))
     }
-}
 
-unsafe impl GlobalAlloc for GlobalAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// Carves `layout` out of the bump region, mapping a new region when the
+    /// current one is full. The caller must hold the lock.
+    unsafe fn bump(&self, layout: Layout) -> *mut u8 {
         unsafe {
             let (beg, end) = &mut *self.0.0.get();
-            if *beg == 0 {
-                let (alloc_beg, alloc_len) = sbrk().expect("unable to alloc");
+            let mut location = align_up(*beg, layout.align());
+            if *beg == 0 || location + layout.size() > *end {
+                let length = align_up(layout.size() + layout.align(), ALLOCATION_CHUNK);
+                let alloc_beg = match mmap(None, length, MEMORY_READ | MEMORY_WRITE) {
+                    Ok(alloc_beg) => alloc_beg,
+                    Err(_) => return core::ptr::null_mut(),
+                };
                 *beg = alloc_beg;
-                *end = alloc_beg + alloc_len;
+                *end = alloc_beg + length;
+                location = align_up(*beg, layout.align());
             }
 
-            if *beg & (layout.align() - 1) != 0 {
-                *beg = *beg & (!(layout.align() - 1)) + layout.align();
-            }
+            *beg = location + layout.size();
 
-            let location = *beg as *mut u8;
-            *beg += layout.size();
+            location as *mut u8
+        }
+    }
+}
 
-            location
+unsafe impl GlobalAlloc for GlobalAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        while self.0.1.swap(true, Ordering::Acquire) {
+            core::hint::spin_loop();
         }
+        let location = unsafe { self.bump(layout) };
+        self.0.1.store(false, Ordering::Release);
+        location
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
 }
 
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
 #[global_allocator]
 pub static ALLOCATOR: GlobalAllocator = GlobalAllocator::new();
 